@@ -0,0 +1,186 @@
+use super::Error;
+
+const PT_LOAD: u64 = 1;
+
+// A loadable segment; data goes at addr and the rest of mem_size is zeroed
+pub struct Segment<'a> {
+	pub addr: u64,
+	pub data: &'a [u8],
+	pub mem_size: u64,
+}
+
+pub struct Image<'a> {
+	pub entry: u64,
+	pub segments: Vec<Segment<'a>>,
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+	fn slice(&self, offset: u64, len: u64) -> Result<&'a [u8], Error> {
+		match offset.checked_add(len) {
+			Some(end) if end <= (self.bytes.len() as u64) => Ok(&self.bytes[(offset as usize)..(end as usize)]),
+			_ => Err(Error::MalformedImage(format!("ELF truncated reading {:#x} bytes @ {:#x}", len, offset))),
+		}
+	}
+
+	fn uint(&self, offset: u64, width: u64) -> Result<u64, Error> {
+		let field = try!(self.slice(offset, width));
+
+		Ok(if self.big_endian {
+			field.iter().fold(0, |value, byte| (value << 8) | (*byte as u64))
+		} else {
+			field.iter().rev().fold(0, |value, byte| (value << 8) | (*byte as u64))
+		})
+	}
+}
+
+// Segments are placed at their physical addresses; the entry point is returned
+// as is.
+pub fn parse(bytes: &[u8]) -> Result<Image, Error> {
+	if bytes.len() < 16 || &bytes[0..4] != b"\x7fELF" {
+		return Err(Error::MalformedImage(format!("Missing ELF magic")));
+	}
+
+	let is_64 = match bytes[4] {
+		1     => false,
+		2     => true,
+		class => return Err(Error::MalformedImage(format!("Unknown ELF class {}", class))),
+	};
+
+	let big_endian = match bytes[5] {
+		1        => false,
+		2        => true,
+		encoding => return Err(Error::MalformedImage(format!("Unknown ELF data encoding {}", encoding))),
+	};
+
+	let reader = Reader {
+		bytes:      bytes,
+		big_endian: big_endian,
+	};
+
+	let (entry, phoff, phentsize, phnum) = if is_64 {
+		(try!(reader.uint(0x18, 8)), try!(reader.uint(0x20, 8)), try!(reader.uint(0x36, 2)), try!(reader.uint(0x38, 2)))
+	} else {
+		(try!(reader.uint(0x18, 4)), try!(reader.uint(0x1C, 4)), try!(reader.uint(0x2A, 2)), try!(reader.uint(0x2C, 2)))
+	};
+
+	// Every field read below has to land within the entry
+	let min_phentsize = if is_64 { 0x38 } else { 0x20 };
+
+	if phnum != 0 && phentsize < min_phentsize {
+		return Err(Error::MalformedImage(format!("ELF program header entries too small ({:#x} bytes)", phentsize)));
+	}
+
+	let mut segments = Vec::new();
+
+	for i in 0..phnum {
+		let ph = match i.checked_mul(phentsize).and_then(|rel| phoff.checked_add(rel)) {
+			Some(ph) => ph,
+			None     => return Err(Error::MalformedImage(format!("ELF program header {} out of range", i))),
+		};
+
+		try!(reader.slice(ph, phentsize));
+
+		if try!(reader.uint(ph, 4)) != PT_LOAD {
+			continue;
+		}
+
+		let (offset, paddr, filesz, memsz) = if is_64 {
+			(try!(reader.uint(ph + 0x08, 8)), try!(reader.uint(ph + 0x18, 8)), try!(reader.uint(ph + 0x20, 8)), try!(reader.uint(ph + 0x28, 8)))
+		} else {
+			(try!(reader.uint(ph + 0x04, 4)), try!(reader.uint(ph + 0x0C, 4)), try!(reader.uint(ph + 0x10, 4)), try!(reader.uint(ph + 0x14, 4)))
+		};
+
+		if filesz > memsz {
+			return Err(Error::MalformedImage(format!("ELF segment {} has more file than memory ({:#x} > {:#x})", i, filesz, memsz)));
+		}
+
+		segments.push(Segment {
+			addr:     paddr,
+			data:     try!(reader.slice(offset, filesz)),
+			mem_size: memsz,
+		});
+	}
+
+	Ok(Image {
+		entry:    entry,
+		segments: segments,
+	})
+}
+
+#[cfg(test)]
+pub mod tests {
+	use super::*;
+
+	use super::super::Error;
+
+	// A big endian ELF32 with one PT_LOAD segment of 4 file bytes and 8 in memory
+	pub fn elf32_be() -> Vec<u8> {
+		let mut bytes = vec!(0u8; 0x58);
+
+		bytes[0..6].copy_from_slice(b"\x7fELF\x01\x02");
+		bytes[0x18..0x1C].copy_from_slice(&[0x80, 0x00, 0x10, 0x00]); // e_entry
+		bytes[0x1C..0x20].copy_from_slice(&[0x00, 0x00, 0x00, 0x34]); // e_phoff
+		bytes[0x2A..0x2C].copy_from_slice(&[0x00, 0x20]);             // e_phentsize
+		bytes[0x2C..0x2E].copy_from_slice(&[0x00, 0x01]);             // e_phnum
+
+		bytes[0x34..0x38].copy_from_slice(&[0x00, 0x00, 0x00, 0x01]); // p_type
+		bytes[0x38..0x3C].copy_from_slice(&[0x00, 0x00, 0x00, 0x54]); // p_offset
+		bytes[0x40..0x44].copy_from_slice(&[0x00, 0x00, 0x10, 0x00]); // p_paddr
+		bytes[0x44..0x48].copy_from_slice(&[0x00, 0x00, 0x00, 0x04]); // p_filesz
+		bytes[0x48..0x4C].copy_from_slice(&[0x00, 0x00, 0x00, 0x08]); // p_memsz
+
+		bytes[0x54..0x58].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+		bytes
+	}
+
+	#[test]
+	fn parses_elf32_load_segments() {
+		let bytes = elf32_be();
+		let image = parse(&bytes).unwrap();
+
+		assert_eq!(0x80001000, image.entry);
+		assert_eq!(1, image.segments.len());
+		assert_eq!(0x1000, image.segments[0].addr);
+		assert_eq!(&[0xDE, 0xAD, 0xBE, 0xEF], image.segments[0].data);
+		assert_eq!(8, image.segments[0].mem_size);
+	}
+
+	#[test]
+	fn program_headers_past_the_end_rejected() {
+		let mut bytes = elf32_be();
+		bytes[0x2C..0x2E].copy_from_slice(&[0x00, 0x02]); // e_phnum
+
+		match parse(&bytes) {
+			Err(Error::MalformedImage(_)) => {},
+			other => panic!("Unexpected result:  {:?}", other.map(|image| image.entry)),
+		}
+	}
+
+	#[test]
+	fn short_program_headers_rejected() {
+		let mut bytes = elf32_be();
+		bytes[0x2A..0x2C].copy_from_slice(&[0x00, 0x10]); // e_phentsize
+
+		match parse(&bytes) {
+			Err(Error::MalformedImage(_)) => {},
+			other => panic!("Unexpected result:  {:?}", other.map(|image| image.entry)),
+		}
+	}
+
+	#[test]
+	fn truncated_segment_rejected() {
+		let mut bytes = elf32_be();
+		bytes.truncate(0x56);
+
+		match parse(&bytes) {
+			Err(Error::MalformedImage(_)) => {},
+			other => panic!("Unexpected result:  {:?}", other.map(|image| image.entry)),
+		}
+	}
+}
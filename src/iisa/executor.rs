@@ -3,36 +3,99 @@ use super::super::*;
 use super::RegisterFile;
 use super::Translator;
 
-use std::mem::transmute;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::*;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
-#[allow(dead_code)]
 struct BlockHook {
-	hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>,
+	hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>,
 }
 
 unsafe impl Send for BlockHook { }
 
-#[allow(dead_code)]
 struct CodeHook {
 	base: u64,
-	hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>,
+	hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>,
 }
 
 unsafe impl Send for CodeHook { }
 
+struct DecodeHook {
+	hook: Arc<Mutex<Fn(u64, &[iisa::Instr]) + Send>>,
+}
+
+struct DecodeRewriter {
+	hook: Arc<Mutex<Fn(u64, &mut Vec<iisa::Instr>) + Send>>,
+}
+
+struct OpClassHook {
+	class: iisa::OpClass,
+	hook: Arc<Mutex<Fn(u64, &iisa::Op) -> TraceExitHint + Send>>,
+}
+
+struct RegWatch {
+	reg: CpuReg,
+	hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint + Send>>,
+}
+
+struct DiagnosticHook {
+	hook: Arc<Mutex<Fn(&str) + Send>>,
+}
+
 enum Message {
 	Shutdown(Promise<()>),
+	Reset(Promise<()>),
 	FsbUpdateOp(mem::BusMatrixUpdateOp, Promise<()>),
+	SyncMemoryMap(Promise<()>),
 	SetReg(CpuReg, u64, Promise<()>),
 	GetReg(CpuReg, Promise<u64>),
+	AddressBits(Promise<u8>),
+	GetRegAtNextBoundary(CpuReg, Promise<(u64, u64)>),
+	StepOneWithDiff(Promise<StepDiff>),
+	RegisterSnapshot(Promise<RegisterSnapshot>),
+	RestoreRegisterSnapshot(RegisterSnapshot, Promise<()>),
+	SwapContext(Box<RegisterFile>, Promise<RegisterFile>),
+	DisassembleAt(u64, usize, Promise<Vec<(u64, iisa::Instr)>>),
+	LoadIisa(Vec<iisa::Instr>, u64, Promise<()>),
 	AddBlockHookAll(BlockHook, Promise<()>),
 	AddCodeHookSingle(CodeHook, Promise<()>),
+	AddDecodeHook(DecodeHook, Promise<()>),
+	AddDecodeRewriter(DecodeRewriter, Promise<()>),
+	AddOpClassHook(OpClassHook, Promise<()>),
+	AddRegWatch(RegWatch, Promise<()>),
+	SetDiagnosticHook(DiagnosticHook, Promise<()>),
+	SubscribeEvents(Sender<CpuEvent>, Promise<()>),
+	IsRunning(Promise<bool>),
+	OnStop(Promise<ExitReason>, Promise<()>),
+	CurrentInsnBytes(Promise<Vec<u8>>),
+	BranchEdges(Promise<Vec<BranchEdge>>),
+	CycleCount(Promise<u64>),
+	EnableTraceFile(PathBuf, TraceFormat, Promise<()>),
+	SetDecodeErrorPolicy(DecodeErrorPolicy, Promise<()>),
+	SetWatchdog(Option<Arc<Mutex<mem::Watchdog>>>, Promise<()>),
+	SetStoreBufferEntries(usize, Promise<()>),
+	PendingStores(Promise<Vec<iisa::PendingStore>>),
+	StartRecording(Promise<()>),
+	StopRecording(Promise<Vec<ReplayEvent>>),
+	StartReplay(Vec<ReplayEvent>, Promise<()>),
 	Execute(Promise<ExitReason>),
+	Run(RunConfig, Promise<RunOutcome>),
+	RunUntilWrite(u64, u64, Promise<ExitReason>),
 }
 
+// Every call is a blocking round trip to the backend thread, and mpsc
+// delivers messages from a given sender in the order they were sent, so the
+// backend applies a FrontEnd's requests in exactly the order they were made.
+// Clones of a FrontEnd each get their own sender; requests from different
+// clones are ordered by whichever reached the queue first, but each caller
+// always observes the effects of its own earlier requests.
+#[derive(Clone)]
 struct FrontEnd {
 	tx: Sender<Message>,
 }
@@ -43,123 +106,348 @@ impl FrontEnd {
 			tx: tx,
 		}
 	}
+
+	fn round_trip<R: Clone, F: FnOnce(Promise<R>) -> Message>(&self, make_msg: F) -> Result<R, Error> {
+		let mut promise = Promise::<R>::new();
+		let future = promise.get_future();
+
+		let _ = self.tx.send(make_msg(promise));
+
+		future.wait()
+	}
 }
 
 impl Cpu for FrontEnd {
 	fn execute(&mut self) -> Result<ExitReason, Error> {
-		let mut promise = Promise::new();
-		let future = promise.get_future();
+		self.round_trip(Message::Execute)
+	}
 
-		let _ = self.tx.send(Message::Execute(promise));
+	fn run(&mut self, config: RunConfig) -> Result<RunOutcome, Error> {
+		self.round_trip(|promise| Message::Run(config, promise))
+	}
 
-		future.wait()
+	fn run_until_write(&mut self, addr: u64, len: u64) -> Result<ExitReason, Error> {
+		self.round_trip(|promise| Message::RunUntilWrite(addr, len, promise))
 	}
 
-	fn get_reg(&self, reg: CpuReg) -> Result<u64, Error> {
-		let mut promise = Promise::<u64>::new();
-		let future = promise.get_future();
+	fn step_one_with_diff(&mut self) -> Result<StepDiff, Error> {
+		self.round_trip(Message::StepOneWithDiff)
+	}
 
-		let _ = self.tx.send(Message::GetReg(reg, promise));
+	fn register_snapshot(&self) -> Result<RegisterSnapshot, Error> {
+		self.round_trip(Message::RegisterSnapshot)
+	}
 
-		future.wait()
+	fn restore_register_snapshot(&mut self, snapshot: &RegisterSnapshot) -> Result<(), Error> {
+		self.round_trip(|promise| Message::RestoreRegisterSnapshot(snapshot.clone(), promise))
 	}
 
-	fn set_reg(&mut self, reg: CpuReg, value: u64) -> Result<(), Error> {
-		let mut promise = Promise::<()>::new();
-		let future = promise.get_future();
+	fn swap_context(&mut self, new: RegisterFile) -> Result<RegisterFile, Error> {
+		self.round_trip(|promise| Message::SwapContext(Box::new(new), promise))
+	}
 
-		let _ = self.tx.send(Message::SetReg(reg, value, promise));
+	fn disassemble_at(&self, addr: u64, max_insns: usize) -> Result<Vec<(u64, iisa::Instr)>, Error> {
+		self.round_trip(|promise| Message::DisassembleAt(addr, max_insns, promise))
+	}
 
-		future.wait()
+	fn load_iisa(&mut self, program: Vec<iisa::Instr>, base: u64) -> Result<(), Error> {
+		self.round_trip(|promise| Message::LoadIisa(program, base, promise))
 	}
 
-	fn add_block_hook_all(&mut self, hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
-		let mut promise = Promise::<()>::new();
-		let future = promise.get_future();
+	fn current_insn_bytes(&self) -> Result<Vec<u8>, Error> {
+		self.round_trip(Message::CurrentInsnBytes)
+	}
 
-		let _ = self.tx.send(Message::AddBlockHookAll(BlockHook{hook: hook}, promise));
+	fn branch_edges(&self) -> Result<Vec<BranchEdge>, Error> {
+		self.round_trip(Message::BranchEdges)
+	}
 
-		future.wait()
+	fn cycle_count(&self) -> Result<u64, Error> {
+		self.round_trip(Message::CycleCount)
 	}
 
-	fn add_code_hook_single(&mut self, base: u64, hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
-		let mut promise = Promise::<()>::new();
-		let future = promise.get_future();
+	fn enable_trace_file(&mut self, path: &Path, format: TraceFormat) -> Result<(), Error> {
+		self.round_trip(|promise| Message::EnableTraceFile(path.to_path_buf(), format, promise))
+	}
+
+	fn set_decode_error_policy(&mut self, policy: DecodeErrorPolicy) -> Result<(), Error> {
+		self.round_trip(|promise| Message::SetDecodeErrorPolicy(policy, promise))
+	}
+
+	fn set_watchdog(&mut self, watchdog: Option<Arc<Mutex<mem::Watchdog>>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::SetWatchdog(watchdog, promise))
+	}
+
+	fn set_store_buffer_entries(&mut self, entries: usize) -> Result<(), Error> {
+		self.round_trip(|promise| Message::SetStoreBufferEntries(entries, promise))
+	}
+
+	fn pending_stores(&self) -> Result<Vec<iisa::PendingStore>, Error> {
+		self.round_trip(Message::PendingStores)
+	}
+
+	fn start_recording(&mut self) -> Result<(), Error> {
+		self.round_trip(Message::StartRecording)
+	}
+
+	fn stop_recording(&mut self) -> Result<Vec<ReplayEvent>, Error> {
+		self.round_trip(Message::StopRecording)
+	}
+
+	fn start_replay(&mut self, trace: Vec<ReplayEvent>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::StartReplay(trace, promise))
+	}
+
+	fn get_reg(&self, reg: CpuReg) -> Result<u64, Error> {
+		self.round_trip(|promise| Message::GetReg(reg, promise))
+	}
+
+	fn address_bits(&self) -> Result<u8, Error> {
+		self.round_trip(Message::AddressBits)
+	}
+
+	fn get_reg_at_boundary(&self, reg: CpuReg) -> Result<(u64, u64), Error> {
+		self.round_trip(|promise| Message::GetRegAtNextBoundary(reg, promise))
+	}
+
+	fn set_reg(&mut self, reg: CpuReg, value: u64) -> Result<(), Error> {
+		self.round_trip(|promise| Message::SetReg(reg, value, promise))
+	}
 
-		let _ = self.tx.send(Message::AddCodeHookSingle(CodeHook{
+	fn add_block_hook_all(&mut self, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::AddBlockHookAll(BlockHook{hook: hook}, promise))
+	}
+
+	fn add_code_hook_single(&mut self, base: u64, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::AddCodeHookSingle(CodeHook{
 			base: base,
 			hook: hook
-		}, promise));
+		}, promise))
+	}
 
-		future.wait()
+	fn add_decode_hook(&mut self, hook: Arc<Mutex<Fn(u64, &[iisa::Instr]) + Send>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::AddDecodeHook(DecodeHook{hook: hook}, promise))
 	}
 
-	fn shutdown(&mut self) {
-		let mut promise = Promise::<()>::new();
-		let future = promise.get_future();
+	fn add_decode_rewriter(&mut self, hook: Arc<Mutex<Fn(u64, &mut Vec<iisa::Instr>) + Send>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::AddDecodeRewriter(DecodeRewriter{hook: hook}, promise))
+	}
 
-		let _ = self.tx.send(Message::Shutdown(promise));
+	fn add_op_class_hook(&mut self, class: iisa::OpClass, hook: Arc<Mutex<Fn(u64, &iisa::Op) -> TraceExitHint + Send>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::AddOpClassHook(OpClassHook{
+			class: class,
+			hook:  hook,
+		}, promise))
+	}
 
-		let _ = future.wait();
+	fn add_reg_watch(&mut self, reg: CpuReg, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint + Send>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::AddRegWatch(RegWatch{
+			reg:  reg,
+			hook: hook,
+		}, promise))
+	}
+
+	fn set_diagnostic_hook(&mut self, hook: Arc<Mutex<Fn(&str) + Send>>) -> Result<(), Error> {
+		self.round_trip(|promise| Message::SetDiagnosticHook(DiagnosticHook{hook: hook}, promise))
+	}
+
+	fn subscribe_events(&mut self) -> Result<Receiver<CpuEvent>, Error> {
+		let (tx, rx) = channel();
+
+		try!(self.round_trip(|promise| Message::SubscribeEvents(tx, promise)));
+
+		Ok(rx)
+	}
+
+	fn on_stop(&mut self) -> Result<Future<ExitReason>, Error> {
+		let mut waiter = Promise::new();
+		let future = waiter.get_future();
+
+		try!(self.round_trip(|promise| Message::OnStop(waiter, promise)));
+
+		Ok(future)
+	}
+
+	fn is_running(&self) -> Result<bool, Error> {
+		self.round_trip(Message::IsRunning)
+	}
+
+	fn sync_memory_map(&mut self) -> Result<(), Error> {
+		self.round_trip(Message::SyncMemoryMap)
+	}
+
+	fn reset(&mut self) -> Result<(), Error> {
+		self.round_trip(Message::Reset)
+	}
+
+	fn shutdown(&mut self) {
+		let _ = self.round_trip(Message::Shutdown);
 	}
 }
 
 const PAGE_SIZE: usize = 4096;
 
-struct Page<'a> {
+// How much is fetched to decode from up front.  That covers a branch and its
+// delay slot; anything longer asks for the rest with NeedMoreBytes.
+const FETCH_LEN: usize = 8;
+
+// A copy of up to FETCH_LEN mapped bytes from base, never reaching past the
+// end of base's page or the range backing it
+struct Page {
 	base: u64,
-	data: &'a [u8;PAGE_SIZE],
+	data: Vec<u8>,
 }
 
-impl<'a> Page<'a> {
-	fn new(base: u64, data: &'a [u8;PAGE_SIZE]) -> Page {
+impl Page {
+	fn new(base: u64, data: Vec<u8>) -> Page {
 		Page {
 			base: base,
 			data: data,
 		}
 	}
 
-	fn single_step(&self, reg: &mut RegisterFile, translator: &Translator) -> Result<(), Error> {
-		let offset = (reg.pc - self.base) as usize;
+	fn offset_of(&self, addr: u64) -> Result<usize, Error> {
+		match addr.checked_sub(self.base) {
+			Some(offset) if offset < (self.data.len() as u64) => Ok(offset as usize),
+			_                                                  => Err(Error::InvalidPC),
+		}
+	}
+
+	fn is_zero_at(&self, addr: u64, len: usize) -> bool {
+		match self.bytes_at(addr, len) {
+			Ok(bytes) => bytes.iter().all(|byte| *byte == 0),
+			Err(_)    => false,
+		}
+	}
+
+	fn bytes_at(&self, addr: u64, len: usize) -> Result<&[u8], Error> {
+		let offset = try!(self.offset_of(addr));
 
-		if offset > (PAGE_SIZE - 1) {
+		if len > (self.data.len() - offset) {
 			return Err(Error::InvalidPC);
 		}
 
-		let instrs = try!(translator.decode(reg.pc, &self.data[offset..]));
+		Ok(&self.data[offset..offset + len])
+	}
 
-		try!(iisa::interpret_op_list(&instrs, reg));
+	fn instruction_size_at(&self, addr: u64, translator: &Translator) -> Result<u8, Error> {
+		let offset = try!(self.offset_of(addr));
 
-		Ok(())
+		translator.instruction_size_at(addr, &self.data[offset..])
+	}
+
+	fn bytes_from(&self, addr: u64) -> Result<&[u8], Error> {
+		let offset = try!(self.offset_of(addr));
+
+		Ok(&self.data[offset..])
+	}
+
+	fn decode(&self, addr: u64, translator: &Translator) -> Result<iisa::DecodeOutcome, Error> {
+		translator.decode(addr, try!(self.bytes_from(addr)))
 	}
 }
 
+enum StepOutcome {
+	Executed(Vec<iisa::Instr>),
+	Stopped(ExitReason),
+}
+
+// The guest instructions instrs retire.  Only the last instr lowered from an
+// instruction has a size, and a fused branch carries its delay slot as well.
+fn insns_in(instrs: &[iisa::Instr]) -> u64 {
+	instrs.iter().filter(|instr| instr.size != 0).map(|instr| match instr.op {
+		iisa::Op::Branch { .. } => 2,
+		_                       => 1,
+	}).sum()
+}
+
 #[derive(Clone)]
 enum ExecutionState {
 	Paused,
 	Executing(Promise<ExitReason>),
+	Running(Promise<RunOutcome>),
+}
+
+// A replayed trace is consumed as the instruction counts it holds go by
+enum Replay {
+	Off,
+	Recording(Vec<ReplayEvent>),
+	Replaying(VecDeque<ReplayEvent>),
+}
+
+struct RunLimits {
+	start_pc: u64,
+	start_insn_count: u64,
+	max_insns: Option<u64>,
+	deadline: Option<Instant>,
+	stop_at: BTreeSet<u64>,
 }
 
 struct Backend<T: Send> {
 	rx: Receiver<Message>,
 	translator: T,
+	opts: CpuOpt,
 	fsb: mem::BusMatrix,
 	registers: RegisterFile,
 	hooks_on_all: Vec<BlockHook>,
 	code_hooks_on_single: Vec<CodeHook>,
+	decode_hooks: Vec<DecodeHook>,
+	decode_rewriters: Vec<DecodeRewriter>,
+	op_class_hooks: Vec<OpClassHook>,
+	reg_watches: Vec<RegWatch>,
+	diagnostic_hook: Option<DiagnosticHook>,
+	decoded_blocks: BTreeSet<u64>,
+	event_subscribers: Vec<Sender<CpuEvent>>,
+	stop_waiters: Promise<ExitReason>,
+	branch_edges: BTreeSet<BranchEdge>,
+	iisa_programs: BTreeMap<u64, Vec<iisa::Instr>>,
+	trace_file: Option<(BufWriter<File>, TraceFormat)>,
+	decode_error_policy: DecodeErrorPolicy,
+	// The device, and the cycle count it expires at
+	watchdog: Option<(Arc<Mutex<mem::Watchdog>>, u64)>,
+	store_buffer: iisa::StoreBuffer,
+	replay: Replay,
 	execution_state: ExecutionState,
+	run_limits: Option<RunLimits>,
+	write_watch: Option<(u64, u64)>,
+	at_block_start: bool,
+	insn_count: u64,
+	cycle_count: u64,
 }
 
 impl<T: Send+Clone+Translator> Backend<T> {
-	fn new(rx: Receiver<Message>, translator: T) -> Backend<T> {
+	fn new(rx: Receiver<Message>, translator: T, opts: CpuOpt) -> Backend<T> {
+		let register_slots = translator.register_slots();
+
 		Backend {
 			rx:                   rx,
 			translator:           translator,
+			opts:                 opts,
 			fsb:                  Default::default(),
-			registers:            RegisterFile::new(),
+			registers:            RegisterFile::with_slots(register_slots),
 			hooks_on_all:         Vec::new(),
 			code_hooks_on_single: Vec::new(),
+			decode_hooks:         Vec::new(),
+			decode_rewriters:     Vec::new(),
+			op_class_hooks:       Vec::new(),
+			reg_watches:          Vec::new(),
+			diagnostic_hook:      None,
+			decoded_blocks:       BTreeSet::new(),
+			event_subscribers:    Vec::new(),
+			stop_waiters:         Promise::new(),
+			branch_edges:         BTreeSet::new(),
+			iisa_programs:        BTreeMap::new(),
+			trace_file:           None,
+			decode_error_policy:  DecodeErrorPolicy::Fail,
+			watchdog:             None,
+			store_buffer:         Default::default(),
+			replay:               Replay::Off,
 			execution_state:      ExecutionState::Paused,
+			run_limits:           None,
+			write_watch:          None,
+			at_block_start:       true,
+			insn_count:           0,
+			cycle_count:          0,
 		}
 	}
 
@@ -171,18 +459,91 @@ impl<T: Send+Clone+Translator> Backend<T> {
 				return false;
 			},
 
+			Message::Reset(mut promise) => {
+				self.reset();
+
+				promise.signal(Ok(()));
+			},
+
 			Message::FsbUpdateOp(update_op, mut promise) => {
+				if let mem::BusMatrixUpdateOp::Initialized(_, _) = update_op {
+					self.decoded_blocks.clear();
+				}
+
 				self.fsb.apply_update_op(update_op);
 
 				promise.signal(Ok(()));
 			},
 
+			// Map updates share this queue, so every one sent before this
+			// message has already been applied to fsb
+			Message::SyncMemoryMap(mut promise) => {
+				promise.signal(Ok(()));
+			},
+
 			Message::GetReg(reg, mut promise) => {
-				promise.signal(self.translator.get_reg(&self.registers, reg))
+				promise.signal(self.get_reg(reg))
+			},
+
+			Message::AddressBits(mut promise) => {
+				promise.signal(Ok(self.translator.address_bits()))
+			},
+
+			// Messages are only ever processed between steps, so the register
+			// file is at an instruction boundary and the pc is the one the next
+			// step will execute from.
+			Message::GetRegAtNextBoundary(reg, mut promise) => {
+				let pc = self.registers.pc;
+
+				promise.signal(self.get_reg(reg).map(|value| (value, pc)))
+			},
+
+			Message::StepOneWithDiff(mut promise) => {
+				promise.signal(self.step_one_with_diff())
+			},
+
+			Message::RegisterSnapshot(mut promise) => {
+				promise.signal(self.register_snapshot())
+			},
+
+			Message::RestoreRegisterSnapshot(snapshot, mut promise) => {
+				self.at_block_start = true;
+
+				promise.signal(self.restore_register_snapshot(&snapshot))
+			},
+
+			// A file smaller than the arch's would fault (or worse) on the first
+			// register past its end, so it's turned away up front
+			Message::SwapContext(new, mut promise) => {
+				let needed = self.translator.register_slots();
+
+				if new.slots() < needed {
+					promise.signal(Err(Error::RegisterFileTooSmall(new.slots(), needed)))
+				} else {
+					self.at_block_start = true;
+
+					promise.signal(Ok(::std::mem::replace(&mut self.registers, *new)))
+				}
+			},
+
+			Message::DisassembleAt(addr, max_insns, mut promise) => {
+				promise.signal(self.disassemble_at(addr, max_insns))
+			},
+
+			Message::LoadIisa(program, base, mut promise) => {
+				let _ = self.iisa_programs.insert(base, program);
+
+				self.decoded_blocks.clear();
+
+				promise.signal(Ok(()));
 			},
 
 			Message::SetReg(reg, value, mut promise) => {
-				promise.signal(self.translator.set_reg(&mut self.registers, reg, value))
+				if reg == CpuReg::Pc {
+					self.at_block_start = true;
+				}
+
+				promise.signal(self.set_reg(reg, value))
 			},
 
 			Message::AddBlockHookAll(hook, mut promise) => {
@@ -197,94 +558,1373 @@ impl<T: Send+Clone+Translator> Backend<T> {
 				promise.signal(Ok(()));
 			},
 
-			Message::Execute(promise) => {
-				self.execution_state = ExecutionState::Executing(promise);
+			Message::AddDecodeHook(hook, mut promise) => {
+				self.decode_hooks.push(hook);
+
+				promise.signal(Ok(()));
 			},
-		}
 
-		true
-	}
+			// Blocks the decode hooks have already seen may now decode differently
+			Message::AddDecodeRewriter(rewriter, mut promise) => {
+				self.decode_rewriters.push(rewriter);
+				self.decoded_blocks.clear();
 
-	fn single_step(&mut self) -> Result<(), Error> {
-		let page_virt_base = self.registers.pc & !((PAGE_SIZE as u64) - 1);
-		let page_phys_base = match self.translator.virtual_to_phys(&mut self.registers, page_virt_base) {
-			Some(virt) => virt,
-			None => return Err(Error::VirtualAddrNotMappable(page_virt_base)),
-		};
-		let page_mem = match self.fsb.find_range(page_phys_base, PAGE_SIZE) {
-			Ok(raw_ptr) => {
-				unsafe { transmute(raw_ptr) }
+				promise.signal(Ok(()));
 			},
-			Err(err) => {
-				return Err(err);
+
+			Message::AddRegWatch(watch, mut promise) => {
+				self.reg_watches.push(watch);
+
+				promise.signal(Ok(()));
 			},
-		};
-		Page::new(page_virt_base, page_mem).single_step(&mut self.registers, &self.translator)
-	}
 
-	fn execute(&mut self) {
-		let mut running = true;
+			Message::AddOpClassHook(hook, mut promise) => {
+				self.op_class_hooks.push(hook);
 
-		while running {
-			let cur_state = self.execution_state.clone();
-			running = match cur_state {
-				ExecutionState::Paused => {
-					let msg = match self.rx.recv() {
-						Ok(msg) => msg,
-						Err(err) => {
-							println!("Exiting cpu thread because {:?}", err);
-							return;
-						},
-					};
+				promise.signal(Ok(()));
+			},
 
-					self.process_message(msg)
-				},
+			Message::SetDiagnosticHook(hook, mut promise) => {
+				self.diagnostic_hook = Some(hook);
 
-				ExecutionState::Executing(mut promise) => {
-					promise.signal(match self.single_step() {
-						Ok(()) => Ok(ExitReason::CodeHookSignalledStop),
-						Err(err) => Err(err),
-					});
+				promise.signal(Ok(()));
+			},
 
-					self.execution_state = ExecutionState::Paused;
+			Message::SubscribeEvents(subscriber, mut promise) => {
+				self.event_subscribers.push(subscriber);
 
-					let msg = match self.rx.try_recv() {
-						Ok(msg) => msg,
-						Err(TryRecvError::Empty) => {
-							continue;
-						},
-						Err(TryRecvError::Disconnected) => {
-							return;
-						},
-					};
+				promise.signal(Ok(()));
+			},
 
-					self.process_message(msg)
-				},
-			}
-		}
-	}
-}
+			Message::OnStop(waiter, mut promise) => {
+				self.stop_waiters.join(waiter);
 
-pub fn executor<T: 'static+Send+Clone+Translator>(translator: T, fsb: &mut mem::BusMatrix) -> Result<Box<Cpu>, Error> {
-	let (tx, rx) = channel::<Message>();
+				promise.signal(Ok(()));
+			},
 
-	let mem_update_channel = tx.clone();
+			Message::CurrentInsnBytes(mut promise) => {
+				let pc = self.registers.pc;
 
-	thread::spawn(move || {
-		let mut backend = Backend::new(rx, translator);
+				promise.signal(self.insn_bytes_at(pc))
+			},
 
-		backend.execute();
-	});
+			Message::BranchEdges(mut promise) => {
+				promise.signal(Ok(self.branch_edges.iter().cloned().collect()))
+			},
 
-	fsb.add_child_matrix(Box::new(move |update_op| {
-		let mut promise = Promise::<()>::new();
-		let future = promise.get_future();
+			Message::CycleCount(mut promise) => {
+				promise.signal(Ok(self.cycle_count))
+			},
 
-		let _ = mem_update_channel.send(Message::FsbUpdateOp(update_op, promise));
+			Message::EnableTraceFile(path, format, mut promise) => {
+				promise.signal(self.enable_trace_file(&path, format))
+			},
 
-		let _ = future.wait();
-	}));
+			Message::SetDecodeErrorPolicy(policy, mut promise) => {
+				self.decode_error_policy = policy;
 
-	Ok(Box::new(FrontEnd::new(tx)))
-}
+				promise.signal(Ok(()))
+			},
+
+			Message::SetWatchdog(watchdog, mut promise) => {
+				self.watchdog = watchdog.map(|watchdog| (watchdog, 0));
+				self.rearm_watchdog();
+
+				promise.signal(Ok(()))
+			},
+
+			// Whatever's already buffered drains first, so resizing never
+			// drops or reorders a store.  If the drain faults, the old buffer
+			// stays in place with the unwritten stores still pending
+			Message::SetStoreBufferEntries(entries, mut promise) => {
+				let result = self.store_buffer.drain(&mut self.fsb, self.translator.big_endian());
+
+				if result.is_ok() {
+					self.store_buffer = iisa::StoreBuffer::new(entries);
+				}
+
+				promise.signal(result)
+			},
+
+			Message::PendingStores(mut promise) => {
+				promise.signal(Ok(self.store_buffer.pending()))
+			},
+
+			Message::StartRecording(mut promise) => {
+				self.replay = Replay::Recording(Vec::new());
+
+				promise.signal(Ok(()));
+			},
+
+			Message::StopRecording(mut promise) => {
+				let trace = match ::std::mem::replace(&mut self.replay, Replay::Off) {
+					Replay::Recording(trace) => trace,
+					_                        => Vec::new(),
+				};
+
+				promise.signal(Ok(trace));
+			},
+
+			Message::StartReplay(trace, mut promise) => {
+				self.replay = Replay::Replaying(trace.into_iter().collect());
+
+				promise.signal(Ok(()));
+			},
+
+			Message::IsRunning(mut promise) => {
+				promise.signal(Ok(match self.execution_state {
+					ExecutionState::Paused       => false,
+					ExecutionState::Executing(_) => true,
+					ExecutionState::Running(_)   => true,
+				}));
+			},
+
+			Message::Execute(promise) => {
+				self.start_execution(ExecutionState::Executing(promise), None);
+			},
+
+			Message::Run(config, promise) => {
+				let limits = RunLimits {
+					start_pc:         self.registers.pc,
+					start_insn_count: self.insn_count,
+					max_insns:        config.max_insns,
+					deadline:         config.timeout.map(|timeout| Instant::now() + timeout),
+					stop_at:          config.stop_at.into_iter().collect(),
+				};
+
+				self.start_execution(ExecutionState::Running(promise), Some(limits));
+			},
+
+			// The watch is on the physical address the range maps to now
+			Message::RunUntilWrite(addr, len, mut promise) => {
+				match self.translator.virtual_to_phys(&self.registers, addr) {
+					Some(phys) => {
+						self.fsb.set_write_watch(Some((phys, len)));
+						self.write_watch = Some((addr, phys));
+
+						self.start_execution(ExecutionState::Executing(promise), None);
+					},
+
+					None => promise.signal(Err(Error::DataTranslationFault(addr))),
+				}
+			},
+		}
+
+		true
+	}
+
+	fn start_execution(&mut self, state: ExecutionState, limits: Option<RunLimits>) {
+		self.at_block_start = true;
+		self.execution_state = state;
+		self.run_limits = limits;
+
+		self.emit_event(CpuEvent::Started);
+	}
+
+	fn finish_execution(&mut self, state: ExecutionState, result: Result<ExitReason, Error>) {
+		self.run_limits = None;
 
+		if self.write_watch.take().is_some() {
+			self.fsb.set_write_watch(None);
+		}
+		self.execution_state = ExecutionState::Paused;
+
+		::std::mem::replace(&mut self.stop_waiters, Promise::new()).signal(result.clone());
+
+		match state {
+			ExecutionState::Executing(mut promise) => promise.signal(result),
+
+			// A run's faults are part of its outcome, so the caller still learns
+			// where it stopped
+			ExecutionState::Running(mut promise) => promise.signal(Ok(RunOutcome {
+				stop: match result {
+					Ok(exit_reason) => RunStop::Exited(exit_reason),
+					Err(err)        => RunStop::Fault(err),
+				},
+				pc:   self.registers.pc,
+			})),
+
+			ExecutionState::Paused => {},
+		}
+	}
+
+	// Every watched register's value ahead of a step.  Which of them the step
+	// could have changed isn't known until it has been decoded and run.
+	fn reg_watch_values(&self) -> Vec<(usize, u64)> {
+		self.reg_watches.iter().enumerate().filter_map(|(i, watch)| {
+			self.get_reg(watch.reg.clone()).ok().map(|value| (i, value))
+		}).collect()
+	}
+
+	// Only registers the step's ops write are read back, unless the translator
+	// can't say where a register is held
+	fn check_reg_watches(&self, pc: u64, before: Vec<(usize, u64)>, instrs: &[iisa::Instr]) -> Vec<TraceExitHint> {
+		let mut hints = Vec::new();
+
+		if before.is_empty() {
+			return hints;
+		}
+
+		let defs: Vec<iisa::R> = instrs.iter()
+		                               .flat_map(|instr| iisa::op_defs(&instr.op, &self.translator))
+		                               .collect();
+
+		for (i, old) in before.into_iter() {
+			let watch = &self.reg_watches[i];
+
+			let written = match self.translator.reg_storage(&watch.reg) {
+				Some(storage) => defs.iter().any(|def| def.aliases(storage)),
+				None          => true,
+			};
+
+			if !written {
+				continue;
+			}
+
+			let new = match self.get_reg(watch.reg.clone()) {
+				Ok(new) if new != old => new,
+				_                     => continue,
+			};
+
+			if let Ok(hook) = watch.hook.lock() {
+				hints.push((*hook)(pc, old, new));
+			}
+		}
+
+		hints
+	}
+
+	fn check_write_watch(&mut self) -> Option<ExitReason> {
+		let (watch_virt, watch_phys) = match self.write_watch {
+			Some(watch) => watch,
+			None        => return None,
+		};
+
+		self.fsb.take_watch_hit().map(|hit| {
+			let big_endian = self.translator.big_endian();
+
+			let value = match hit.width {
+				1 => hit.data,
+				2 => (if big_endian { u16::from_be(hit.data as u16) } else { u16::from_le(hit.data as u16) }) as u64,
+				4 => (if big_endian { u32::from_be(hit.data as u32) } else { u32::from_le(hit.data as u32) }) as u64,
+				_ => if big_endian { u64::from_be(hit.data) } else { u64::from_le(hit.data) },
+			};
+
+			ExitReason::WatchpointHit {
+				addr:  hit.addr.wrapping_sub(watch_phys).wrapping_add(watch_virt),
+				value: value,
+			}
+		})
+	}
+
+	// A pc that doesn't translate is left to the fetch path, since the
+	// translator may want to turn that into a guest exception
+	fn check_pc_in_bounds(&self, pc: u64) -> Option<ExitReason> {
+		if self.iisa_group_at(pc).is_some() {
+			return None;
+		}
+
+		let phys = match self.translator.virtual_to_phys(&self.registers, pc) {
+			Some(phys) => phys,
+			None       => return None,
+		};
+
+		match self.fsb.probe(phys, PROT_EXEC) {
+			mem::ProbeResult::Allowed => None,
+			_                         => Some(ExitReason::PcOutOfBounds(pc)),
+		}
+	}
+
+	// With CPU_EXIT_AT_END_OF_CODE, running out of bytes to fetch ends the run
+	// rather than failing it.  Untranslatable pcs are still the translator's.
+	fn fell_off_end(&self, pc: u64, err: &Error) -> Option<ExitReason> {
+		if !self.opts.contains(CPU_EXIT_AT_END_OF_CODE) {
+			return None;
+		}
+
+		match *err {
+			Error::UnableToFindRange(..)   |
+			Error::BusFault{..}            |
+			Error::TruncatedInstruction(_) => Some(ExitReason::FellOffEnd(pc)),
+
+			_ => None,
+		}
+	}
+
+	fn stop_if_fell_off_end(&self, pc: u64, err: Error) -> Result<StepOutcome, Error> {
+		match self.fell_off_end(pc, &err) {
+			Some(exit_reason) => Ok(StepOutcome::Stopped(exit_reason)),
+			None              => Err(err),
+		}
+	}
+
+	fn check_run_limits(&self, pc: u64) -> Option<ExitReason> {
+		let limits = match self.run_limits {
+			Some(ref limits) => limits,
+			None             => return None,
+		};
+
+		let insns_run = self.insn_count - limits.start_insn_count;
+
+		if limits.stop_at.contains(&pc) && (pc != limits.start_pc || insns_run != 0) {
+			Some(ExitReason::StopAddressReached(pc))
+		} else if limits.max_insns.map_or(false, |max_insns| insns_run >= max_insns) {
+			Some(ExitReason::InstructionLimitReached)
+		} else if limits.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+			Some(ExitReason::TimedOut)
+		} else {
+			None
+		}
+	}
+
+	fn enable_trace_file(&mut self, path: &Path, format: TraceFormat) -> Result<(), Error> {
+		try!(self.flush_trace());
+
+		let file = try!(File::create(path).map_err(|err| Error::Io(err.to_string())));
+
+		self.trace_file = Some((BufWriter::new(file), format));
+
+		Ok(())
+	}
+
+	fn write_trace(&mut self, pc: u64) -> Result<(), Error> {
+		let result = match self.trace_file {
+			Some((ref mut writer, TraceFormat::PcOnly)) => {
+				let mut record = [0u8; 8];
+
+				for (i, byte) in record.iter_mut().enumerate() {
+					*byte = (pc >> (i * 8)) as u8;
+				}
+
+				writer.write_all(&record)
+			},
+
+			None => Ok(()),
+		};
+
+		result.map_err(|err| Error::Io(err.to_string()))
+	}
+
+	fn flush_trace(&mut self) -> Result<(), Error> {
+		match self.trace_file {
+			Some((ref mut writer, _)) => writer.flush().map_err(|err| Error::Io(err.to_string())),
+			None                      => Ok(()),
+		}
+	}
+
+	fn get_reg(&self, reg: CpuReg) -> Result<u64, Error> {
+		match reg {
+			CpuReg::Cycles     => Ok(self.cycle_count),
+			CpuReg::Predicates => Ok(self.registers.preds()),
+			_                  => self.translator.get_reg(&self.registers, reg),
+		}
+	}
+
+	fn set_reg(&mut self, reg: CpuReg, value: u64) -> Result<(), Error> {
+		match reg {
+			CpuReg::Cycles     => { self.cycle_count = value; Ok(()) },
+			CpuReg::Predicates => { self.registers.set_preds(value); Ok(()) },
+			_                  => self.translator.set_reg(&mut self.registers, reg, value),
+		}
+	}
+
+	fn diagnostic(&self, message: &str) {
+		if let Some(ref diagnostic_hook) = self.diagnostic_hook {
+			if let Ok(hook) = diagnostic_hook.hook.lock() {
+				(*hook)(message);
+			}
+		}
+	}
+
+	fn emit_event(&mut self, event: CpuEvent) {
+		// Subscribers that have hung up are dropped rather than treated as an error
+		self.event_subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+	}
+
+	// Stores still in the buffer are lost, as with any other reset
+	// The reset vector starts a block, like any other control transfer
+	fn reset(&mut self) {
+		self.cycle_count = 0;
+		self.registers.clear();
+		self.store_buffer.clear();
+		self.registers.pc = self.translator.reset_vector();
+		self.at_block_start = true;
+		self.rearm_watchdog();
+	}
+
+	fn rearm_watchdog(&mut self) {
+		let cycle_count = self.cycle_count;
+
+		if let Some((ref watchdog, ref mut deadline)) = self.watchdog {
+			let timeout_cycles = watchdog.lock().map(|watchdog| watchdog.timeout_cycles()).unwrap_or(0);
+
+			*deadline = cycle_count.saturating_add(timeout_cycles);
+		}
+	}
+
+	// Restarts the countdown if the guest kicked since the last check, or resets
+	// the CPU if the countdown ran out first
+	fn check_watchdog(&mut self) -> Option<ExitReason> {
+		let (kicked, deadline) = match self.watchdog {
+			Some((ref watchdog, deadline)) => (watchdog.lock().map(|mut watchdog| watchdog.take_kick()).unwrap_or(false), deadline),
+			None                           => return None,
+		};
+
+		if kicked {
+			self.rearm_watchdog();
+
+			None
+		} else if self.cycle_count >= deadline {
+			self.reset();
+
+			Some(ExitReason::WatchdogReset)
+		} else {
+			None
+		}
+	}
+
+	// Up to len bytes are copied out, as other CPUs sharing the memory can write
+	// them while they're being decoded
+	fn fetch(&self, addr: u64, len: usize) -> Result<(Page, u64), Error> {
+		let page_virt_base = addr & !((PAGE_SIZE as u64) - 1);
+		let page_phys_base = match self.translator.virtual_to_phys(&self.registers, page_virt_base) {
+			Some(virt) => virt,
+			None => return Err(Error::FetchTranslationFault(addr)),
+		};
+		let page_offset = (addr - page_virt_base) as usize;
+		let addr_phys = page_phys_base + (page_offset as u64);
+
+		let (_, bytes) = try!(self.fsb.read_span(addr_phys, addr_phys, cmp::min(len, PAGE_SIZE - page_offset)));
+
+		Ok((Page::new(addr, bytes), addr_phys))
+	}
+
+	fn iisa_group_at(&self, addr: u64) -> Option<Vec<iisa::Instr>> {
+		for (&base, program) in self.iisa_programs.iter() {
+			let mut cur_addr = base;
+			let mut group = Vec::new();
+
+			for instr in program.iter() {
+				group.push(instr.clone());
+
+				if instr.size == 0 {
+					continue;
+				}
+
+				if cur_addr == addr {
+					return Some(group);
+				}
+
+				cur_addr += instr.size as u64;
+				group = Vec::new();
+			}
+		}
+
+		None
+	}
+
+	fn decode_at(&self, addr: u64) -> Result<Vec<iisa::Instr>, Error> {
+		if let Some(instrs) = self.iisa_group_at(addr) {
+			return Ok(instrs);
+		}
+
+		let (page, _) = try!(self.fetch(addr, FETCH_LEN));
+
+		self.decode_in_page(&page, addr)
+	}
+
+	// Every fetch that goes through the translator is rewritten here, so
+	// nothing downstream ever sees the unrewritten instrs
+	fn decode_in_page(&self, page: &Page, addr: u64) -> Result<Vec<iisa::Instr>, Error> {
+		let mut instrs = try!(self.translate_in_page(page, addr));
+
+		for rewriter in self.decode_rewriters.iter() {
+			if let Ok(hook) = rewriter.hook.lock() {
+				(*hook)(addr, &mut instrs);
+			}
+		}
+
+		Ok(instrs)
+	}
+
+	fn translate_in_page(&self, page: &Page, addr: u64) -> Result<Vec<iisa::Instr>, Error> {
+		let mut needed = match try!(page.decode(addr, &self.translator)) {
+			iisa::DecodeOutcome::Decoded(instrs)    => return Ok(instrs),
+			iisa::DecodeOutcome::NeedMoreBytes(len) => len,
+		};
+
+		// The instruction runs past what was fetched, so gather the rest of it
+		// (from the pages that follow, if need be) and decode from the copy instead
+		let mut buffer = try!(page.bytes_from(addr)).to_vec();
+
+		loop {
+			while buffer.len() < needed {
+				let next_addr = addr + (buffer.len() as u64);
+				let next_page = match self.fetch(next_addr, needed - buffer.len()) {
+					Ok((next_page, _)) => next_page,
+					Err(_)             => return Err(Error::TruncatedInstruction(next_addr)),
+				};
+				let available = try!(next_page.bytes_from(next_addr));
+				let len = cmp::min(available.len(), needed - buffer.len());
+
+				buffer.extend_from_slice(&available[..len]);
+			}
+
+			needed = match try!(self.translator.decode(addr, &buffer)) {
+				iisa::DecodeOutcome::Decoded(instrs)                          => return Ok(instrs),
+				iisa::DecodeOutcome::NeedMoreBytes(len) if len > buffer.len() => len,
+				iisa::DecodeOutcome::NeedMoreBytes(_)                         => return Err(Error::TruncatedInstruction(addr)),
+			};
+		}
+	}
+
+	fn disassemble_at(&self, addr: u64, max_insns: usize) -> Result<Vec<(u64, iisa::Instr)>, Error> {
+		let mut disassembly = Vec::new();
+		let mut cur_addr = addr;
+
+		while disassembly.len() < max_insns {
+			let instrs = try!(self.decode_at(cur_addr));
+
+			for instr in instrs.into_iter().take(max_insns - disassembly.len()) {
+				let end_of_block = iisa::is_end_of_block(&instr.op);
+
+				let size = instr.size as u64;
+
+				disassembly.push((cur_addr, instr));
+				cur_addr += size;
+
+				if end_of_block {
+					return Ok(disassembly);
+				}
+			}
+		}
+
+		Ok(disassembly)
+	}
+
+	fn record_branch(&mut self, from: u64, instrs: &[iisa::Instr]) {
+		if !instrs.iter().any(|instr| iisa::is_end_of_block(&instr.op)) {
+			return;
+		}
+
+		let size = instrs.iter().fold(0, |size, instr| size + (instr.size as u64));
+		let to = self.registers.pc;
+
+		self.branch_edges.insert(BranchEdge {
+			from:        from,
+			to:          to,
+			fallthrough: to == from.wrapping_add(size),
+		});
+	}
+
+	// The bus only hears about the trace for the span of one step, so reads
+	// are stamped with (or matched against) the instruction that made them.
+	// Events for an instruction already gone by were never read, so replay has
+	// diverged.
+	fn tap_mmio(&mut self) -> Option<ExitReason> {
+		let insn_count = self.insn_count;
+
+		let tap = match self.replay {
+			Replay::Off => return None,
+
+			Replay::Recording(_) => mem::MmioTap::Record(Vec::new()),
+
+			Replay::Replaying(ref mut trace) => {
+				let mut reads = VecDeque::new();
+
+				while trace.front().map_or(false, |event| event.insn_count <= insn_count) {
+					let event = trace.pop_front().unwrap();
+
+					if event.insn_count < insn_count {
+						self.replay = Replay::Off;
+
+						return Some(ExitReason::ReplayDiverged{insn_count: event.insn_count, expected: Some(event.read), actual: None});
+					}
+
+					reads.push_back(event.read);
+				}
+
+				mem::MmioTap::Replay(reads)
+			},
+		};
+
+		self.fsb.set_mmio_tap(Some(tap));
+
+		None
+	}
+
+	fn untap_mmio(&mut self, insn_count: u64) -> Option<ExitReason> {
+		let diverged = match (&mut self.replay, self.fsb.take_mmio_tap()) {
+			(&mut Replay::Recording(ref mut trace), Some(mem::MmioTap::Record(reads))) => {
+				trace.extend(reads.into_iter().map(|read| ReplayEvent{insn_count: insn_count, read: read}));
+
+				None
+			},
+
+			(_, Some(mem::MmioTap::Replay(mut reads))) => reads.pop_front().map(|read| {
+				ExitReason::ReplayDiverged{insn_count: insn_count, expected: Some(read), actual: None}
+			}),
+
+			(_, Some(mem::MmioTap::Diverged{expected, addr, width})) => {
+				Some(ExitReason::ReplayDiverged{insn_count: insn_count, expected: expected, actual: Some((addr, width))})
+			},
+
+			_ => None,
+		};
+
+		if diverged.is_some() {
+			self.replay = Replay::Off;
+		}
+
+		diverged
+	}
+
+	// decoded is what the caller already decoded at the pc, if anything, so it
+	// needn't be decoded again
+	fn single_step(&mut self, decoded: Option<Vec<iisa::Instr>>) -> Result<StepOutcome, Error> {
+		let pc = self.registers.pc;
+		let insn_count = self.insn_count;
+
+		if let Some(exit_reason) = self.tap_mmio() {
+			return Ok(StepOutcome::Stopped(exit_reason));
+		}
+
+		let outcome = self.single_step_inner(decoded);
+
+		// The bus error a diverged read takes is replay's doing, not the guest's
+		if let Some(exit_reason) = self.untap_mmio(insn_count) {
+			return Ok(StepOutcome::Stopped(exit_reason));
+		}
+
+		let outcome = try!(outcome);
+
+		if let StepOutcome::Executed(ref instrs) = outcome {
+			try!(self.write_trace(pc));
+
+			let cycles = instrs.iter().fold(0, |cycles, instr| cycles + self.translator.op_cost(&instr.op));
+
+			self.cycle_count = self.cycle_count.wrapping_add(cycles as u64);
+			self.translator.advance_time(&mut self.registers, cycles);
+
+			if try!(self.translator.take_interrupt(&mut self.registers)) {
+				self.at_block_start = true;
+			}
+
+			if self.opts.contains(CPU_RECORD_BRANCHES) {
+				self.record_branch(pc, instrs);
+			}
+		}
+
+		Ok(outcome)
+	}
+
+	fn single_step_inner(&mut self, decoded: Option<Vec<iisa::Instr>>) -> Result<StepOutcome, Error> {
+		let pc = self.registers.pc;
+
+		if let Some(instrs) = self.iisa_group_at(pc) {
+			try!(self.interpret(&instrs));
+
+			return Ok(StepOutcome::Executed(instrs));
+		}
+
+		let (page, pc_phys) = match self.fetch(pc, FETCH_LEN) {
+			Ok(fetched) => fetched,
+			Err(err)    => return self.stop_if_fell_off_end(pc, err),
+		};
+
+		if self.opts.contains(CPU_TRAP_ON_ZERO_FETCH) {
+			let size = match page.instruction_size_at(pc, &self.translator) {
+				Ok(size) => size as usize,
+				Err(err) => return self.stop_if_fell_off_end(pc, err),
+			};
+
+			if page.is_zero_at(pc, size) && !self.fsb.is_initialized(pc_phys, size as u64) {
+				return Ok(StepOutcome::Stopped(ExitReason::ZeroInstructionFetch(pc)));
+			}
+		}
+
+		let decoded = match decoded {
+			Some(instrs) => Ok(instrs),
+			None         => self.decode_in_page(&page, pc),
+		};
+
+		let instrs = match decoded {
+			Ok(instrs) => instrs,
+
+			// Neither running out of mapped bytes nor an arch that can't decode
+			// anything yet is a decode error
+			Err(Error::TruncatedInstruction(addr)) => return self.stop_if_fell_off_end(pc, Error::TruncatedInstruction(addr)),
+			Err(Error::UnimplementedArchitecture)  => return Err(Error::UnimplementedArchitecture),
+
+			Err(err) => match self.decode_error_policy {
+				DecodeErrorPolicy::Fail => return Err(err),
+
+				DecodeErrorPolicy::SkipOne => {
+					let size = try!(page.instruction_size_at(pc, &self.translator));
+
+					vec!(iisa::Instr{op: iisa::Op::Nop, pred: iisa::Pred::None, exc: 0, size: size})
+				},
+
+				DecodeErrorPolicy::Trap => {
+					let code = self.translator.reserved_instruction_code();
+
+					try!(self.translator.raise_exception(&mut self.registers, code));
+
+					self.at_block_start = true;
+
+					return Ok(StepOutcome::Executed(Vec::new()));
+				},
+			},
+		};
+
+		try!(self.interpret(&instrs));
+
+		Ok(StepOutcome::Executed(instrs))
+	}
+
+	// A taken trap is raised here, the same as an exception a hook raises.  The
+	// trapping instruction doesn't retire, so only an untrapped run is counted.
+	fn interpret(&mut self, instrs: &Vec<iisa::Instr>) -> Result<(), Error> {
+		let trap = try!(iisa::interpret_op_list(instrs, &mut self.registers, &mut self.fsb, &mut self.store_buffer, &self.translator));
+
+		match trap {
+			Some(code) => {
+				try!(self.translator.raise_exception(&mut self.registers, code));
+
+				self.at_block_start = true;
+			},
+
+			None => self.insn_count += insns_in(instrs),
+		}
+
+		Ok(())
+	}
+
+	fn read_regs(&self, regs: &[CpuReg]) -> Result<Vec<u64>, Error> {
+		regs.iter().map(|reg| self.get_reg(reg.clone())).collect()
+	}
+
+	fn register_snapshot(&self) -> Result<RegisterSnapshot, Error> {
+		let mut regs = self.translator.arch_registers();
+		regs.push(CpuReg::Predicates);
+
+		let values = try!(self.read_regs(&regs));
+
+		Ok(RegisterSnapshot::new(self.registers.pc, regs.into_iter().zip(values.into_iter()).collect()))
+	}
+
+	fn restore_register_snapshot(&mut self, snapshot: &RegisterSnapshot) -> Result<(), Error> {
+		for &(ref reg, value) in snapshot.regs.iter() {
+			try!(self.set_reg(reg.clone(), value));
+		}
+
+		self.set_reg(CpuReg::Pc, snapshot.pc)
+	}
+
+	fn step_one_with_diff(&mut self) -> Result<StepDiff, Error> {
+		let arch_regs = self.translator.arch_registers();
+
+		let before = try!(self.read_regs(&arch_regs));
+		let outcome = try!(self.single_step(None));
+		try!(self.flush_trace());
+		let after = try!(self.read_regs(&arch_regs));
+
+		let changed = arch_regs.into_iter()
+		                       .zip(before.into_iter().zip(after.into_iter()))
+		                       .filter(|&(_, (old, new))| old != new)
+		                       .map(|(reg, (old, new))| (reg, old, new))
+		                       .collect();
+
+		match outcome {
+			StepOutcome::Executed(instrs)     => Ok((instrs, changed, ExitReason::SingleStep)),
+			StepOutcome::Stopped(exit_reason) => Ok((Vec::new(), changed, exit_reason)),
+		}
+	}
+
+	fn insn_bytes_at(&self, addr: u64) -> Result<Vec<u8>, Error> {
+		let (page, _) = try!(self.fetch(addr, FETCH_LEN));
+		let size = try!(page.instruction_size_at(addr, &self.translator)) as usize;
+
+		if size <= FETCH_LEN {
+			return Ok(try!(page.bytes_at(addr, size)).to_vec());
+		}
+
+		let (page, _) = try!(self.fetch(addr, size));
+
+		Ok(try!(page.bytes_at(addr, size)).to_vec())
+	}
+
+	fn insn_size_at(&self, addr: u64) -> Result<u64, Error> {
+		if let Some(instrs) = self.iisa_group_at(addr) {
+			return Ok(instrs.iter().fold(0, |size, instr| size + (instr.size as u64)));
+		}
+
+		let (page, _) = try!(self.fetch(addr, FETCH_LEN));
+
+		Ok(try!(page.instruction_size_at(addr, &self.translator)) as u64)
+	}
+
+	// The architectural instructions a step from addr covers, as (address, size).
+	// Each instr with a size ends an instruction.  A fused pair (a branch and
+	// its delay slot) decodes to one Instr the size of both, but is still two
+	// instructions of their own sizes.
+	fn step_insns_at(&self, addr: u64, decoded: &Result<Vec<iisa::Instr>, Error>) -> Vec<(u64, u64)> {
+		let instrs = match *decoded {
+			Ok(ref instrs) => instrs,
+
+			// What doesn't decode is still an instruction at addr, if there's
+			// anything there at all
+			Err(_) => return self.insn_size_at(addr).map(|size| vec!((addr, size))).unwrap_or_else(|_| Vec::new()),
+		};
+
+		let mut insns = Vec::new();
+		let mut cur_addr = addr;
+
+		for instr in instrs.iter().filter(|instr| instr.size != 0) {
+			let size = instr.size as u64;
+
+			let branch_size = match instr.op {
+				iisa::Op::Branch { .. } => self.insn_size_at(cur_addr).unwrap_or(size),
+				_                       => size,
+			};
+
+			insns.push((cur_addr, cmp::min(branch_size, size)));
+
+			if branch_size < size {
+				insns.push((cur_addr + branch_size, size - branch_size));
+			}
+
+			cur_addr += size;
+		}
+
+		insns
+	}
+
+	fn decode_block(&self, addr: u64) -> Vec<iisa::Instr> {
+		let mut block = Vec::new();
+		let mut cur_addr = addr;
+
+		while (cur_addr - addr) < (PAGE_SIZE as u64) {
+			let instrs = match self.decode_at(cur_addr) {
+				Ok(instrs) => instrs,
+				Err(_)     => break,
+			};
+
+			let mut end_of_block = false;
+
+			for instr in instrs.into_iter() {
+				cur_addr += instr.size as u64;
+				end_of_block |= iisa::is_end_of_block(&instr.op);
+
+				block.push(instr);
+			}
+
+			if end_of_block {
+				break;
+			}
+		}
+
+		block
+	}
+
+	fn block_size_at(&self, addr: u64) -> u64 {
+		self.decode_block(addr).iter().fold(0, |size, instr| size + (instr.size as u64))
+	}
+
+	fn notify_decode(&mut self, addr: u64) {
+		if self.decode_hooks.is_empty() || self.decoded_blocks.contains(&addr) {
+			return;
+		}
+
+		let block = self.decode_block(addr);
+
+		for decode_hook in self.decode_hooks.iter() {
+			if let Ok(hook) = decode_hook.hook.lock() {
+				(*hook)(addr, &block);
+			}
+		}
+
+		self.decoded_blocks.insert(addr);
+	}
+
+	fn execute_step(&mut self) -> Result<Option<ExitReason>, Error> {
+		let pc = self.registers.pc;
+		let mut stop_requested = false;
+		let mut exception = None;
+
+		if let Some(exit_reason) = self.check_run_limits(pc) {
+			return Ok(Some(exit_reason));
+		}
+
+		if let Some(exit_reason) = self.check_watchdog() {
+			return Ok(Some(exit_reason));
+		}
+
+		// Every control transfer starts a block, so checking here catches a
+		// jump or exception vector into nothing before the fetch path does
+		if self.at_block_start {
+			if let Some(exit_reason) = self.check_pc_in_bounds(pc) {
+				return Ok(Some(exit_reason));
+			}
+		}
+
+		// Decoded once for everything below that looks at the step's instrs,
+		// single_step included
+		let decoded = if self.opts.contains(CPU_EXIT_AT_END_OF_CODE) || !self.code_hooks_on_single.is_empty() || !self.op_class_hooks.is_empty() {
+			Some(self.decode_at(pc))
+		} else {
+			None
+		};
+
+		if let Some(Err(ref err)) = decoded {
+			if let Some(exit_reason) = self.fell_off_end(pc, err) {
+				return Ok(Some(exit_reason));
+			}
+		}
+
+		if self.at_block_start {
+			self.notify_decode(pc);
+		}
+
+		if self.at_block_start && !self.hooks_on_all.is_empty() {
+			let block_size = self.block_size_at(pc);
+
+			for block_hook in self.hooks_on_all.iter() {
+				if let Ok(hook) = block_hook.hook.lock() {
+					match (*hook)(pc, block_size, self.insn_count) {
+						TraceExitHint::ContinueExecution    => {},
+						TraceExitHint::StopExecution        => stop_requested = true,
+						TraceExitHint::RaiseException(code) => exception = Some(code),
+					}
+				}
+			}
+		}
+
+		// Hooks on the delay slot of a fused pair fire along with the branch's,
+		// since the two execute as one step
+		let insns = match decoded {
+			Some(ref decoded) if !self.code_hooks_on_single.is_empty() => self.step_insns_at(pc, decoded),
+
+			_ => Vec::new(),
+		};
+
+		for (i, &(insn_addr, size)) in insns.iter().enumerate() {
+			for code_hook in self.code_hooks_on_single.iter().filter(|code_hook| code_hook.base == insn_addr) {
+				if let Ok(hook) = code_hook.hook.lock() {
+					match (*hook)(insn_addr, size, self.insn_count + (i as u64)) {
+						TraceExitHint::ContinueExecution    => {},
+						TraceExitHint::StopExecution        => stop_requested = true,
+						TraceExitHint::RaiseException(code) => exception = Some(code),
+					}
+				}
+			}
+		}
+
+		if let Some(Ok(ref instrs)) = decoded {
+			// Anything that doesn't decode faults in single_step instead
+			let mut ops = Vec::new();
+
+			for instr in instrs.iter() {
+				ops.push(&instr.op);
+
+				if let iisa::Op::Branch { ref delay_slot, .. } = instr.op {
+					ops.push(&**delay_slot);
+				}
+			}
+
+			for op in ops.into_iter() {
+				let class = iisa::op_class(op);
+
+				for op_class_hook in self.op_class_hooks.iter().filter(|op_class_hook| op_class_hook.class == class) {
+					if let Ok(hook) = op_class_hook.hook.lock() {
+						match (*hook)(pc, op) {
+							TraceExitHint::ContinueExecution    => {},
+							TraceExitHint::StopExecution        => stop_requested = true,
+							TraceExitHint::RaiseException(code) => exception = Some(code),
+						}
+					}
+				}
+			}
+		}
+
+		// A raised exception takes the place of the hooked instruction, so it's
+		// never executed and EPC points back at it
+		if let Some(code) = exception {
+			try!(self.translator.raise_exception(&mut self.registers, code));
+
+			self.at_block_start = true;
+
+			return Ok(if stop_requested { Some(ExitReason::CodeHookSignalledStop) } else { None });
+		}
+
+		let watched = self.reg_watch_values();
+
+		// single_step marks a block start itself if it diverts the pc (say, to
+		// take an interrupt)
+		self.at_block_start = false;
+
+		let instrs = match try!(self.single_step(decoded.and_then(|decoded| decoded.ok()))) {
+			StepOutcome::Stopped(exit_reason) => {
+				return Ok(Some(exit_reason));
+			},
+
+			StepOutcome::Executed(instrs) => {
+				self.at_block_start |= instrs.iter().any(|instr| iisa::is_end_of_block(&instr.op));
+
+				instrs
+			},
+		};
+
+		// The change has already happened by the time a watch fires, so an
+		// exception it raises lands after the instruction rather than on it
+		for hint in self.check_reg_watches(pc, watched, &instrs).into_iter() {
+			match hint {
+				TraceExitHint::ContinueExecution    => {},
+				TraceExitHint::StopExecution        => stop_requested = true,
+				TraceExitHint::RaiseException(code) => {
+					try!(self.translator.raise_exception(&mut self.registers, code));
+
+					self.at_block_start = true;
+				},
+			}
+		}
+
+		if let Some(exit_reason) = self.check_write_watch() {
+			return Ok(Some(exit_reason));
+		}
+
+		if stop_requested {
+			Ok(Some(ExitReason::CodeHookSignalledStop))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn execute(&mut self) {
+		let mut running = true;
+
+		while running {
+			let cur_state = self.execution_state.clone();
+			running = match cur_state {
+				ExecutionState::Paused => {
+					let msg = match self.rx.recv() {
+						Ok(msg) => msg,
+						Err(err) => {
+							self.diagnostic(&format!("Exiting cpu thread because {:?}", err));
+							return;
+						},
+					};
+
+					self.process_message(msg)
+				},
+
+				_ => {
+					match self.execute_step() {
+						Ok(None) => {},
+						result => {
+							let result = result.map(|exit_reason| exit_reason.unwrap())
+							                   .and_then(|exit_reason| self.flush_trace().map(|_| exit_reason));
+
+							self.emit_event(match result {
+								Ok(ref exit_reason) => CpuEvent::Stopped(exit_reason.clone()),
+								Err(ref err)        => CpuEvent::Fault(err.clone()),
+							});
+
+							self.finish_execution(cur_state, result);
+						},
+					}
+
+					let msg = match self.rx.try_recv() {
+						Ok(msg) => msg,
+						Err(TryRecvError::Empty) => {
+							continue;
+						},
+						Err(TryRecvError::Disconnected) => {
+							return;
+						},
+					};
+
+					self.process_message(msg)
+				},
+			}
+		}
+	}
+}
+
+fn spawn_backend<T: 'static+Send+Clone+Translator>(translator: T, opts: CpuOpt, fsb: &mut mem::BusMatrix) -> FrontEnd {
+	let (tx, rx) = channel::<Message>();
+
+	let mem_update_channel = tx.clone();
+
+	thread::spawn(move || {
+		let mut backend = Backend::new(rx, translator, opts);
+
+		backend.execute();
+	});
+
+	fsb.add_child_matrix(Box::new(move |update_op| {
+		let mut promise = Promise::<()>::new();
+		let future = promise.get_future();
+
+		let _ = mem_update_channel.send(Message::FsbUpdateOp(update_op, promise));
+
+		let _ = future.wait();
+	}));
+
+	FrontEnd::new(tx)
+}
+
+pub fn executor<T: 'static+Send+Clone+Translator>(translator: T, opts: CpuOpt, fsb: &mut mem::BusMatrix) -> Result<Box<Cpu>, Error> {
+	Ok(Box::new(spawn_backend(translator, opts, fsb)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{spawn_backend, FrontEnd};
+
+	use super::super::super::{Cpu, CpuReg, TraceExitHint, CPU_ENDIAN_BIG, PROT_ALL};
+	use super::super::super::mem;
+	use super::super::super::mips;
+	use super::super::super::mips::translate::MipsTranslator;
+
+	use std::sync::{Arc, Mutex};
+	use std::sync::mpsc::{channel, Receiver};
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::thread;
+
+	#[test]
+	fn front_end_clones_observe_their_own_order() {
+		const ITERATIONS: u64 = 500;
+
+		let mut fsb: mem::BusMatrix = Default::default();
+
+		let front_end = spawn_backend(MipsTranslator{ arch: mips::Arch::R2000, big_endian: true }, CPU_ENDIAN_BIG, &mut fsb);
+
+		let threads: Vec<_> = (0..2).map(|thread_num| {
+			let mut front_end = front_end.clone();
+
+			thread::spawn(move || {
+				let own_reg   = CpuReg::CpuSpecific(1 + thread_num);
+				let other_reg = CpuReg::CpuSpecific(2 - thread_num);
+
+				let mut last_other = 0;
+
+				for i in 1..(ITERATIONS + 1) {
+					front_end.set_reg(own_reg.clone(), i).unwrap();
+
+					assert_eq!(i, front_end.get_reg(own_reg.clone()).unwrap());
+
+					let other = front_end.get_reg(other_reg.clone()).unwrap();
+
+					assert!(other >= last_other);
+
+					last_other = other;
+				}
+			})
+		}).collect();
+
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		let mut front_end = front_end;
+
+		assert_eq!(ITERATIONS, front_end.get_reg(CpuReg::CpuSpecific(1)).unwrap());
+		assert_eq!(ITERATIONS, front_end.get_reg(CpuReg::CpuSpecific(2)).unwrap());
+
+		front_end.shutdown();
+	}
+
+	#[test]
+	fn reg_read_mid_execute_is_consistent_with_pc() {
+		const CODE_PHYS: u64 = 0x1FC00000;
+		const CODE_VIRT: u64 = 0xBFC00000;
+
+		// $at always holds the index of the instruction about to execute
+		const CODE: [u32; 4] = [
+			0x24010001, // addiu $at, $zero, 1
+			0x24010002, // addiu $at, $zero, 2
+			0x1000FFFD, // beq $zero, $zero, -12
+			0x24010000, // addiu $at, $zero, 0
+		];
+
+		let mut fsb: mem::BusMatrix = Default::default();
+
+		let mut front_end = spawn_backend(MipsTranslator{ arch: mips::Arch::R2000, big_endian: true }, CPU_ENDIAN_BIG, &mut fsb);
+
+		fsb.add_mappable_range(CODE_PHYS, 0x1000, PROT_ALL).unwrap();
+
+		for (i, word) in CODE.iter().enumerate() {
+			fsb.set_u32(CODE_PHYS + ((i as u64) * 4), *word, mem::Endian::Big).unwrap();
+		}
+
+		let stop = Arc::new(AtomicBool::new(false));
+		let hook_stop = stop.clone();
+
+		front_end.add_block_hook_all(Arc::new(Mutex::new(move |_, _, _| {
+			if hook_stop.load(Ordering::SeqCst) {
+				TraceExitHint::StopExecution
+			} else {
+				TraceExitHint::ContinueExecution
+			}
+		}))).unwrap();
+
+		front_end.set_reg(CpuReg::Pc, CODE_VIRT).unwrap();
+
+		let mut runner = front_end.clone();
+		let execution = thread::spawn(move || runner.execute());
+
+		for _ in 0..200 {
+			let (at, pc) = front_end.get_reg_at_boundary(mips::REG_AT).unwrap();
+
+			assert_eq!((pc - CODE_VIRT) / 4, at);
+		}
+
+		stop.store(true, Ordering::SeqCst);
+
+		execution.join().unwrap().unwrap();
+
+		front_end.shutdown();
+	}
+
+	#[test]
+	fn sync_memory_map_makes_new_ranges_visible_mid_execute() {
+		const CODE_PHYS: u64 = 0x1FC00000;
+		const CODE_VIRT: u64 = 0xBFC00000;
+		const NEW_PHYS: u64 = 0x00001000;
+		const NEW_VIRT: u64 = 0xA0001000;
+
+		let mut fsb: mem::BusMatrix = Default::default();
+
+		let mut front_end = spawn_backend(MipsTranslator{ arch: mips::Arch::R2000, big_endian: true }, CPU_ENDIAN_BIG, &mut fsb);
+
+		fsb.add_mappable_range(CODE_PHYS, 0x1000, PROT_ALL).unwrap();
+		fsb.set_u32(CODE_PHYS + 0, 0x1000FFFF, mem::Endian::Big).unwrap(); // beq $zero, $zero, -4
+		fsb.set_u32(CODE_PHYS + 4, 0x24010000, mem::Endian::Big).unwrap(); // addiu $at, $zero, 0
+
+		let stop = Arc::new(AtomicBool::new(false));
+		let hook_stop = stop.clone();
+
+		front_end.add_block_hook_all(Arc::new(Mutex::new(move |_, _, _| {
+			if hook_stop.load(Ordering::SeqCst) {
+				TraceExitHint::StopExecution
+			} else {
+				TraceExitHint::ContinueExecution
+			}
+		}))).unwrap();
+
+		front_end.set_reg(CpuReg::Pc, CODE_VIRT).unwrap();
+
+		let mut runner = front_end.clone();
+		let execution = thread::spawn(move || runner.execute());
+
+		while !front_end.is_running().unwrap() {
+			thread::yield_now();
+		}
+
+		fsb.add_mappable_range(NEW_PHYS, 0x1000, PROT_ALL).unwrap();
+		fsb.set_u32(NEW_PHYS, 0x34213456, mem::Endian::Big).unwrap(); // ori $at, $at, 0x3456
+
+		front_end.sync_memory_map().unwrap();
+
+		assert!(front_end.is_running().unwrap());
+		assert_eq!(1, front_end.disassemble_at(NEW_VIRT, 1).unwrap().len());
+
+		stop.store(true, Ordering::SeqCst);
+
+		execution.join().unwrap().unwrap();
+
+		front_end.shutdown();
+	}
+
+	#[test]
+	fn reset_mid_block_starts_a_block_at_the_reset_vector() {
+		const CODE_PHYS: u64 = 0x1FC00000;
+		const CODE_VIRT: u64 = 0xBFC00000;
+		const END_VIRT:  u64 = CODE_VIRT + 0x900;
+		const LOOP_VIRT: u64 = CODE_VIRT + 0x1000;
+
+		let mut fsb: mem::BusMatrix = Default::default();
+
+		let mut front_end = spawn_backend(MipsTranslator{ arch: mips::Arch::R2000, big_endian: true }, CPU_ENDIAN_BIG, &mut fsb);
+
+		// Everything else is ori $at, $at, 0, so the loop is one long block and
+		// a reset almost always lands in the middle of it
+		fsb.add_mappable_range(CODE_PHYS, 0x2000, PROT_ALL).unwrap();
+
+		for offset in 0..0x800 {
+			fsb.set_u32(CODE_PHYS + (offset * 4), 0x34210000, mem::Endian::Big).unwrap();
+		}
+
+		fsb.set_u32(CODE_PHYS + 0x800,  0x1000003F, mem::Endian::Big).unwrap(); // beq $zero, $zero, END
+		fsb.set_u32(CODE_PHYS + 0x17F8, 0x1000FE01, mem::Endian::Big).unwrap(); // beq $zero, $zero, LOOP
+
+		let blocks = Arc::new(Mutex::new(Vec::new()));
+		let hook_blocks = blocks.clone();
+
+		front_end.add_block_hook_all(Arc::new(Mutex::new(move |pc, _, _| {
+			hook_blocks.lock().unwrap().push(pc);
+
+			if pc == END_VIRT {
+				TraceExitHint::StopExecution
+			} else {
+				TraceExitHint::ContinueExecution
+			}
+		}))).unwrap();
+
+		front_end.set_reg(CpuReg::Pc, LOOP_VIRT).unwrap();
+
+		let mut runner = front_end.clone();
+		let execution = thread::spawn(move || runner.execute());
+
+		while blocks.lock().unwrap().is_empty() {
+			thread::yield_now();
+		}
+
+		front_end.reset().unwrap();
+
+		execution.join().unwrap().unwrap();
+
+		assert!(blocks.lock().unwrap().contains(&CODE_VIRT));
+
+		front_end.shutdown();
+	}
+
+	fn spawn_with_diagnostics(fsb: &mut mem::BusMatrix) -> (FrontEnd, Receiver<String>) {
+		let mut front_end = spawn_backend(MipsTranslator{ arch: mips::Arch::R2000, big_endian: true }, CPU_ENDIAN_BIG, fsb);
+
+		let (tx, rx) = channel();
+		let tx = Mutex::new(tx);
+
+		front_end.set_diagnostic_hook(Arc::new(Mutex::new(move |message: &str| {
+			let _ = tx.lock().unwrap().send(message.to_string());
+		}))).unwrap();
+
+		(front_end, rx)
+	}
+
+	#[test]
+	fn shutdown_is_silent() {
+		let mut fsb: mem::BusMatrix = Default::default();
+
+		let (mut front_end, diagnostics) = spawn_with_diagnostics(&mut fsb);
+
+		front_end.shutdown();
+
+		// The hook (and its sender) is only dropped once the backend thread has
+		// exited, so this can't race with a late diagnostic
+		assert!(diagnostics.recv().is_err());
+	}
+
+	#[test]
+	fn lost_front_end_is_reported_to_hook() {
+		let mut fsb: mem::BusMatrix = Default::default();
+
+		let (front_end, diagnostics) = spawn_with_diagnostics(&mut fsb);
+
+		drop(front_end);
+		drop(fsb);
+
+		assert!(diagnostics.recv().unwrap().starts_with("Exiting cpu thread because"));
+	}
+}
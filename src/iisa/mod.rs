@@ -1,8 +1,13 @@
 pub mod executor;
+mod wire;
 
 use super::CpuReg;
 use super::Error;
 
+use super::mem::{BusMatrix, BusSlave};
+
+use std::collections::VecDeque;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum R {
 	Ip,
@@ -24,6 +29,92 @@ pub enum R {
 	TX(u16),
 }
 
+// B, H, W and X registers are views of the same byte addressed register file,
+// each indexed in units of its own width (see RegisterFile), and two views
+// alias exactly when their bytes overlap.  So W(0) is the low half of X(0) and
+// W(1) its high half.  The temporaries are views of a separate file laid out
+// the same way.
+impl R {
+	fn view_offset(self) -> Option<(u32, bool)> {
+		match self {
+			R::B(n)  => Some((n as u32, false)),
+			R::H(n)  => Some(((n as u32) * 2, false)),
+			R::W(n)  => Some(((n as u32) * 4, false)),
+			R::X(n)  => Some(((n as u32) * 8, false)),
+			R::TB(n) => Some((n as u32, true)),
+			R::TH(n) => Some(((n as u32) * 2, true)),
+			R::TW(n) => Some(((n as u32) * 4, true)),
+			R::TX(n) => Some(((n as u32) * 8, true)),
+
+			_ => None,
+		}
+	}
+
+	fn view_len(self) -> u32 {
+		match self {
+			R::B(_) | R::TB(_) => 1,
+			R::H(_) | R::TH(_) => 2,
+			R::W(_) | R::TW(_) => 4,
+			R::X(_) | R::TX(_) => 8,
+
+			_ => 0,
+		}
+	}
+
+	// Whether writing one of the two registers can change the other
+	pub fn aliases(self, other: R) -> bool {
+		match (self.view_offset(), other.view_offset()) {
+			(Some((offset, temp)), Some((other_offset, other_temp))) => {
+				(temp == other_temp) && (offset < other_offset + other.view_len()) && (other_offset < offset + self.view_len())
+			},
+
+			_ => self == other,
+		}
+	}
+
+	// The byte offset of the register's first byte in the architectural register
+	// file, or None for anything not held there (temporaries included)
+	pub fn base_index(self) -> Option<u16> {
+		match self.view_offset() {
+			Some((offset, false)) if offset <= (u16::max_value() as u32) => Some(offset as u16),
+			_                                                               => None,
+		}
+	}
+
+	// The view of width bits (8, 16, 32 or 64) that holds this register's first
+	// byte, in the same file.  Registers that aren't views are returned as is.
+	// None if there's no view that wide, or none numbered low enough to reach
+	// the byte.
+	pub fn with_width(self, width: u8) -> Option<R> {
+		let (offset, temp) = match self.view_offset() {
+			Some(view) => view,
+			None       => return Some(self),
+		};
+
+		let index = match width {
+			8 | 16 | 32 | 64 => offset / ((width / 8) as u32),
+			_                => return None,
+		};
+
+		if index > (u16::max_value() as u32) {
+			return None;
+		}
+
+		let index = index as u16;
+
+		Some(match (width, temp) {
+			(8,  false) => R::B(index),
+			(16, false) => R::H(index),
+			(32, false) => R::W(index),
+			(8,  true)  => R::TB(index),
+			(16, true)  => R::TH(index),
+			(32, true)  => R::TW(index),
+			(_,  false) => R::X(index),
+			(_,  true)  => R::TX(index),
+		})
+	}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Src {
 	Reg(R),
@@ -55,12 +146,23 @@ pub struct DstSrc {
 	pub src: Src,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SrcSrc {
+	pub src: [Src; 2],
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct SrcSrcTarget {
 	pub src: [Src; 2],
 	pub target: Src,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ext {
+	pub signed: bool,
+	pub width: u8,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Cond {
 	Ne,
@@ -71,12 +173,15 @@ pub enum Cond {
 	Lt,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Op {
 	Nop,
 
 	Add(DstSrcSrc),
 	Sub(DstSrcSrc),
+	Mul(DstSrcSrc),
+	Mulh(DstSrcSrc),
+	Mulhu(DstSrcSrc),
 	Div(DstSrcSrc),
 	Divu(DstSrcSrc),
 	Mod(DstSrcSrc),
@@ -91,6 +196,10 @@ pub enum Op {
 	Xor(DstSrcSrc),
 
 	Set(Cond, DstSrcSrc),
+	CMov(Cond, DstSrcSrc),
+	Ext(Ext, DstSrc),
+	Clz(DstSrc),
+	Clo(DstSrc),
 
 	Lb(DstSrcSrc),
 	Lbs(DstSrcSrc),
@@ -100,11 +209,21 @@ pub enum Op {
 	Sh(SrcSrcSrc),
 	Sw(SrcSrcSrc),
 	Ld(DstSrc),
+	// Memory accesses before the fence complete before any after it
+	Fence,
 
 	Call(Src),
 	B(Cond, SrcSrcTarget),
+	// A branch with a delay slot.  The condition's sources and the target are
+	// all read first (as at branch issue), then the slot executes whether or
+	// not the branch is taken, and only then is the pc redirected, so a slot
+	// that overwrites a source doesn't change the branch's outcome.
+	Branch { cond: Cond, src: [Src; 2], target: Src, delay_slot: Box<Op> },
 	Exc,
 	J(Src),
+	// Raises the Instr's exc if the sources compare as cond says, before
+	// anything after it in the step executes
+	Trap(Cond, SrcSrc),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -114,78 +233,1952 @@ pub enum Pred {
 	NotPred(R),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Instr {
 	pub op: Op,
 	pub pred: Pred,
 	pub exc: u8,
+	// Bytes of guest code covered.  A branch fused with its delay slot covers
+	// both instructions; an Instr of size 0 executes as part of the next one.
 	pub size: u8,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeOutcome {
+	Decoded(Vec<Instr>),
+
+	// The buffer stopped short of a whole instruction; holds the total number of
+	// bytes from the decode base needed before decoding can be retried
+	NeedMoreBytes(usize),
+}
+
+// Coarse groupings of ops for instrumentation
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OpClass {
+	Nop,
+	Arithmetic,
+	Logical,
+	Move,
+	Load,
+	Store,
+	Fence,
+	Branch,
+	Exception,
+}
+
+// A delay-slot branch is a Branch; the op in its slot has a class of its own.
+pub fn op_class(op: &Op) -> OpClass {
+	match *op {
+		Op::Nop => OpClass::Nop,
+
+		Op::Add(_)  | Op::Sub(_)  | Op::Mul(_)  | Op::Mulh(_) | Op::Mulhu(_) |
+		Op::Div(_)  | Op::Divu(_) | Op::Mod(_)  | Op::Modu(_) |
+		Op::Set(..) | Op::Ext(..) | Op::Clz(_)  | Op::Clo(_)  => OpClass::Arithmetic,
+
+		Op::And(_) | Op::Or(_) | Op::Nor(_) | Op::Sll(_) | Op::Sra(_) | Op::Srl(_) | Op::Xor(_) => OpClass::Logical,
+
+		Op::Ld(_) | Op::CMov(..) => OpClass::Move,
+
+		Op::Lb(_) | Op::Lbs(_) | Op::Lh(_) | Op::Lw(_) => OpClass::Load,
+		Op::Sb(_) | Op::Sh(_) | Op::Sw(_)              => OpClass::Store,
+		Op::Fence                                      => OpClass::Fence,
+
+		Op::Call(_) | Op::B(..) | Op::Branch { .. } | Op::J(_) => OpClass::Branch,
+
+		Op::Exc | Op::Trap(..) => OpClass::Exception,
+	}
+}
+
+// The registers op writes, including those written by a branch's delay slot
+pub fn op_defs(op: &Op, translator: &Translator) -> Vec<R> {
+	match *op {
+		Op::Add(args)  | Op::Sub(args)  | Op::Mul(args)  | Op::Mulh(args) | Op::Mulhu(args) |
+		Op::Div(args)  | Op::Divu(args) | Op::Mod(args)  | Op::Modu(args) |
+		Op::And(args)  | Op::Or(args)   | Op::Nor(args)  | Op::Sll(args)  | Op::Sra(args)   | Op::Srl(args) | Op::Xor(args) |
+		Op::Lb(args)   | Op::Lbs(args)  | Op::Lh(args)   | Op::Lw(args)   |
+		Op::Set(_, args) | Op::CMov(_, args) => vec!(args.dst),
+
+		Op::Ext(_, args) | Op::Clz(args) | Op::Clo(args) | Op::Ld(args) => vec!(args.dst),
+
+		Op::Call(_) => vec!(translator.link_reg()),
+
+		Op::Branch { ref delay_slot, .. } => op_defs(delay_slot, translator),
+
+		Op::Nop | Op::Sb(_) | Op::Sh(_) | Op::Sw(_) | Op::Fence | Op::B(..) | Op::Exc | Op::J(_) | Op::Trap(..) => Vec::new(),
+	}
+}
+
+// The registers op reads, including those read by a branch's delay slot.  A
+// CMov reads its destination too, as that's what's left there when it doesn't
+// move.
+pub fn op_uses(op: &Op) -> Vec<R> {
+	let srcs = match *op {
+		Op::Add(args)  | Op::Sub(args)  | Op::Mul(args)  | Op::Mulh(args) | Op::Mulhu(args) |
+		Op::Div(args)  | Op::Divu(args) | Op::Mod(args)  | Op::Modu(args) |
+		Op::And(args)  | Op::Or(args)   | Op::Nor(args)  | Op::Sll(args)  | Op::Sra(args)   | Op::Srl(args) | Op::Xor(args) |
+		Op::Lb(args)   | Op::Lbs(args)  | Op::Lh(args)   | Op::Lw(args)   |
+		Op::Set(_, args) => args.src.to_vec(),
+
+		Op::CMov(_, args) => vec!(args.src[0], args.src[1], Src::Reg(args.dst)),
+
+		Op::Ext(_, args) | Op::Clz(args) | Op::Clo(args) | Op::Ld(args) => vec!(args.src),
+
+		Op::Sb(args) | Op::Sh(args) | Op::Sw(args) => args.src.to_vec(),
+
+		Op::Call(target) | Op::J(target) => vec!(target),
+
+		Op::B(_, args) => vec!(args.src[0], args.src[1], args.target),
+
+		Op::Trap(_, args) => args.src.to_vec(),
+
+		Op::Branch { src, target, ref delay_slot, .. } => {
+			let mut uses = op_uses(delay_slot);
+
+			uses.extend(src_regs(&[src[0], src[1], target]));
+
+			return uses;
+		},
+
+		Op::Nop | Op::Fence | Op::Exc => Vec::new(),
+	};
+
+	src_regs(&srcs)
+}
+
+fn src_regs(srcs: &[Src]) -> Vec<R> {
+	srcs.iter().filter_map(|src| match *src {
+		Src::Reg(reg) => Some(reg),
+		_             => None,
+	}).collect()
+}
+
+fn instr_uses(instr: &Instr) -> Vec<R> {
+	let mut uses = op_uses(&instr.op);
+
+	match instr.pred {
+		Pred::Pred(reg) | Pred::NotPred(reg) => uses.push(reg),
+		Pred::None                           => {},
+	}
+
+	uses
+}
+
 pub fn is_end_of_block(op: &Op) -> bool {
 	match *op {
-		Op::Call(_) => true,
-		Op::B(_, _) => true,
-		Op::Exc     => true,
-		Op::J(_)    => true,
+		Op::Call(_)           => true,
+		Op::B(_, _)           => true,
+		Op::Branch { .. }     => true,
+		Op::Exc               => true,
+		Op::J(_)              => true,
+		Op::Trap(..)          => true,
 
 		_ => false,
 	}
 }
 
-fn interpret_op_list(instrs: &Vec<Instr>, regs: &mut RegisterFile) -> Result<(), Error> {
-	for ref instr in instrs.iter() {
-		match instr.op {
+// 64-bit sources, registers and immediates alike, give their low 32 bits
+fn read_src_u32<G: RegFile>(regs: &G, src: Src) -> Result<u32, Error> {
+	match src {
+		Src::Reg(R::B(_)) | Src::Reg(R::H(_)) | Src::Reg(R::W(_)) | Src::Reg(R::X(_)) | Src::Reg(R::Zero) |
+		Src::ImmU8(_)     | Src::ImmU16(_)    | Src::ImmU32(_)    | Src::ImmU64(_)    |
+		Src::ImmI8(_)     | Src::ImmI16(_)    | Src::ImmI32(_)    | Src::ImmI64(_)    => Ok(regs.resolve_src(src) as u32),
 
-			//or_w_w_immu16
-			Op::Or(DstSrcSrc { dst: R::W(dst_reg), src: [Src::Reg(R::W(src_reg)), Src::ImmU16(imm)]}) => {
-				let result = regs.read_u32(src_reg) | (imm as u32);
-				regs.write_u32(dst_reg, result);
-			},
+		_ => Err(Error::Unimplemented(format!("Unsupported iisa 32-bit source ({:?})", src))),
+	}
+}
 
-			_ => { return Err(Error::Unimplemented(format!("Unknown iisa instruction ({:?}) @ {:#x}", instr, regs.pc))); },
-		}
+// Takes the same sources as read_src_u32, at their full width
+fn read_src_u64<G: RegFile>(regs: &G, src: Src) -> Result<u64, Error> {
+	read_src_u32(regs, src).and(Ok(regs.resolve_src(src)))
+}
 
-		regs.pc += instr.size as u64;
+// Writing a destination narrower than the result truncates it and leaves the
+// rest of the enclosing register alone, while a destination of the result's
+// own width replaces the whole register.  A 64-bit destination gets the
+// result sign extended from bit 31, which is how MIPS64 holds the results of
+// its 32-bit ops.
+fn write_dst_u32<G: RegFile>(regs: &mut G, dst: R, value: u32) -> Result<(), Error> {
+	match dst {
+		R::B(_) | R::H(_) | R::W(_) | R::Discard => regs.store_dst(dst, value as u64),
+		R::X(_)                                  => regs.store_dst(dst, value as i32 as i64 as u64),
+
+		_ => { return Err(Error::Unimplemented(format!("Unsupported iisa 32-bit destination ({:?})", dst))); },
 	}
+
 	Ok(())
 }
 
-pub struct RegisterFile {
-	bytes: [u8;4096],
+// A 64-bit result only fits a 64-bit destination
+fn write_dst_u64<G: RegFile>(regs: &mut G, dst: R, value: u64) -> Result<(), Error> {
+	match dst {
+		R::X(_) | R::Discard => regs.store_dst(dst, value),
+
+		_ => { return Err(Error::Unimplemented(format!("Unsupported iisa 64-bit destination ({:?})", dst))); },
+	}
+
+	Ok(())
+}
+
+fn cond_holds_u32(cond: Cond, a: u32, b: u32) -> bool {
+	match cond {
+		Cond::Ne => a != b,
+		Cond::Eq => a == b,
+		Cond::Ge => (a as i32) >= (b as i32),
+		Cond::Gt => (a as i32) >  (b as i32),
+		Cond::Le => (a as i32) <= (b as i32),
+		Cond::Lt => (a as i32) <  (b as i32),
+	}
+}
+
+fn data_phys_addr<G: RegFile>(regs: &G, translator: &Translator, addr: u64) -> Result<u64, Error> {
+	match translator.virtual_to_phys(regs, addr) {
+		Some(phys) => Ok(phys),
+		None       => Err(Error::DataTranslationFault(addr)),
+	}
+}
+
+fn effective_addr_u32<G: RegFile>(regs: &G, a: Src, b: Src) -> Result<u64, Error> {
+	Ok(try!(read_src_u32(regs, a)).wrapping_add(try!(read_src_u32(regs, b))) as u64)
+}
+
+// Loads width bytes at addr in the translator's byte order, zero extended
+fn load<G: RegFile>(regs: &G, bus: &mut BusMatrix, stores: &mut StoreBuffer, translator: &Translator, addr: u64, width: u8) -> Result<u32, Error> {
+	let phys = try!(data_phys_addr(regs, translator, addr));
+	let big_endian = translator.big_endian();
+
+	try!(stores.drain_overlapping(bus, big_endian, phys, width));
+
+	match width {
+		1 => Ok(try!(bus.read_u8(phys).into_result(addr)) as u32),
+		2 => {
+			let value = try!(bus.read_u16(phys).into_result(addr));
+			Ok((if big_endian { u16::from_be(value) } else { u16::from_le(value) }) as u32)
+		},
+		_ => {
+			let value = try!(bus.read_u32(phys).into_result(addr));
+			Ok(if big_endian { u32::from_be(value) } else { u32::from_le(value) })
+		},
+	}
+}
+
+fn store<G: RegFile>(regs: &G, bus: &mut BusMatrix, stores: &mut StoreBuffer, translator: &Translator, addr: u64, width: u8, value: u64) -> Result<(), Error> {
+	let phys = try!(data_phys_addr(regs, translator, addr));
+
+	stores.push(bus, translator.big_endian(), PendingStore {
+		pc:    regs.pc(),
+		addr:  addr,
+		phys:  phys,
+		width: width,
+		value: value,
+	})
+}
+
+// A store that's been executed, but hasn't reached the bus yet.  pc is the
+// store's own, for reporting a fault once the CPU has moved on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PendingStore {
 	pub pc: u64,
+	pub addr: u64,
+	pub phys: u64,
+	pub width: u8,
+	pub value: u64,
 }
 
-impl RegisterFile {
-	fn new() -> RegisterFile {
-		RegisterFile {
-			bytes: [0; 4096],
-			pc:    0,
+impl PendingStore {
+	fn overlaps(&self, phys: u64, width: u8) -> bool {
+		(self.phys < phys + (width as u64)) && (phys < self.phys + (self.width as u64))
+	}
+
+	fn write_to(&self, bus: &mut BusMatrix, big_endian: bool) -> Result<(), Error> {
+		let result = match self.width {
+			1 => bus.write_u8(self.phys, self.value as u8),
+			2 => bus.write_u16(self.phys, if big_endian { (self.value as u16).to_be() } else { (self.value as u16).to_le() }),
+			4 => bus.write_u32(self.phys, if big_endian { (self.value as u32).to_be() } else { (self.value as u32).to_le() }),
+			_ => bus.write_u64(self.phys, if big_endian { self.value.to_be() } else { self.value.to_le() }),
+		};
+
+		result.into_result(self.addr, self.width)
+	}
+
+	fn write_back(&self, bus: &mut BusMatrix, big_endian: bool) -> Result<(), Error> {
+		self.write_to(bus, big_endian).map_err(|fault| Error::StoreFault{pc: self.pc, fault: Box::new(fault)})
+	}
+}
+
+// Holds up to capacity stores, oldest first, until a fence drains them or a
+// new store needs the room.  A load touching a buffered store drains the
+// buffer first, so a CPU always sees its own stores.  With a capacity of zero
+// (the default) stores go straight to the bus.
+#[derive(Default)]
+pub struct StoreBuffer {
+	capacity: usize,
+	pending: VecDeque<PendingStore>,
+}
+
+impl StoreBuffer {
+	pub fn new(capacity: usize) -> StoreBuffer {
+		StoreBuffer {
+			capacity: capacity,
+			pending:  VecDeque::new(),
 		}
 	}
 
-	pub fn write_u32(&mut self, reg: u16, value: u32) {
-		let reg_off: usize = (reg as usize) * 4;
-		self.bytes[reg_off + 0] = (value >>  0) as u8;
-		self.bytes[reg_off + 1] = (value >>  8) as u8;
-		self.bytes[reg_off + 2] = (value >> 16) as u8;
-		self.bytes[reg_off + 3] = (value >> 24) as u8;
+	pub fn pending(&self) -> Vec<PendingStore> {
+		self.pending.iter().cloned().collect()
 	}
 
-	pub fn read_u32(&self, reg: u16) -> u32 {
-		let reg_off = (reg as usize) * 4;
+	pub fn push(&mut self, bus: &mut BusMatrix, big_endian: bool, store: PendingStore) -> Result<(), Error> {
+		if self.capacity == 0 {
+			return store.write_to(bus, big_endian);
+		}
 
-		((self.bytes[reg_off + 0] as u32) <<  0) |
-		((self.bytes[reg_off + 1] as u32) <<  8) |
-		((self.bytes[reg_off + 2] as u32) << 16) |
-		((self.bytes[reg_off + 3] as u32) << 24)
+		while self.pending.len() >= self.capacity {
+			try!(self.write_back_oldest(bus, big_endian));
+		}
+
+		self.pending.push_back(store);
+
+		Ok(())
+	}
+
+	// A store that faults stays at the head of the buffer, along with every
+	// store queued behind it
+	pub fn drain(&mut self, bus: &mut BusMatrix, big_endian: bool) -> Result<(), Error> {
+		while !self.pending.is_empty() {
+			try!(self.write_back_oldest(bus, big_endian));
+		}
+
+		Ok(())
+	}
+
+	fn write_back_oldest(&mut self, bus: &mut BusMatrix, big_endian: bool) -> Result<(), Error> {
+		if let Some(oldest) = self.pending.pop_front() {
+			if let Err(err) = oldest.write_back(bus, big_endian) {
+				self.pending.push_front(oldest);
+				return Err(err);
+			}
+		}
+
+		Ok(())
+	}
+
+	// Drops every pending store without it reaching the bus
+	pub fn clear(&mut self) {
+		self.pending.clear();
+	}
+
+	fn drain_overlapping(&mut self, bus: &mut BusMatrix, big_endian: bool, phys: u64, width: u8) -> Result<(), Error> {
+		if self.pending.iter().any(|store| store.overlaps(phys, width)) {
+			self.drain(bus, big_endian)
+		} else {
+			Ok(())
+		}
 	}
 }
 
-pub trait Translator {
-	fn decode(&self, base: u64, buffer: &[u8]) -> Result<Vec<Instr>, Error>;
-	fn virtual_to_phys(&self, registers: &RegisterFile, addr: u64) -> Option<u64>;
-	fn set_reg(&mut self, registers: &mut RegisterFile, reg: CpuReg, value: u64) -> Result<(), Error>;
-	fn get_reg(&self, registers: &RegisterFile, reg: CpuReg) -> Result<u64, Error>;
+// All interpreter arithmetic is spelled out with wrapping_*/saturating_* so that
+// guest-visible results don't depend on whether overflow checks are compiled
+// in.  Adds, subtracts, multiplies and negations wrap modulo 2^width.  Shift
+// amounts are masked to the operand width, as on the hardware we model.
+fn shl_u32(value: u32, amount: u32) -> u32 {
+	value.wrapping_shl(amount)
+}
+
+fn shr_u32(value: u32, amount: u32) -> u32 {
+	value.wrapping_shr(amount)
+}
+
+fn sar_u32(value: u32, amount: u32) -> u32 {
+	(value as i32).wrapping_shr(amount) as u32
+}
+
+fn shl_u64(value: u64, amount: u32) -> u64 {
+	value.wrapping_shl(amount)
+}
+
+fn shr_u64(value: u64, amount: u32) -> u64 {
+	value.wrapping_shr(amount)
+}
+
+fn sar_u64(value: u64, amount: u32) -> u64 {
+	(value as i64).wrapping_shr(amount) as u64
+}
+
+fn ext_u32(value: u32, signed: bool, width: u8) -> u32 {
+	// Extending from a width of zero yields zero, and widths at or beyond the
+	// register size leave the value untouched.
+	let shift = 32u32.saturating_sub(width as u32);
+
+	if shift >= 32 {
+		return 0;
+	}
+
+	if signed {
+		sar_u32(shl_u32(value, shift), shift)
+	} else {
+		shr_u32(shl_u32(value, shift), shift)
+	}
+}
+
+// Only the low bits of a and b take part, with the top one of those as the
+// sign
+fn cond_holds_at_width(cond: Cond, a: u64, b: u64, bits: u32) -> bool {
+	let a = (a << (64 - bits)) as i64;
+	let b = (b << (64 - bits)) as i64;
+
+	match cond {
+		Cond::Ne => a != b,
+		Cond::Eq => a == b,
+		Cond::Ge => a >= b,
+		Cond::Gt => a >  b,
+		Cond::Le => a <= b,
+		Cond::Lt => a <  b,
+	}
+}
+
+// Targets are either absolute addresses or computed, in which case the source
+// holds the address to transfer to
+fn branch_target<G: RegFile>(regs: &G, target: Src) -> Result<u64, Error> {
+	match target {
+		Src::Addr(addr) => Ok(addr),
+		_               => Ok(try!(read_src_u32(regs, target)) as u64),
+	}
+}
+
+// Returns the new pc if the op transferred control, or None to fall through to
+// next_pc
+fn interpret_op<G: RegFile>(op: &Op, next_pc: u64, regs: &mut G, bus: &mut BusMatrix, stores: &mut StoreBuffer, translator: &Translator) -> Result<Option<u64>, Error> {
+	match *op {
+		Op::Nop => {},
+
+		//or_w_w_immu16
+		Op::Or(DstSrcSrc { dst: R::W(dst_reg), src: [Src::Reg(R::W(src_reg)), Src::ImmU16(imm)]}) => {
+			let result = (regs.read_reg(R::W(src_reg)) as u32) | (imm as u32);
+			regs.write_reg(R::W(dst_reg), result as u64);
+		},
+
+		// dst = (src[0] cond src[1]) ? 1 : 0, compared at the destination's width
+		Op::Set(cond, DstSrcSrc { dst, src: [a, b] }) => {
+			let bits = match dst {
+				R::B(_) => 8,
+				R::H(_) => 16,
+				R::X(_) => 64,
+				_       => 32,
+			};
+
+			let holds = cond_holds_at_width(cond, try!(read_src_u64(regs, a)), try!(read_src_u64(regs, b)), bits);
+			try!(write_dst_u32(regs, dst, holds as u32));
+		},
+
+		// dst = src[0] if (src[1] cond 0)
+		Op::CMov(cond, DstSrcSrc { dst, src: [value, test] }) => {
+			if cond_holds_u32(cond, try!(read_src_u32(regs, test)), 0) {
+				let value = try!(read_src_u32(regs, value));
+				try!(write_dst_u32(regs, dst, value));
+			}
+		},
+
+		Op::Ext(Ext { signed, width }, DstSrc { dst, src }) => {
+			let value = try!(read_src_u32(regs, src));
+			try!(write_dst_u32(regs, dst, ext_u32(value, signed, width)));
+		},
+
+		// A 64-bit destination takes the source at 64 bits, immediates extended
+		// by their kind
+		Op::Ld(DstSrc { dst: dst @ R::X(_), src }) => {
+			let value = try!(read_src_u64(regs, src));
+			try!(write_dst_u64(regs, dst, value));
+		},
+
+		Op::Ld(DstSrc { dst, src }) => {
+			let value = try!(read_src_u32(regs, src));
+			try!(write_dst_u32(regs, dst, value));
+		},
+
+		Op::Clz(DstSrc { dst, src }) => {
+			let value = try!(read_src_u32(regs, src));
+			try!(write_dst_u32(regs, dst, value.leading_zeros()));
+		},
+
+		Op::Clo(DstSrc { dst, src }) => {
+			let value = try!(read_src_u32(regs, src));
+			try!(write_dst_u32(regs, dst, (!value).leading_zeros()));
+		},
+
+		Op::Lb(DstSrcSrc { dst, src: [a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(load(regs, bus, stores, translator, addr, 1));
+			try!(write_dst_u32(regs, dst, value));
+		},
+
+		Op::Lbs(DstSrcSrc { dst, src: [a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(load(regs, bus, stores, translator, addr, 1));
+			try!(write_dst_u32(regs, dst, value as u8 as i8 as i32 as u32));
+		},
+
+		// Like Lb, Lh zero extends
+		Op::Lh(DstSrcSrc { dst, src: [a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(load(regs, bus, stores, translator, addr, 2));
+			try!(write_dst_u32(regs, dst, value));
+		},
+
+		Op::Lw(DstSrcSrc { dst, src: [a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(load(regs, bus, stores, translator, addr, 4));
+			try!(write_dst_u32(regs, dst, value));
+		},
+
+		// Stores take [value, offset, base] and keep only as much of the value as
+		// the access is wide
+		Op::Sb(SrcSrcSrc { src: [value, a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(read_src_u32(regs, value));
+			try!(store(regs, bus, stores, translator, addr, 1, value as u8 as u64));
+		},
+
+		Op::Sh(SrcSrcSrc { src: [value, a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(read_src_u32(regs, value));
+			try!(store(regs, bus, stores, translator, addr, 2, value as u16 as u64));
+		},
+
+		Op::Sw(SrcSrcSrc { src: [value, a, b] }) => {
+			let addr = try!(effective_addr_u32(regs, a, b));
+			let value = try!(read_src_u32(regs, value));
+			try!(store(regs, bus, stores, translator, addr, 4, value as u64));
+		},
+
+		Op::Fence => {
+			try!(stores.drain(bus, translator.big_endian()));
+		},
+
+		Op::Add(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = try!(read_src_u32(regs, a)).wrapping_add(try!(read_src_u32(regs, b)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Sub(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = try!(read_src_u32(regs, a)).wrapping_sub(try!(read_src_u32(regs, b)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::And(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = try!(read_src_u32(regs, a)) & try!(read_src_u32(regs, b));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Or(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = try!(read_src_u32(regs, a)) | try!(read_src_u32(regs, b));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Xor(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = try!(read_src_u32(regs, a)) ^ try!(read_src_u32(regs, b));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Nor(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = !(try!(read_src_u32(regs, a)) | try!(read_src_u32(regs, b)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		// dst = src[0] shifted by src[1], at 64 bits into a 64-bit destination
+		Op::Sll(DstSrcSrc { dst: dst @ R::X(_), src: [value, amount] }) => {
+			let result = shl_u64(try!(read_src_u64(regs, value)), try!(read_src_u32(regs, amount)));
+			try!(write_dst_u64(regs, dst, result));
+		},
+
+		Op::Srl(DstSrcSrc { dst: dst @ R::X(_), src: [value, amount] }) => {
+			let result = shr_u64(try!(read_src_u64(regs, value)), try!(read_src_u32(regs, amount)));
+			try!(write_dst_u64(regs, dst, result));
+		},
+
+		Op::Sra(DstSrcSrc { dst: dst @ R::X(_), src: [value, amount] }) => {
+			let result = sar_u64(try!(read_src_u64(regs, value)), try!(read_src_u32(regs, amount)));
+			try!(write_dst_u64(regs, dst, result));
+		},
+
+		Op::Sll(DstSrcSrc { dst, src: [value, amount] }) => {
+			let result = shl_u32(try!(read_src_u32(regs, value)), try!(read_src_u32(regs, amount)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Srl(DstSrcSrc { dst, src: [value, amount] }) => {
+			let result = shr_u32(try!(read_src_u32(regs, value)), try!(read_src_u32(regs, amount)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Sra(DstSrcSrc { dst, src: [value, amount] }) => {
+			let result = sar_u32(try!(read_src_u32(regs, value)), try!(read_src_u32(regs, amount)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Mul(DstSrcSrc { dst, src: [a, b] }) => {
+			let result = try!(read_src_u32(regs, a)).wrapping_mul(try!(read_src_u32(regs, b)));
+			try!(write_dst_u32(regs, dst, result));
+		},
+
+		Op::Mulh(DstSrcSrc { dst, src: [a, b] }) => {
+			let a = try!(read_src_u32(regs, a)) as i32 as i64;
+			let b = try!(read_src_u32(regs, b)) as i32 as i64;
+			try!(write_dst_u32(regs, dst, (a.wrapping_mul(b) >> 32) as u32));
+		},
+
+		Op::Mulhu(DstSrcSrc { dst, src: [a, b] }) => {
+			let a = try!(read_src_u32(regs, a)) as u64;
+			let b = try!(read_src_u32(regs, b)) as u64;
+			try!(write_dst_u32(regs, dst, (a.wrapping_mul(b) >> 32) as u32));
+		},
+
+		// Quotients truncate toward zero, and INT_MIN / -1 wraps back to INT_MIN
+		// (with a remainder of 0).  MIPS leaves the result of dividing by zero
+		// unpredictable, so it's modelled as not writing the destination at all.
+		// A 64-bit destination divides at 64 bits.
+		Op::Div(DstSrcSrc { dst: dst @ R::X(_), src: [a, b] }) => {
+			let a = try!(read_src_u64(regs, a)) as i64;
+			let b = try!(read_src_u64(regs, b)) as i64;
+
+			if b != 0 {
+				try!(write_dst_u64(regs, dst, a.wrapping_div(b) as u64));
+			}
+		},
+
+		Op::Divu(DstSrcSrc { dst: dst @ R::X(_), src: [a, b] }) => {
+			let a = try!(read_src_u64(regs, a));
+			let b = try!(read_src_u64(regs, b));
+
+			if b != 0 {
+				try!(write_dst_u64(regs, dst, a / b));
+			}
+		},
+
+		Op::Mod(DstSrcSrc { dst: dst @ R::X(_), src: [a, b] }) => {
+			let a = try!(read_src_u64(regs, a)) as i64;
+			let b = try!(read_src_u64(regs, b)) as i64;
+
+			if b != 0 {
+				try!(write_dst_u64(regs, dst, a.wrapping_rem(b) as u64));
+			}
+		},
+
+		Op::Modu(DstSrcSrc { dst: dst @ R::X(_), src: [a, b] }) => {
+			let a = try!(read_src_u64(regs, a));
+			let b = try!(read_src_u64(regs, b));
+
+			if b != 0 {
+				try!(write_dst_u64(regs, dst, a % b));
+			}
+		},
+
+		Op::Div(DstSrcSrc { dst, src: [a, b] }) => {
+			let a = try!(read_src_u32(regs, a)) as i32;
+			let b = try!(read_src_u32(regs, b)) as i32;
+
+			if b != 0 {
+				try!(write_dst_u32(regs, dst, a.wrapping_div(b) as u32));
+			}
+		},
+
+		Op::Divu(DstSrcSrc { dst, src: [a, b] }) => {
+			let a = try!(read_src_u32(regs, a));
+			let b = try!(read_src_u32(regs, b));
+
+			if b != 0 {
+				try!(write_dst_u32(regs, dst, a / b));
+			}
+		},
+
+		Op::Mod(DstSrcSrc { dst, src: [a, b] }) => {
+			let a = try!(read_src_u32(regs, a)) as i32;
+			let b = try!(read_src_u32(regs, b)) as i32;
+
+			if b != 0 {
+				try!(write_dst_u32(regs, dst, a.wrapping_rem(b) as u32));
+			}
+		},
+
+		Op::Modu(DstSrcSrc { dst, src: [a, b] }) => {
+			let a = try!(read_src_u32(regs, a));
+			let b = try!(read_src_u32(regs, b));
+
+			if b != 0 {
+				try!(write_dst_u32(regs, dst, a % b));
+			}
+		},
+
+		// interpret_op_list raises traps itself, so this is only reached from a
+		// delay slot, where there's no Instr to hold the exception code
+		Op::Trap(cond, SrcSrc { src: [a, b] }) => {
+			if cond_holds_u32(cond, try!(read_src_u32(regs, a)), try!(read_src_u32(regs, b))) {
+				return Err(Error::Unimplemented(format!("Trap taken in a delay slot @ {:#x}", regs.pc())));
+			}
+		},
+
+		Op::B(cond, SrcSrcTarget { src: [a, b], target }) => {
+			if cond_holds_u32(cond, try!(read_src_u32(regs, a)), try!(read_src_u32(regs, b))) {
+				return Ok(Some(try!(branch_target(regs, target))));
+			}
+		},
+
+		Op::Branch { cond, src: [a, b], target, ref delay_slot } => {
+			let taken = cond_holds_u32(cond, try!(read_src_u32(regs, a)), try!(read_src_u32(regs, b)));
+			let target = try!(branch_target(regs, target));
+
+			if try!(interpret_op(delay_slot, next_pc, regs, bus, stores, translator)).is_some() {
+				return Err(Error::Unimplemented(format!("Control transfer in iisa delay slot ({:?})", delay_slot)));
+			}
+
+			if taken {
+				return Ok(Some(target));
+			}
+		},
+
+		Op::J(target) => {
+			return Ok(Some(try!(branch_target(regs, target))));
+		},
+
+		// The target is read before the link is written, in case they're the
+		// same register
+		Op::Call(target) => {
+			let target = try!(branch_target(regs, target));
+			try!(write_dst_u32(regs, translator.link_reg(), next_pc as u32));
+
+			return Ok(Some(target));
+		},
+
+		_ => { return Err(Error::Unimplemented(format!("Unknown iisa op ({:?}) @ {:#x}", op, regs.pc()))); },
+	}
+
+	Ok(None)
+}
+
+// P(n) and Pred(n) name the same predicate registers
+fn pred_holds<G: RegFile>(regs: &G, pred: Pred) -> Result<bool, Error> {
+	match pred {
+		Pred::None => Ok(true),
+
+		Pred::Pred(reg @ R::P(_))    | Pred::Pred(reg @ R::Pred(_))    => Ok(regs.read_reg(reg) != 0),
+		Pred::NotPred(reg @ R::P(_)) | Pred::NotPred(reg @ R::Pred(_)) => Ok(regs.read_reg(reg) == 0),
+
+		_ => Err(Error::Unimplemented(format!("Unsupported iisa predicate ({:?})", pred))),
+	}
+}
+
+// Instrs can come from outside (load_iisa, decode rewriters, the wire
+// format), so one naming a register the file doesn't have is an error rather
+// than something to index with
+fn check_regs_held<G: RegFile>(regs: &G, instr: &Instr, translator: &Translator) -> Result<(), Error> {
+	let mut named = instr_uses(instr);
+
+	named.extend(op_defs(&instr.op, translator));
+
+	match named.into_iter().find(|reg| !regs.holds(*reg)) {
+		Some(reg) => Err(Error::InvalidRegister(reg)),
+		None      => Ok(()),
+	}
 }
 
+// An instr whose predicate doesn't hold is skipped, though the pc still moves
+// past it.  Returns the exc of a trap that was taken, in which case the pc is
+// left on the trapping instruction and nothing after it has executed.  Raising
+// it is up to the caller.
+pub fn interpret_op_list<G: RegFile>(instrs: &Vec<Instr>, regs: &mut G, bus: &mut BusMatrix, stores: &mut StoreBuffer, translator: &Translator) -> Result<Option<u8>, Error> {
+	for instr in instrs.iter() {
+		try!(check_regs_held(regs, instr, translator));
+
+		if !try!(pred_holds(regs, instr.pred)) {
+			let next_pc = regs.pc().wrapping_add(instr.size as u64);
+
+			regs.set_pc(next_pc);
+
+			continue;
+		}
+
+		if let Op::Trap(cond, SrcSrc { src: [a, b] }) = instr.op {
+			if cond_holds_u32(cond, try!(read_src_u32(regs, a)), try!(read_src_u32(regs, b))) {
+				return Ok(Some(instr.exc));
+			}
+		}
+
+		let next_pc = regs.pc().wrapping_add(instr.size as u64);
+
+		let pc = match try!(interpret_op(&instr.op, next_pc, regs, bus, stores, translator)) {
+			Some(target) => target,
+			None         => next_pc,
+		};
+
+		regs.set_pc(pc);
+	}
+
+	Ok(None)
+}
+
+// 4096 bytes, for register files built without an arch to size them
+pub const DEFAULT_REGISTER_SLOTS: u16 = 512;
+
+// P(0) to P(63), as bits of a u64
+pub const PRED_REGS: u32 = 64;
+
+// Registers of each width index into the same little endian backing store at
+// multiples of their size, so B(4 * n) and H(2 * n) alias the low bytes of
+// W(n); the narrow accessors only ever touch their own bytes.
+//
+// A file is sized in 64-bit slots, so one of n slots holds X(0..n), W(0..2n),
+// H(0..4n) and B(0..8n).  Touching a register past that is a bug in whoever
+// picked the register, and panics rather than landing on some other register.
+#[derive(Clone)]
+pub struct RegisterFile {
+	bytes: Vec<u8>,
+	preds: u64,
+	pub pc: u64,
+}
+
+impl Default for RegisterFile {
+	fn default() -> RegisterFile {
+		RegisterFile::new()
+	}
+}
+
+impl RegisterFile {
+	pub fn new() -> RegisterFile {
+		RegisterFile::with_slots(DEFAULT_REGISTER_SLOTS)
+	}
+
+	pub fn with_slots(slots: u16) -> RegisterFile {
+		RegisterFile {
+			bytes: vec!(0; (slots as usize) * 8),
+			preds: 0,
+			pc:    0,
+		}
+	}
+
+	pub fn slots(&self) -> u16 {
+		(self.bytes.len() / 8) as u16
+	}
+
+	// Where a register of size bytes starts in the backing store, if it fits
+	fn offset(&self, reg: u16, size: usize) -> Option<usize> {
+		let reg_off = (reg as usize) * size;
+
+		if reg_off + size > self.bytes.len() {
+			None
+		} else {
+			Some(reg_off)
+		}
+	}
+
+	// For the accessors below, where the host picked the register; the
+	// interpreter checks guest supplied ones up front (see holds)
+	fn offset_or_panic(&self, reg: u16, size: usize) -> usize {
+		match self.offset(reg, size) {
+			Some(reg_off) => reg_off,
+			None          => panic!("{} byte register {} is past the end of a {} slot register file", size, reg, self.slots()),
+		}
+	}
+
+	pub fn clear(&mut self) {
+		for byte in self.bytes.iter_mut() {
+			*byte = 0;
+		}
+
+		self.preds = 0;
+		self.pc = 0;
+	}
+
+	// Predicate registers are single bits, with P(n) held in bit n
+	pub fn read_pred(&self, reg: u8) -> Result<bool, Error> {
+		if (reg as u32) >= PRED_REGS {
+			return Err(Error::InvalidRegister(R::P(reg)));
+		}
+
+		Ok((self.preds >> reg) & 1 != 0)
+	}
+
+	pub fn write_pred(&mut self, reg: u8, value: bool) -> Result<(), Error> {
+		if (reg as u32) >= PRED_REGS {
+			return Err(Error::InvalidRegister(R::P(reg)));
+		}
+
+		if value {
+			self.preds |= 1 << reg;
+		} else {
+			self.preds &= !(1 << reg);
+		}
+
+		Ok(())
+	}
+
+	pub fn preds(&self) -> u64 {
+		self.preds
+	}
+
+	pub fn set_preds(&mut self, preds: u64) {
+		self.preds = preds;
+	}
+
+	pub fn write_u8(&mut self, reg: u16, value: u8) {
+		let reg_off = self.offset_or_panic(reg, 1);
+		self.bytes[reg_off] = value;
+	}
+
+	pub fn read_u8(&self, reg: u16) -> u8 {
+		self.bytes[self.offset_or_panic(reg, 1)]
+	}
+
+	pub fn write_u16(&mut self, reg: u16, value: u16) {
+		let reg_off = self.offset_or_panic(reg, 2);
+		self.bytes[reg_off + 0] = (value >>  0) as u8;
+		self.bytes[reg_off + 1] = (value >>  8) as u8;
+	}
+
+	pub fn read_u16(&self, reg: u16) -> u16 {
+		let reg_off = self.offset_or_panic(reg, 2);
+
+		((self.bytes[reg_off + 0] as u16) <<  0) |
+		((self.bytes[reg_off + 1] as u16) <<  8)
+	}
+
+	pub fn write_u32(&mut self, reg: u16, value: u32) {
+		let reg_off = self.offset_or_panic(reg, 4);
+		self.bytes[reg_off + 0] = (value >>  0) as u8;
+		self.bytes[reg_off + 1] = (value >>  8) as u8;
+		self.bytes[reg_off + 2] = (value >> 16) as u8;
+		self.bytes[reg_off + 3] = (value >> 24) as u8;
+	}
+
+	pub fn read_u32(&self, reg: u16) -> u32 {
+		let reg_off = self.offset_or_panic(reg, 4);
+
+		((self.bytes[reg_off + 0] as u32) <<  0) |
+		((self.bytes[reg_off + 1] as u32) <<  8) |
+		((self.bytes[reg_off + 2] as u32) << 16) |
+		((self.bytes[reg_off + 3] as u32) << 24)
+	}
+
+	pub fn write_u64(&mut self, reg: u16, value: u64) {
+		let reg_off = self.offset_or_panic(reg, 8);
+
+		for i in 0..8 {
+			self.bytes[reg_off + i] = (value >> (i * 8)) as u8;
+		}
+	}
+
+	pub fn read_u64(&self, reg: u16) -> u64 {
+		let reg_off = self.offset_or_panic(reg, 8);
+
+		(0..8).fold(0, |value, i| value | ((self.bytes[reg_off + i] as u64) << (i * 8)))
+	}
+}
+
+// What the interpreter needs of a register file, so that other backings (say,
+// one that logs every access) can run the same ops.  Values are zero extended
+// from the view's width, and writes only take the view's width of value.
+pub trait RegFile {
+	// Whether reg is backed by this file; the interpreter fails an instr that
+	// names one that isn't, rather than touching it
+	fn holds(&self, _: R) -> bool {
+		true
+	}
+
+	fn read_reg(&self, reg: R) -> u64;
+	fn write_reg(&mut self, reg: R, value: u64);
+	fn pc(&self) -> u64;
+	fn set_pc(&mut self, pc: u64);
+
+	// Unsigned immediates are zero extended and signed ones sign extended,
+	// registers are read at their view's width, and R::Zero reads as zero.
+	// Anything that isn't a plain value (like Src::Addr) also reads as zero.
+	fn resolve_src(&self, src: Src) -> u64 {
+		match src {
+			Src::Reg(R::Zero) => 0,
+			Src::Reg(reg)     => self.read_reg(reg),
+			Src::ImmU8(imm)   => imm as u64,
+			Src::ImmU16(imm)  => imm as u64,
+			Src::ImmU32(imm)  => imm as u64,
+			Src::ImmU64(imm)  => imm,
+			Src::ImmI8(imm)   => imm as i64 as u64,
+			Src::ImmI16(imm)  => imm as i64 as u64,
+			Src::ImmI32(imm)  => imm as i64 as u64,
+			Src::ImmI64(imm)  => imm as u64,
+			Src::Addr(_)      => 0,
+		}
+	}
+
+	// Writes dst's width of value, and nothing for R::Discard
+	fn store_dst(&mut self, dst: R, value: u64) {
+		match dst {
+			R::Discard => {},
+			_          => self.write_reg(dst, value),
+		}
+	}
+}
+
+// P(n) reads as 0 or 1 and takes the low bit of writes.  Views the interpreter
+// doesn't hand out read as zero, and writes to them are dropped
+impl RegFile for RegisterFile {
+	fn holds(&self, reg: R) -> bool {
+		match reg {
+			R::B(reg) => self.offset(reg, 1).is_some(),
+			R::H(reg) => self.offset(reg, 2).is_some(),
+			R::W(reg) => self.offset(reg, 4).is_some(),
+			R::X(reg) => self.offset(reg, 8).is_some(),
+
+			R::P(reg)    => (reg as u32) < PRED_REGS,
+			R::Pred(reg) => reg < (PRED_REGS as u64),
+
+			_ => true,
+		}
+	}
+
+	fn read_reg(&self, reg: R) -> u64 {
+		match reg {
+			R::P(reg) => self.read_pred(reg).unwrap_or(false) as u64,
+
+			R::Pred(reg) if reg < (PRED_REGS as u64) => self.read_pred(reg as u8).unwrap_or(false) as u64,
+
+			R::B(reg) => self.read_u8(reg) as u64,
+			R::H(reg) => self.read_u16(reg) as u64,
+			R::W(reg) => self.read_u32(reg) as u64,
+			R::X(reg) => self.read_u64(reg),
+			_         => 0,
+		}
+	}
+
+	fn write_reg(&mut self, reg: R, value: u64) {
+		match reg {
+			R::P(reg) => { let _ = self.write_pred(reg, value & 1 != 0); },
+
+			R::Pred(reg) if reg < (PRED_REGS as u64) => { let _ = self.write_pred(reg as u8, value & 1 != 0); },
+
+			R::B(reg) => self.write_u8(reg, value as u8),
+			R::H(reg) => self.write_u16(reg, value as u16),
+			R::W(reg) => self.write_u32(reg, value as u32),
+			R::X(reg) => self.write_u64(reg, value),
+			_         => {},
+		}
+	}
+
+	fn pc(&self) -> u64 {
+		self.pc
+	}
+
+	fn set_pc(&mut self, pc: u64) {
+		self.pc = pc;
+	}
+}
+
+pub trait Translator {
+	fn decode(&self, base: u64, buffer: &[u8]) -> Result<DecodeOutcome, Error>;
+	fn big_endian(&self) -> bool;
+	fn instruction_size_at(&self, base: u64, buffer: &[u8]) -> Result<u8, Error>;
+	fn reset_vector(&self) -> u64;
+	fn virtual_to_phys(&self, registers: &RegFile, addr: u64) -> Option<u64>;
+	fn set_reg(&mut self, registers: &mut RegisterFile, reg: CpuReg, value: u64) -> Result<(), Error>;
+	fn get_reg(&self, registers: &RegisterFile, reg: CpuReg) -> Result<u64, Error>;
+	fn arch_registers(&self) -> Vec<CpuReg>;
+	fn raise_exception(&self, registers: &mut RegisterFile, code: u8) -> Result<(), Error>;
+	fn reserved_instruction_code(&self) -> u8;
+	// Where Op::Call leaves the return address
+	fn link_reg(&self) -> R;
+	// The view reg is held in, or None if it can change outside of the ops that
+	// write it (like the pc, which changes every step)
+	fn reg_storage(&self, reg: &CpuReg) -> Option<R>;
+	fn op_cost(&self, op: &Op) -> u32;
+	fn advance_time(&self, registers: &mut RegisterFile, cycles: u32);
+	fn take_interrupt(&self, registers: &mut RegisterFile) -> Result<bool, Error>;
+	// How many 64-bit slots the CPU's RegisterFile needs to hold every register
+	// the arch uses
+	fn register_slots(&self) -> u16;
+	// Width of the addresses the CPU can put on the bus
+	fn address_bits(&self) -> u8;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use super::super::{CpuReg, Error, PROT_ALL};
+	use super::super::mem::BusMatrix;
+
+	struct TestTranslator;
+
+	impl Translator for TestTranslator {
+		fn decode(&self, _: u64, _: &[u8]) -> Result<DecodeOutcome, Error> {
+			Err(Error::Unimplemented(format!("TestTranslator doesn't decode")))
+		}
+
+		fn big_endian(&self) -> bool {
+			false
+		}
+
+		fn instruction_size_at(&self, _: u64, _: &[u8]) -> Result<u8, Error> {
+			Ok(4)
+		}
+
+		fn reset_vector(&self) -> u64 {
+			0
+		}
+
+		fn virtual_to_phys(&self, _: &RegFile, addr: u64) -> Option<u64> {
+			Some(addr)
+		}
+
+		fn set_reg(&mut self, _: &mut RegisterFile, reg: CpuReg, value: u64) -> Result<(), Error> {
+			Err(Error::SetRegUnknownReg(reg, value))
+		}
+
+		fn get_reg(&self, _: &RegisterFile, reg: CpuReg) -> Result<u64, Error> {
+			Err(Error::GetRegUnknownReg(reg))
+		}
+
+		fn arch_registers(&self) -> Vec<CpuReg> {
+			Vec::new()
+		}
+
+		fn raise_exception(&self, _: &mut RegisterFile, code: u8) -> Result<(), Error> {
+			Err(Error::Unimplemented(format!("TestTranslator doesn't raise exception {}", code)))
+		}
+
+		fn reserved_instruction_code(&self) -> u8 {
+			0
+		}
+
+		fn register_slots(&self) -> u16 {
+			DEFAULT_REGISTER_SLOTS
+		}
+
+		fn address_bits(&self) -> u8 {
+			64
+		}
+
+		fn link_reg(&self) -> R {
+			R::W(31)
+		}
+
+		fn reg_storage(&self, _: &CpuReg) -> Option<R> {
+			None
+		}
+
+		fn op_cost(&self, _: &Op) -> u32 {
+			1
+		}
+
+		fn advance_time(&self, _: &mut RegisterFile, _: u32) {
+		}
+
+		fn take_interrupt(&self, _: &mut RegisterFile) -> Result<bool, Error> {
+			Ok(false)
+		}
+	}
+
+	fn interpret(ops: Vec<Op>, regs: &mut RegisterFile) {
+		let mut bus: BusMatrix = Default::default();
+
+		interpret_with_bus(ops, regs, &mut bus).unwrap();
+	}
+
+	fn interpret_with_bus(ops: Vec<Op>, regs: &mut RegisterFile, bus: &mut BusMatrix) -> Result<(), Error> {
+		let instrs = ops.into_iter().map(|op| {
+			Instr{op: op, pred: Pred::None, exc: 0, size: 4}
+		}).collect();
+
+		interpret_op_list(&instrs, regs, bus, &mut StoreBuffer::default(), &TestTranslator).map(|_| ())
+	}
+
+	#[test]
+	fn register_file_clear() {
+		let mut regs = RegisterFile::new();
+
+		for reg in 0..1024 {
+			regs.write_u32(reg, 0xFFFFFFFF);
+		}
+		regs.pc = 0x80001000;
+
+		regs.clear();
+
+		assert!(regs.bytes.iter().all(|byte| *byte == 0));
+		assert_eq!(0, regs.pc);
+	}
+
+	#[test]
+	fn narrow_writes_preserve_upper_bytes() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x12345678);
+		regs.write_u32(2, 0x12345678);
+		regs.write_u32(3, 0x12345678);
+
+		interpret(vec!(
+			Op::Add(DstSrcSrc{dst: R::H(2), src: [Src::ImmU32(0xFFFFBEEE), Src::ImmU32(1)]}),
+			Op::Add(DstSrcSrc{dst: R::B(8), src: [Src::ImmU32(0xFFFFFFEE), Src::ImmU32(1)]}),
+			Op::Add(DstSrcSrc{dst: R::W(3), src: [Src::ImmU32(0x0000BEEE), Src::ImmU32(1)]}),
+		), &mut regs);
+
+		assert_eq!(0x1234BEEF, regs.read_u32(1));
+		assert_eq!(0x123456EF, regs.read_u32(2));
+		assert_eq!(0x0000BEEF, regs.read_u32(3));
+
+		regs.write_u16(3, 0xCAFE);
+
+		assert_eq!(0xCAFEBEEF, regs.read_u32(1));
+		assert_eq!(0xEF, regs.read_u8(8));
+	}
+
+	#[test]
+	fn ext_sign_extends_byte() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x12345680);
+
+		interpret(vec!(Op::Ext(Ext{signed: true, width: 8}, DstSrc{dst: R::W(2), src: Src::Reg(R::W(1))})), &mut regs);
+
+		assert_eq!(0xFFFFFF80, regs.read_u32(2));
+	}
+
+	#[test]
+	fn ext_sign_extends_half() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x12347FFF);
+
+		interpret(vec!(Op::Ext(Ext{signed: true, width: 16}, DstSrc{dst: R::W(2), src: Src::Reg(R::W(1))})), &mut regs);
+
+		assert_eq!(0x00007FFF, regs.read_u32(2));
+	}
+
+	#[test]
+	fn ext_zero_extends_byte() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x12345680);
+
+		interpret(vec!(Op::Ext(Ext{signed: false, width: 8}, DstSrc{dst: R::W(2), src: Src::Reg(R::W(1))})), &mut regs);
+
+		assert_eq!(0x00000080, regs.read_u32(2));
+	}
+
+	// Runs op with W(1) = a and W(2) = b as reg, reg into W(3), then as reg, imm
+	// into W(4), returning both
+	fn reg_and_imm_forms(op: fn(DstSrcSrc) -> Op, a: u32, b: u32) -> (u32, u32) {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, a);
+		regs.write_u32(2, b);
+
+		interpret(vec!(
+			op(DstSrcSrc{dst: R::W(3), src: [Src::Reg(R::W(1)), Src::Reg(R::W(2))]}),
+			op(DstSrcSrc{dst: R::W(4), src: [Src::Reg(R::W(1)), Src::ImmU32(b)]}),
+		), &mut regs);
+
+		(regs.read_u32(3), regs.read_u32(4))
+	}
+
+	#[test]
+	fn sub_wraps() {
+		assert_eq!((0x00000002, 0x00000002), reg_and_imm_forms(Op::Sub, 5, 3));
+		assert_eq!((0xFFFFFFFE, 0xFFFFFFFE), reg_and_imm_forms(Op::Sub, 3, 5));
+		assert_eq!((0x7FFFFFFF, 0x7FFFFFFF), reg_and_imm_forms(Op::Sub, 0x80000000, 1));
+	}
+
+	#[test]
+	fn and_or_xor() {
+		assert_eq!((0x00000F00, 0x00000F00), reg_and_imm_forms(Op::And, 0x0000FF00, 0x00F00F0F));
+		assert_eq!((0x00F0FF0F, 0x00F0FF0F), reg_and_imm_forms(Op::Or,  0x0000FF00, 0x00F00F0F));
+		assert_eq!((0x00F0F00F, 0x00F0F00F), reg_and_imm_forms(Op::Xor, 0x0000FF00, 0x00F00F0F));
+	}
+
+	#[test]
+	fn nor_is_masked_to_the_destination() {
+		assert_eq!((0xFF0F00F0, 0xFF0F00F0), reg_and_imm_forms(Op::Nor, 0x0000FF00, 0x00F00F0F));
+
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x12345678);
+
+		interpret(vec!(
+			Op::Nor(DstSrcSrc{dst: R::B(4), src: [Src::Reg(R::Zero), Src::ImmU8(0x0F)]}),
+			Op::Nor(DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::Zero), Src::Reg(R::Zero)]}),
+		), &mut regs);
+
+		assert_eq!(0x123456F0, regs.read_u32(1));
+		assert_eq!(0xFFFFFFFF, regs.read_u32(2));
+	}
+
+	#[test]
+	fn sll_drops_bits_off_the_top() {
+		assert_eq!((0x00000050, 0x00000050), reg_and_imm_forms(Op::Sll, 0x00000005, 4));
+		assert_eq!((0x00000000, 0x00000000), reg_and_imm_forms(Op::Sll, 0x80000000, 1));
+		assert_eq!((0xFFFFFFF0, 0xFFFFFFF0), reg_and_imm_forms(Op::Sll, 0xFFFFFFFF, 4));
+	}
+
+	#[test]
+	fn srl_shifts_in_zeroes() {
+		assert_eq!((0x00000005, 0x00000005), reg_and_imm_forms(Op::Srl, 0x00000050, 4));
+		assert_eq!((0x08000000, 0x08000000), reg_and_imm_forms(Op::Srl, 0x80000000, 4));
+		assert_eq!((0x00000001, 0x00000001), reg_and_imm_forms(Op::Srl, 0xFFFFFFFF, 31));
+	}
+
+	#[test]
+	fn sra_keeps_the_sign() {
+		assert_eq!((0x00000005, 0x00000005), reg_and_imm_forms(Op::Sra, 0x00000050, 4));
+		assert_eq!((0xF8000000, 0xF8000000), reg_and_imm_forms(Op::Sra, 0x80000000, 4));
+		assert_eq!((0xFFFFFFFF, 0xFFFFFFFF), reg_and_imm_forms(Op::Sra, 0xFFFFFFF0, 31));
+	}
+
+	#[test]
+	fn shift_amounts_use_only_their_low_five_bits() {
+		assert_eq!((0x00000002, 0x00000002), reg_and_imm_forms(Op::Sll, 0x00000001, 33));
+		assert_eq!((0x40000000, 0x40000000), reg_and_imm_forms(Op::Srl, 0x80000000, 0xFFFFFFE1));
+		assert_eq!((0xC0000000, 0xC0000000), reg_and_imm_forms(Op::Sra, 0x80000000, 0xFFFFFFE1));
+	}
+
+	#[test]
+	fn shifts_into_x_are_64_bit() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0x80000000_00000010);
+		regs.write_u64(2, 65);
+
+		interpret(vec!(
+			Op::Sll(DstSrcSrc{dst: R::X(3), src: [Src::ImmU32(0x40000000), Src::ImmU8(33)]}),
+			Op::Sll(DstSrcSrc{dst: R::X(4), src: [Src::Reg(R::X(1)), Src::Reg(R::X(2))]}),
+			Op::Srl(DstSrcSrc{dst: R::X(5), src: [Src::Reg(R::X(1)), Src::ImmU8(4)]}),
+			Op::Sra(DstSrcSrc{dst: R::X(6), src: [Src::Reg(R::X(1)), Src::ImmU8(4)]}),
+			Op::Srl(DstSrcSrc{dst: R::X(7), src: [Src::ImmU64(0x00000000_F0000000), Src::Reg(R::X(2))]}),
+			Op::Sra(DstSrcSrc{dst: R::X(8), src: [Src::ImmU64(0x00000000_F0000000), Src::ImmU8(4)]}),
+		), &mut regs);
+
+		// The amount's low six bits count, so 65 shifts by 1
+		assert_eq!(0x80000000_00000000, regs.read_u64(3));
+		assert_eq!(0x00000000_00000020, regs.read_u64(4));
+		assert_eq!(0x08000000_00000001, regs.read_u64(5));
+		assert_eq!(0xF8000000_00000001, regs.read_u64(6));
+		assert_eq!(0x00000000_78000000, regs.read_u64(7));
+		assert_eq!(0x00000000_0F000000, regs.read_u64(8));
+	}
+
+	#[test]
+	fn division_truncates_toward_zero() {
+		assert_eq!((0x00000003, 0x00000003), reg_and_imm_forms(Op::Div,  7, 2));
+		assert_eq!((0x00000001, 0x00000001), reg_and_imm_forms(Op::Mod,  7, 2));
+		assert_eq!((0xFFFFFFFD, 0xFFFFFFFD), reg_and_imm_forms(Op::Div,  (-7i32) as u32, 2));
+		assert_eq!((0xFFFFFFFF, 0xFFFFFFFF), reg_and_imm_forms(Op::Mod,  (-7i32) as u32, 2));
+		assert_eq!((0x7FFFFFFC, 0x7FFFFFFC), reg_and_imm_forms(Op::Divu, (-7i32) as u32, 2));
+		assert_eq!((0x00000001, 0x00000001), reg_and_imm_forms(Op::Modu, (-7i32) as u32, 2));
+	}
+
+	#[test]
+	fn int_min_divided_by_minus_one_wraps() {
+		assert_eq!((0x80000000, 0x80000000), reg_and_imm_forms(Op::Div,  0x80000000, 0xFFFFFFFF));
+		assert_eq!((0x00000000, 0x00000000), reg_and_imm_forms(Op::Mod,  0x80000000, 0xFFFFFFFF));
+		assert_eq!((0x00000000, 0x00000000), reg_and_imm_forms(Op::Divu, 0x80000000, 0xFFFFFFFF));
+		assert_eq!((0x80000000, 0x80000000), reg_and_imm_forms(Op::Modu, 0x80000000, 0xFFFFFFFF));
+	}
+
+	#[test]
+	fn divide_by_zero_leaves_the_destination_alone() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 7);
+		regs.write_u32(3, 0xDEADBEEF);
+
+		interpret(vec!(
+			Op::Div(DstSrcSrc{dst: R::W(3),  src: [Src::Reg(R::W(1)), Src::Reg(R::Zero)]}),
+			Op::Divu(DstSrcSrc{dst: R::W(3), src: [Src::Reg(R::W(1)), Src::ImmU32(0)]}),
+			Op::Mod(DstSrcSrc{dst: R::W(3),  src: [Src::Reg(R::W(1)), Src::Reg(R::W(2))]}),
+			Op::Modu(DstSrcSrc{dst: R::W(3), src: [Src::Reg(R::W(1)), Src::ImmI8(0)]}),
+		), &mut regs);
+
+		assert_eq!(0xDEADBEEF, regs.read_u32(3));
+	}
+
+	#[test]
+	fn division_into_x_is_64_bit() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0x80000000_00000000);
+		regs.write_u64(2, 0xFFFFFFFF_FFFFFFFF);
+		regs.write_u64(10, 0xDEADBEEF_DEADBEEF);
+
+		interpret(vec!(
+			Op::Div(DstSrcSrc{dst: R::X(3),  src: [Src::Reg(R::X(1)), Src::Reg(R::X(2))]}),
+			Op::Mod(DstSrcSrc{dst: R::X(4),  src: [Src::Reg(R::X(1)), Src::ImmI64(-1)]}),
+			Op::Divu(DstSrcSrc{dst: R::X(5), src: [Src::Reg(R::X(2)), Src::ImmU32(0x10)]}),
+			Op::Modu(DstSrcSrc{dst: R::X(6), src: [Src::Reg(R::X(2)), Src::ImmU32(0x10)]}),
+			Op::Div(DstSrcSrc{dst: R::X(7),  src: [Src::ImmI64(-7), Src::ImmI8(2)]}),
+			Op::Mod(DstSrcSrc{dst: R::X(8),  src: [Src::ImmI64(-7), Src::ImmI8(2)]}),
+
+			Op::Div(DstSrcSrc{dst: R::X(10),  src: [Src::Reg(R::X(1)), Src::Reg(R::Zero)]}),
+			Op::Divu(DstSrcSrc{dst: R::X(10), src: [Src::Reg(R::X(1)), Src::ImmU64(0)]}),
+			Op::Mod(DstSrcSrc{dst: R::X(10),  src: [Src::Reg(R::X(1)), Src::ImmI64(0)]}),
+			Op::Modu(DstSrcSrc{dst: R::X(10), src: [Src::Reg(R::X(1)), Src::Reg(R::X(9))]}),
+		), &mut regs);
+
+		// i64::MIN / -1 wraps back to i64::MIN
+		assert_eq!(0x80000000_00000000, regs.read_u64(3));
+		assert_eq!(0x00000000_00000000, regs.read_u64(4));
+		assert_eq!(0x0FFFFFFF_FFFFFFFF, regs.read_u64(5));
+		assert_eq!(0x00000000_0000000F, regs.read_u64(6));
+		assert_eq!(0xFFFFFFFF_FFFFFFFD, regs.read_u64(7));
+		assert_eq!(0xFFFFFFFF_FFFFFFFF, regs.read_u64(8));
+		assert_eq!(0xDEADBEEF_DEADBEEF, regs.read_u64(10));
+	}
+
+	#[test]
+	fn set_compares_at_the_destination_width() {
+		fn set_ne(args: DstSrcSrc) -> Op { Op::Set(Cond::Ne, args) }
+		fn set_eq(args: DstSrcSrc) -> Op { Op::Set(Cond::Eq, args) }
+		fn set_ge(args: DstSrcSrc) -> Op { Op::Set(Cond::Ge, args) }
+		fn set_gt(args: DstSrcSrc) -> Op { Op::Set(Cond::Gt, args) }
+		fn set_le(args: DstSrcSrc) -> Op { Op::Set(Cond::Le, args) }
+		fn set_lt(args: DstSrcSrc) -> Op { Op::Set(Cond::Lt, args) }
+
+		let ops: [(&str, fn(DstSrcSrc) -> Op); 6] = [("ne", set_ne), ("eq", set_eq), ("ge", set_ge), ("gt", set_gt), ("le", set_le), ("lt", set_lt)];
+
+		// (a, b, what ne, eq, ge, gt, le and lt give for a W destination)
+		let cases: [(u32, u32, [u32; 6]); 5] = [
+			(5,          5,          [0, 1, 1, 0, 1, 0]),
+			(3,          5,          [1, 0, 0, 0, 1, 1]),
+			(0xFFFFFFFF, 1,          [1, 0, 0, 0, 1, 1]), // -1 < 1 signed, though not unsigned
+			(1,          0xFFFFFFFF, [1, 0, 1, 1, 0, 0]),
+			(0x80000000, 0x7FFFFFFF, [1, 0, 0, 0, 1, 1]),
+		];
+
+		for &(a, b, expected) in cases.iter() {
+			for (&(name, op), expected) in ops.iter().zip(expected.iter()) {
+				assert_eq!((*expected, *expected), reg_and_imm_forms(op, a, b), "{} {:#x} {:#x}", name, a, b);
+			}
+		}
+
+		// 0x80 is negative as a byte but not as a word
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x00000080);
+
+		interpret(vec!(
+			Op::Set(Cond::Lt, DstSrcSrc{dst: R::B(8),  src: [Src::Reg(R::W(1)), Src::ImmU32(0)]}),
+			Op::Set(Cond::Lt, DstSrcSrc{dst: R::W(3),  src: [Src::Reg(R::W(1)), Src::ImmU32(0)]}),
+			Op::Set(Cond::Eq, DstSrcSrc{dst: R::H(8),  src: [Src::Reg(R::W(1)), Src::ImmU32(0xFFFF0080)]}),
+			Op::Set(Cond::Gt, DstSrcSrc{dst: R::X(2),  src: [Src::ImmU64(1 << 32), Src::ImmI64(-1)]}),
+		), &mut regs);
+
+		assert_eq!(1, regs.read_u8(8));
+		assert_eq!(0, regs.read_u32(3));
+		assert_eq!(1, regs.read_u16(8));
+		assert_eq!(1, regs.read_u64(2));
+	}
+
+	#[test]
+	fn or_w_w_immu16_still_zero_extends() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x80000001);
+
+		interpret(vec!(
+			Op::Or(DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(1)), Src::ImmU16(0x8000)]}),
+		), &mut regs);
+
+		assert_eq!(0x80008001, regs.read_u32(2));
+	}
+
+	#[test]
+	fn arithmetic_wraps_instead_of_panicking() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x80000000);
+		regs.write_u32(2, 0x12345678);
+
+		interpret(vec!(
+			Op::Mul(DstSrcSrc{dst: R::W(3), src: [Src::Reg(R::W(1)), Src::ImmI32(-1)]}),
+			Op::Mulh(DstSrcSrc{dst: R::W(4), src: [Src::Reg(R::W(1)), Src::Reg(R::W(1))]}),
+			Op::Ext(Ext{signed: true, width: 0}, DstSrc{dst: R::W(5), src: Src::Reg(R::W(2))}),
+			Op::Ext(Ext{signed: false, width: 200}, DstSrc{dst: R::W(6), src: Src::Reg(R::W(2))}),
+		), &mut regs);
+
+		assert_eq!(0x80000000, regs.read_u32(3));
+		assert_eq!(0x40000000, regs.read_u32(4));
+		assert_eq!(0x00000000, regs.read_u32(5));
+		assert_eq!(0x12345678, regs.read_u32(6));
+
+		assert_eq!(0x00000002, shl_u32(0x00000001, 33));
+		assert_eq!(0x40000000, shr_u32(0x80000000, 33));
+		assert_eq!(0xC0000000, sar_u32(0x80000000, 0xFFFFFFE1));
+
+		regs.pc = 0xFFFFFFFFFFFFFFFC;
+
+		interpret(vec!(Op::Mul(DstSrcSrc{dst: R::Discard, src: [Src::ImmU32(0), Src::ImmU32(0)]})), &mut regs);
+
+		assert_eq!(0, regs.pc);
+	}
+
+	#[test]
+	fn ld_copies_source() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(6, 0x12345678);
+		regs.write_u32(7, 0xFFFFFFFF);
+
+		interpret(vec!(
+			Op::Ld(DstSrc{dst: R::W(5), src: Src::Reg(R::W(6))}),
+			Op::Ld(DstSrc{dst: R::W(8), src: Src::ImmI16(-2)}),
+			Op::Ld(DstSrc{dst: R::W(9), src: Src::ImmU32(0xABCD0000)}),
+			Op::Ld(DstSrc{dst: R::H(14), src: Src::Reg(R::H(12))}),
+		), &mut regs);
+
+		assert_eq!(0x12345678, regs.read_u32(5));
+		assert_eq!(0x12345678, regs.read_u32(6));
+		assert_eq!(0xFFFFFFFE, regs.read_u32(8));
+		assert_eq!(0xABCD0000, regs.read_u32(9));
+		assert_eq!(0xFFFF5678, regs.read_u32(7));
+	}
+
+	#[test]
+	fn ld_x_takes_64_bit_sources() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0x89ABCDEF_01234567);
+
+		interpret(vec!(
+			Op::Ld(DstSrc{dst: R::X(2), src: Src::ImmU64(0x12345678_9ABCDEF0)}),
+			Op::Ld(DstSrc{dst: R::X(3), src: Src::ImmI64(-2)}),
+			Op::Ld(DstSrc{dst: R::X(4), src: Src::ImmI16(-2)}),
+			Op::Ld(DstSrc{dst: R::X(5), src: Src::Reg(R::X(1))}),
+		), &mut regs);
+
+		assert_eq!(0x12345678_9ABCDEF0, regs.read_u64(2));
+		assert_eq!(0xFFFFFFFF_FFFFFFFE, regs.read_u64(3));
+		assert_eq!(0xFFFFFFFF_FFFFFFFE, regs.read_u64(4));
+		assert_eq!(0x89ABCDEF_01234567, regs.read_u64(5));
+	}
+
+	#[test]
+	fn clz_clo() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x00000000);
+		regs.write_u32(2, 0xFFFFFFFF);
+		regs.write_u32(3, 0x00012345);
+		regs.write_u32(4, 0xFFF0FFFF);
+
+		interpret(vec!(
+			Op::Clz(DstSrc{dst: R::W(10), src: Src::Reg(R::W(1))}),
+			Op::Clz(DstSrc{dst: R::W(11), src: Src::Reg(R::W(2))}),
+			Op::Clz(DstSrc{dst: R::W(12), src: Src::Reg(R::W(3))}),
+			Op::Clo(DstSrc{dst: R::W(13), src: Src::Reg(R::W(1))}),
+			Op::Clo(DstSrc{dst: R::W(14), src: Src::Reg(R::W(2))}),
+			Op::Clo(DstSrc{dst: R::W(15), src: Src::Reg(R::W(4))}),
+		), &mut regs);
+
+		assert_eq!(32, regs.read_u32(10));
+		assert_eq!( 0, regs.read_u32(11));
+		assert_eq!(15, regs.read_u32(12));
+		assert_eq!( 0, regs.read_u32(13));
+		assert_eq!(32, regs.read_u32(14));
+		assert_eq!(12, regs.read_u32(15));
+	}
+
+	#[test]
+	fn lb_zero_extends_lbs_sign_extends() {
+		let mut regs = RegisterFile::new();
+		let mut bus: BusMatrix = Default::default();
+
+		bus.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+		bus.set_range(&[0x00, 0xFF], 0x1000).unwrap();
+
+		regs.write_u32(1, 0x1000);
+
+		interpret_with_bus(vec!(
+			Op::Lb(DstSrcSrc{dst: R::W(2), src: [Src::ImmI16(1), Src::Reg(R::W(1))]}),
+			Op::Lbs(DstSrcSrc{dst: R::W(3), src: [Src::ImmI16(1), Src::Reg(R::W(1))]}),
+		), &mut regs, &mut bus).unwrap();
+
+		assert_eq!(0x000000FF, regs.read_u32(2));
+		assert_eq!(0xFFFFFFFF, regs.read_u32(3));
+	}
+
+	#[test]
+	fn lh_zero_extends_and_faults_off_the_bus() {
+		let mut regs = RegisterFile::new();
+		let mut bus: BusMatrix = Default::default();
+
+		bus.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+		bus.set_range(&[0x00, 0x00, 0xEF, 0xBE], 0x1000).unwrap();
+
+		regs.write_u32(1, 0x1000);
+
+		interpret_with_bus(vec!(
+			Op::Lh(DstSrcSrc{dst: R::W(2), src: [Src::ImmI16(2), Src::Reg(R::W(1))]}),
+			Op::Lw(DstSrcSrc{dst: R::W(3), src: [Src::ImmI16(0), Src::Reg(R::W(1))]}),
+		), &mut regs, &mut bus).unwrap();
+
+		assert_eq!(0x0000BEEF, regs.read_u32(2));
+		assert_eq!(0xBEEF0000, regs.read_u32(3));
+
+		let result = interpret_with_bus(vec!(
+			Op::Lh(DstSrcSrc{dst: R::W(2), src: [Src::ImmI16(-2), Src::Reg(R::W(1))]}),
+		), &mut regs, &mut bus);
+
+		match result {
+			Err(Error::BusFault{ addr: 0xFFE, width: 2, is_write: false }) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn u64_round_trips_through_narrower_views() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0x01234567_89ABCDEF);
+
+		assert_eq!(0x01234567_89ABCDEF, regs.read_u64(1));
+		assert_eq!(0x89ABCDEF, regs.read_u32(2));
+		assert_eq!(0x01234567, regs.read_u32(3));
+		assert_eq!(0xCDEF, regs.read_u16(4));
+		assert_eq!(0x0123, regs.read_u16(7));
+		assert_eq!(0xEF, regs.read_u8(8));
+		assert_eq!(0x01, regs.read_u8(15));
+
+		// Narrow writes land inside the 64-bit value, little end first
+		regs.write_u16(5, 0xBEEF);
+		regs.write_u8(14, 0x55);
+
+		assert_eq!(0x01554567_BEEFCDEF, regs.read_u64(1));
+		assert_eq!(0, regs.read_u64(0));
+		assert_eq!(0, regs.read_u64(2));
+	}
+
+	#[test]
+	fn predicated_add_runs_only_when_its_predicate_says() {
+		let add = Op::Add(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmU32(1)]});
+
+		let instrs = vec!(
+			Instr{op: add.clone(), pred: Pred::Pred(R::P(0)),    exc: 0, size: 4},
+			Instr{op: add.clone(), pred: Pred::NotPred(R::P(0)), exc: 0, size: 4},
+			Instr{op: add.clone(), pred: Pred::Pred(R::P(3)),    exc: 0, size: 4},
+			Instr{op: add.clone(), pred: Pred::NotPred(R::P(3)), exc: 0, size: 4},
+		);
+
+		let mut bus: BusMatrix = Default::default();
+		let mut regs = RegisterFile::new();
+
+		regs.write_pred(3, true).unwrap();
+		regs.pc = 0x1000;
+
+		interpret_op_list(&instrs, &mut regs, &mut bus, &mut StoreBuffer::default(), &TestTranslator).unwrap();
+
+		// Only the NotPred(P0) and Pred(P3) adds ran, but the pc passed all four
+		assert_eq!(2, regs.read_u32(1));
+		assert_eq!(0x1010, regs.pc);
+	}
+
+	#[test]
+	fn pred_registers_past_63_are_errors() {
+		let add = Op::Add(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmU32(1)]});
+		let mut regs = RegisterFile::new();
+		let mut bus: BusMatrix = Default::default();
+
+		regs.write_pred(63, true).unwrap();
+
+		match regs.write_pred(64, true) {
+			Err(Error::InvalidRegister(R::P(64))) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match regs.read_pred(200) {
+			Err(Error::InvalidRegister(R::P(200))) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		// Pred(n) is P(n) by another name
+		let instrs = vec!(
+			Instr{op: add.clone(), pred: Pred::Pred(R::Pred(63)),    exc: 0, size: 4},
+			Instr{op: add.clone(), pred: Pred::NotPred(R::Pred(63)), exc: 0, size: 4},
+		);
+
+		interpret_op_list(&instrs, &mut regs, &mut bus, &mut StoreBuffer::default(), &TestTranslator).unwrap();
+
+		assert_eq!(1, regs.read_u32(1));
+
+		for pred in vec!(Pred::Pred(R::P(64)), Pred::NotPred(R::Pred(1 << 40))) {
+			let instrs = vec!(Instr{op: add.clone(), pred: pred, exc: 0, size: 4});
+
+			match interpret_op_list(&instrs, &mut regs, &mut bus, &mut StoreBuffer::default(), &TestTranslator) {
+				Err(Error::InvalidRegister(_)) => {},
+				other => panic!("Unexpected result:  {:?}", other),
+			}
+		}
+
+		assert_eq!(1, regs.read_u32(1));
+	}
+
+	#[test]
+	fn last_slot_is_the_last_64_bit_register() {
+		let mut regs = RegisterFile::with_slots(16);
+
+		regs.write_u64(15, 0x01234567_89ABCDEF);
+
+		assert_eq!(16, regs.slots());
+		assert_eq!(0x01234567_89ABCDEF, regs.read_u64(15));
+		assert_eq!(0x01234567, regs.read_u32(31));
+		assert_eq!(0x01, regs.read_u8(127));
+		assert_eq!(512, RegisterFile::new().slots());
+	}
+
+	#[test]
+	#[should_panic(expected = "8 byte register 16 is past the end of a 16 slot register file")]
+	fn first_register_past_the_slots_panics() {
+		RegisterFile::with_slots(16).write_u64(16, 0);
+	}
+
+	#[test]
+	#[should_panic(expected = "4 byte register 32 is past the end of a 16 slot register file")]
+	fn narrow_register_past_the_slots_panics() {
+		RegisterFile::with_slots(16).read_u32(32);
+	}
+
+	#[test]
+	fn instr_past_the_slots_is_an_error() {
+		let mut regs = RegisterFile::with_slots(16);
+		let mut bus: BusMatrix = Default::default();
+
+		for op in vec!(
+			Op::Add(DstSrcSrc{dst: R::X(16), src: [Src::ImmU32(1), Src::ImmU32(2)]}),
+			Op::Add(DstSrcSrc{dst: R::W(1),  src: [Src::Reg(R::W(32)), Src::ImmU32(2)]}),
+		) {
+			let instrs = vec!(Instr{op: op, pred: Pred::None, exc: 0, size: 4});
+
+			match interpret_op_list(&instrs, &mut regs, &mut bus, &mut StoreBuffer::default(), &TestTranslator) {
+				Err(Error::InvalidRegister(_)) => {},
+				other => panic!("Unexpected result:  {:?}", other),
+			}
+		}
+
+		assert_eq!(0, regs.read_u32(1));
+	}
+
+	#[test]
+	fn register_views_alias_by_byte_offset() {
+		assert_eq!(Some(4), R::W(1).base_index());
+		assert_eq!(Some(4), R::H(2).base_index());
+		assert_eq!(Some(4), R::B(4).base_index());
+
+		// W(0) and X(0) alias; W(1) is the high half of X(0)
+		assert_eq!(R::W(0).base_index(), R::X(0).base_index());
+		assert_eq!(Some(R::X(0)), R::W(1).with_width(64));
+		assert_eq!(Some(R::W(2)), R::X(1).with_width(32));
+
+		assert_eq!(Some(R::B(4)), R::W(1).with_width(8));
+		assert_eq!(Some(R::W(1)), R::B(7).with_width(32));
+		assert_eq!(Some(R::H(3)), R::H(3).with_width(16));
+		assert_eq!(Some(R::TH(6)), R::TW(3).with_width(16));
+
+		// No 12-bit views, and no byte view numbered high enough
+		assert_eq!(None, R::W(1).with_width(12));
+		assert_eq!(None, R::W(0x8000).with_width(8));
+
+		assert_eq!(None, R::Ip.base_index());
+		assert_eq!(None, R::Zero.base_index());
+		assert_eq!(None, R::Discard.base_index());
+		assert_eq!(None, R::TW(0).base_index());
+		assert_eq!(Some(R::Zero), R::Zero.with_width(32));
+	}
+
+	#[test]
+	fn aliases_defs_and_uses() {
+		assert!(R::W(1).aliases(R::X(0)));
+		assert!(R::B(7).aliases(R::W(1)));
+		assert!(!R::W(0).aliases(R::W(1)));
+		assert!(!R::W(1).aliases(R::TW(1)));
+		assert!(R::Ip.aliases(R::Ip));
+
+		let add = Op::Add(DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(3)), Src::ImmU32(1)]});
+		let branch = Op::Branch{cond: Cond::Eq, src: [Src::ImmU32(0), Src::ImmU32(0)], target: Src::Addr(0), delay_slot: Box::new(add.clone())};
+
+		assert_eq!(vec!(R::W(2)), op_defs(&add, &TestTranslator));
+		assert_eq!(vec!(R::W(2)), op_defs(&branch, &TestTranslator));
+		assert_eq!(vec!(R::W(31)), op_defs(&Op::Call(Src::Addr(0)), &TestTranslator));
+		assert_eq!(Vec::<R>::new(), op_defs(&Op::Sw(SrcSrcSrc{src: [Src::ImmU32(0); 3]}), &TestTranslator));
+
+		assert_eq!(vec!(R::W(3)), op_uses(&add));
+		assert_eq!(vec!(R::W(3)), op_uses(&branch));
+		assert_eq!(vec!(R::W(4), R::W(2)), op_uses(&Op::CMov(Cond::Eq, DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(4)), Src::ImmU32(0)]})));
+	}
+
+	#[test]
+	fn sb_sh_store_only_the_low_bits() {
+		let mut regs = RegisterFile::new();
+		let mut bus: BusMatrix = Default::default();
+
+		bus.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+		bus.set_range(&[0xAA; 8], 0x1000).unwrap();
+
+		regs.write_u32(1, 0x1000);
+		regs.write_u32(2, 0x12345678);
+
+		interpret_with_bus(vec!(
+			Op::Sb(SrcSrcSrc{src: [Src::Reg(R::W(2)), Src::ImmI16(1), Src::Reg(R::W(1))]}),
+			Op::Sh(SrcSrcSrc{src: [Src::Reg(R::W(2)), Src::ImmI16(4), Src::Reg(R::W(1))]}),
+		), &mut regs, &mut bus).unwrap();
+
+		let bytes: Vec<u8> = (0..8).map(|offset| bus.read_u8(0x1000 + offset).into_result(0x1000 + offset).unwrap()).collect();
+
+		assert_eq!(vec!(0xAA, 0x78, 0xAA, 0xAA, 0x78, 0x56, 0xAA, 0xAA), bytes);
+	}
+
+	#[test]
+	fn resolve_src_and_store_dst_widths() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0x89ABCDEF_01234567);
+
+		assert_eq!(0x67,                  regs.resolve_src(Src::Reg(R::B(8))));
+		assert_eq!(0x4567,                regs.resolve_src(Src::Reg(R::H(4))));
+		assert_eq!(0x01234567,            regs.resolve_src(Src::Reg(R::W(2))));
+		assert_eq!(0x89ABCDEF_01234567,   regs.resolve_src(Src::Reg(R::X(1))));
+		assert_eq!(0,                     regs.resolve_src(Src::Reg(R::Zero)));
+		assert_eq!(0xFF,                  regs.resolve_src(Src::ImmU8(0xFF)));
+		assert_eq!(0xFFFFFFFF_FFFFFFFF,   regs.resolve_src(Src::ImmI8(-1)));
+		assert_eq!(0x8000,                regs.resolve_src(Src::ImmU16(0x8000)));
+		assert_eq!(0xFFFFFFFF_FFFF8000,   regs.resolve_src(Src::ImmI16(-0x8000)));
+		assert_eq!(0x80000000,            regs.resolve_src(Src::ImmU32(0x80000000)));
+		assert_eq!(0xFFFFFFFF_80000000,   regs.resolve_src(Src::ImmI32(-0x80000000)));
+
+		regs.store_dst(R::B(8), 0x1122334455667788);
+		regs.store_dst(R::H(5), 0x1122334455667788);
+		regs.store_dst(R::Discard, 0x1122334455667788);
+
+		assert_eq!(0x89ABCDEF_77884588, regs.read_u64(1));
+
+		regs.store_dst(R::W(3), 0x1122334455667788);
+
+		assert_eq!(0x55667788_77884588, regs.read_u64(1));
+	}
+
+	#[test]
+	fn add_extends_each_immediate_by_its_kind() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x00001000);
+		regs.write_u32(9, 0xDEADBEEF);
+
+		interpret(vec!(
+			Op::Add(DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(1)), Src::ImmI8(-1)]}),
+			Op::Add(DstSrcSrc{dst: R::W(3), src: [Src::Reg(R::W(1)), Src::ImmU8(0xFF)]}),
+			Op::Add(DstSrcSrc{dst: R::W(4), src: [Src::Reg(R::W(1)), Src::ImmI16(-0x1000)]}),
+			Op::Add(DstSrcSrc{dst: R::W(5), src: [Src::Reg(R::W(1)), Src::ImmU16(0xF000)]}),
+			Op::Add(DstSrcSrc{dst: R::W(6), src: [Src::ImmU32(0xFFFFFFFF), Src::ImmI32(2)]}),
+			Op::Add(DstSrcSrc{dst: R::W(7), src: [Src::Reg(R::Zero), Src::ImmU16(0x55)]}),
+			Op::Add(DstSrcSrc{dst: R::Discard, src: [Src::Reg(R::W(9)), Src::ImmU32(1)]}),
+			Op::Add(DstSrcSrc{dst: R::W(10), src: [Src::Reg(R::W(1)), Src::ImmU64(0x00000001_00000001)]}),
+			Op::Add(DstSrcSrc{dst: R::W(11), src: [Src::Reg(R::W(1)), Src::ImmI64(-1)]}),
+		), &mut regs);
+
+		assert_eq!(0x00000FFF, regs.read_u32(2));
+		assert_eq!(0x000010FF, regs.read_u32(3));
+		assert_eq!(0x00000000, regs.read_u32(4));
+		assert_eq!(0x00010000, regs.read_u32(5));
+		assert_eq!(0x00000001, regs.read_u32(6));
+		assert_eq!(0x00000055, regs.read_u32(7));
+		assert_eq!(0xDEADBEEF, regs.read_u32(9));
+		assert_eq!(0x00001001, regs.read_u32(10));
+		assert_eq!(0x00000FFF, regs.read_u32(11));
+	}
+
+	#[test]
+	fn add_w_sign_extends_into_x_dst() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0x7FFFFFFF);
+		regs.write_u64(2, 0x00000001);
+		regs.write_u64(3, 0x12345678_00000000);
+
+		interpret(vec!(Op::Add(DstSrcSrc{dst: R::X(3), src: [Src::Reg(R::X(1)), Src::Reg(R::X(2))]})), &mut regs);
+
+		assert_eq!(0xFFFFFFFF_80000000, regs.read_u64(3));
+	}
+
+	#[test]
+	fn add_w_clears_high_bits_of_x_dst_when_positive() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u64(1, 0xFFFFFFFF_00000001);
+		regs.write_u64(3, 0xFFFFFFFF_FFFFFFFF);
+
+		interpret(vec!(Op::Add(DstSrcSrc{dst: R::X(3), src: [Src::Reg(R::X(1)), Src::ImmU32(1)]})), &mut regs);
+
+		assert_eq!(0x00000000_00000002, regs.read_u64(3));
+	}
+
+	#[test]
+	fn cmov_taken() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x1234);
+		regs.write_u32(2, 0x5678);
+		regs.write_u32(3, 0);
+
+		interpret(vec!(Op::CMov(Cond::Eq, DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(1)), Src::Reg(R::W(3))]})), &mut regs);
+
+		assert_eq!(0x1234, regs.read_u32(2));
+	}
+
+	#[test]
+	fn cmov_skipped() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 0x1234);
+		regs.write_u32(2, 0x5678);
+		regs.write_u32(3, 0);
+
+		interpret(vec!(Op::CMov(Cond::Ne, DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(1)), Src::Reg(R::W(3))]})), &mut regs);
+
+		assert_eq!(0x5678, regs.read_u32(2));
+	}
+
+	#[test]
+	fn branch_tests_condition_before_delay_slot() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 5);
+		regs.pc = 0x1000;
+
+		// The slot clobbers the compared register, but the branch must still be taken
+		interpret(vec!(Op::Branch{cond:       Cond::Eq,
+		                          src:        [Src::Reg(R::W(1)), Src::ImmU32(5)],
+		                          target:     Src::Addr(0x2000),
+		                          delay_slot: Box::new(Op::Ld(DstSrc{dst: R::W(1), src: Src::ImmU32(0)}))}), &mut regs);
+
+		assert_eq!(0, regs.read_u32(1));
+		assert_eq!(0x2000, regs.pc);
+	}
+
+	#[test]
+	fn untaken_branch_still_runs_delay_slot() {
+		let mut regs = RegisterFile::new();
+
+		regs.pc = 0x1000;
+
+		interpret(vec!(Op::Branch{cond:       Cond::Ne,
+		                          src:        [Src::Reg(R::W(1)), Src::ImmU32(0)],
+		                          target:     Src::Addr(0x2000),
+		                          delay_slot: Box::new(Op::Ld(DstSrc{dst: R::W(2), src: Src::ImmU32(0x1234)}))}), &mut regs);
+
+		assert_eq!(0x1234, regs.read_u32(2));
+		assert_eq!(0x1004, regs.pc);
+	}
+
+	#[test]
+	fn branch_to_register_target() {
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(1, 7);
+		regs.write_u32(2, 0x80001234);
+		regs.pc = 0x1000;
+
+		interpret(vec!(Op::B(Cond::Eq, SrcSrcTarget{src: [Src::Reg(R::W(1)), Src::ImmU32(7)], target: Src::Reg(R::W(2))})), &mut regs);
+
+		assert_eq!(0x80001234, regs.pc);
+	}
+}
@@ -0,0 +1,524 @@
+// A stable byte encoding of Instrs, for caching decoded code outside of the
+// process.  Each Instr is a version byte followed by its op, pred, exc and
+// size.  Enums are a one byte tag (their variant's position in the
+// declaration) followed by their fields in order, and integers are little
+// endian at their natural width.  Anything that changes that layout has to
+// bump WIRE_VERSION.
+
+use super::{Cond, DstSrc, DstSrcSrc, Ext, Instr, Op, Pred, R, Src, SrcSrc, SrcSrcSrc, SrcSrcTarget};
+
+use super::super::Error;
+
+const WIRE_VERSION: u8 = 1;
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+	buf.push((value >> 0) as u8);
+	buf.push((value >> 8) as u8);
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+	put_u16(buf, (value >>  0) as u16);
+	put_u16(buf, (value >> 16) as u16);
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+	put_u32(buf, (value >>  0) as u32);
+	put_u32(buf, (value >> 32) as u32);
+}
+
+fn put_r(buf: &mut Vec<u8>, r: R) {
+	match r {
+		R::Ip       => buf.push(0),
+		R::Discard  => buf.push(1),
+		R::Zero     => buf.push(2),
+		R::P(n)     => { buf.push(3);  buf.push(n); },
+		R::Pred(n)  => { buf.push(4);  put_u64(buf, n); },
+		R::B(n)     => { buf.push(5);  put_u16(buf, n); },
+		R::H(n)     => { buf.push(6);  put_u16(buf, n); },
+		R::W(n)     => { buf.push(7);  put_u16(buf, n); },
+		R::X(n)     => { buf.push(8);  put_u16(buf, n); },
+		R::TP(n)    => { buf.push(9);  buf.push(n); },
+		R::TPred(n) => { buf.push(10); put_u64(buf, n); },
+		R::TB(n)    => { buf.push(11); put_u16(buf, n); },
+		R::TH(n)    => { buf.push(12); put_u16(buf, n); },
+		R::TW(n)    => { buf.push(13); put_u16(buf, n); },
+		R::TX(n)    => { buf.push(14); put_u16(buf, n); },
+	}
+}
+
+fn put_src(buf: &mut Vec<u8>, src: Src) {
+	match src {
+		Src::Reg(r)      => { buf.push(0); put_r(buf, r); },
+		Src::ImmU8(imm)  => { buf.push(1); buf.push(imm); },
+		Src::ImmU16(imm) => { buf.push(2); put_u16(buf, imm); },
+		Src::ImmU32(imm) => { buf.push(3); put_u32(buf, imm); },
+		Src::ImmU64(imm) => { buf.push(4); put_u64(buf, imm); },
+		Src::ImmI8(imm)  => { buf.push(5); buf.push(imm as u8); },
+		Src::ImmI16(imm) => { buf.push(6); put_u16(buf, imm as u16); },
+		Src::ImmI32(imm) => { buf.push(7); put_u32(buf, imm as u32); },
+		Src::ImmI64(imm) => { buf.push(8); put_u64(buf, imm as u64); },
+		Src::Addr(addr)  => { buf.push(9); put_u64(buf, addr); },
+	}
+}
+
+fn put_cond(buf: &mut Vec<u8>, cond: Cond) {
+	buf.push(match cond {
+		Cond::Ne => 0,
+		Cond::Eq => 1,
+		Cond::Ge => 2,
+		Cond::Gt => 3,
+		Cond::Le => 4,
+		Cond::Lt => 5,
+	});
+}
+
+fn put_dst_src(buf: &mut Vec<u8>, args: DstSrc) {
+	put_r(buf, args.dst);
+	put_src(buf, args.src);
+}
+
+fn put_dst_src_src(buf: &mut Vec<u8>, args: DstSrcSrc) {
+	put_r(buf, args.dst);
+	put_src(buf, args.src[0]);
+	put_src(buf, args.src[1]);
+}
+
+fn put_src_src_src(buf: &mut Vec<u8>, args: SrcSrcSrc) {
+	for src in args.src.iter() {
+		put_src(buf, *src);
+	}
+}
+
+fn put_op(buf: &mut Vec<u8>, op: &Op) {
+	match *op {
+		Op::Nop => buf.push(0),
+
+		Op::Add(args)   => { buf.push(1);  put_dst_src_src(buf, args); },
+		Op::Sub(args)   => { buf.push(2);  put_dst_src_src(buf, args); },
+		Op::Mul(args)   => { buf.push(3);  put_dst_src_src(buf, args); },
+		Op::Mulh(args)  => { buf.push(4);  put_dst_src_src(buf, args); },
+		Op::Mulhu(args) => { buf.push(5);  put_dst_src_src(buf, args); },
+		Op::Div(args)   => { buf.push(6);  put_dst_src_src(buf, args); },
+		Op::Divu(args)  => { buf.push(7);  put_dst_src_src(buf, args); },
+		Op::Mod(args)   => { buf.push(8);  put_dst_src_src(buf, args); },
+		Op::Modu(args)  => { buf.push(9);  put_dst_src_src(buf, args); },
+
+		Op::And(args) => { buf.push(10); put_dst_src_src(buf, args); },
+		Op::Or(args)  => { buf.push(11); put_dst_src_src(buf, args); },
+		Op::Nor(args) => { buf.push(12); put_dst_src_src(buf, args); },
+		Op::Sll(args) => { buf.push(13); put_dst_src_src(buf, args); },
+		Op::Sra(args) => { buf.push(14); put_dst_src_src(buf, args); },
+		Op::Srl(args) => { buf.push(15); put_dst_src_src(buf, args); },
+		Op::Xor(args) => { buf.push(16); put_dst_src_src(buf, args); },
+
+		Op::Set(cond, args)  => { buf.push(17); put_cond(buf, cond); put_dst_src_src(buf, args); },
+		Op::CMov(cond, args) => { buf.push(18); put_cond(buf, cond); put_dst_src_src(buf, args); },
+		Op::Ext(ext, args)   => { buf.push(19); buf.push(ext.signed as u8); buf.push(ext.width); put_dst_src(buf, args); },
+		Op::Clz(args)        => { buf.push(20); put_dst_src(buf, args); },
+		Op::Clo(args)        => { buf.push(21); put_dst_src(buf, args); },
+
+		Op::Lb(args)  => { buf.push(22); put_dst_src_src(buf, args); },
+		Op::Lbs(args) => { buf.push(23); put_dst_src_src(buf, args); },
+		Op::Lh(args)  => { buf.push(24); put_dst_src_src(buf, args); },
+		Op::Lw(args)  => { buf.push(25); put_dst_src_src(buf, args); },
+		Op::Sb(args)  => { buf.push(26); put_src_src_src(buf, args); },
+		Op::Sh(args)  => { buf.push(27); put_src_src_src(buf, args); },
+		Op::Sw(args)  => { buf.push(28); put_src_src_src(buf, args); },
+		Op::Ld(args)  => { buf.push(29); put_dst_src(buf, args); },
+		Op::Fence     => buf.push(30),
+
+		Op::Call(target) => { buf.push(31); put_src(buf, target); },
+
+		Op::B(cond, args) => {
+			buf.push(32);
+			put_cond(buf, cond);
+			put_src(buf, args.src[0]);
+			put_src(buf, args.src[1]);
+			put_src(buf, args.target);
+		},
+
+		Op::Branch { cond, src, target, ref delay_slot } => {
+			buf.push(33);
+			put_cond(buf, cond);
+			put_src(buf, src[0]);
+			put_src(buf, src[1]);
+			put_src(buf, target);
+			put_op(buf, delay_slot);
+		},
+
+		Op::Exc       => buf.push(34),
+		Op::J(target) => { buf.push(35); put_src(buf, target); },
+
+		Op::Trap(cond, args) => { buf.push(36); put_cond(buf, cond); put_src(buf, args.src[0]); put_src(buf, args.src[1]); },
+	}
+}
+
+fn put_pred(buf: &mut Vec<u8>, pred: Pred) {
+	match pred {
+		Pred::None       => buf.push(0),
+		Pred::Pred(r)    => { buf.push(1); put_r(buf, r); },
+		Pred::NotPred(r) => { buf.push(2); put_r(buf, r); },
+	}
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn malformed(&self, what: &str) -> Error {
+		Error::MalformedInstr(format!("{} @ byte {}", what, self.pos))
+	}
+
+	fn u8(&mut self) -> Result<u8, Error> {
+		match self.bytes.get(self.pos) {
+			Some(byte) => {
+				self.pos += 1;
+
+				Ok(*byte)
+			},
+
+			None => Err(self.malformed("Truncated")),
+		}
+	}
+
+	fn u16(&mut self) -> Result<u16, Error> {
+		let low = try!(self.u8()) as u16;
+		let high = try!(self.u8()) as u16;
+
+		Ok(low | (high << 8))
+	}
+
+	fn u32(&mut self) -> Result<u32, Error> {
+		let low = try!(self.u16()) as u32;
+		let high = try!(self.u16()) as u32;
+
+		Ok(low | (high << 16))
+	}
+
+	fn u64(&mut self) -> Result<u64, Error> {
+		let low = try!(self.u32()) as u64;
+		let high = try!(self.u32()) as u64;
+
+		Ok(low | (high << 32))
+	}
+
+	fn r(&mut self) -> Result<R, Error> {
+		Ok(match try!(self.u8()) {
+			0  => R::Ip,
+			1  => R::Discard,
+			2  => R::Zero,
+			3  => R::P(try!(self.u8())),
+			4  => R::Pred(try!(self.u64())),
+			5  => R::B(try!(self.u16())),
+			6  => R::H(try!(self.u16())),
+			7  => R::W(try!(self.u16())),
+			8  => R::X(try!(self.u16())),
+			9  => R::TP(try!(self.u8())),
+			10 => R::TPred(try!(self.u64())),
+			11 => R::TB(try!(self.u16())),
+			12 => R::TH(try!(self.u16())),
+			13 => R::TW(try!(self.u16())),
+			14 => R::TX(try!(self.u16())),
+
+			tag => return Err(self.malformed(&format!("Unknown register tag {}", tag))),
+		})
+	}
+
+	fn src(&mut self) -> Result<Src, Error> {
+		Ok(match try!(self.u8()) {
+			0 => Src::Reg(try!(self.r())),
+			1 => Src::ImmU8(try!(self.u8())),
+			2 => Src::ImmU16(try!(self.u16())),
+			3 => Src::ImmU32(try!(self.u32())),
+			4 => Src::ImmU64(try!(self.u64())),
+			5 => Src::ImmI8(try!(self.u8()) as i8),
+			6 => Src::ImmI16(try!(self.u16()) as i16),
+			7 => Src::ImmI32(try!(self.u32()) as i32),
+			8 => Src::ImmI64(try!(self.u64()) as i64),
+			9 => Src::Addr(try!(self.u64())),
+
+			tag => return Err(self.malformed(&format!("Unknown source tag {}", tag))),
+		})
+	}
+
+	fn cond(&mut self) -> Result<Cond, Error> {
+		Ok(match try!(self.u8()) {
+			0 => Cond::Ne,
+			1 => Cond::Eq,
+			2 => Cond::Ge,
+			3 => Cond::Gt,
+			4 => Cond::Le,
+			5 => Cond::Lt,
+
+			tag => return Err(self.malformed(&format!("Unknown condition tag {}", tag))),
+		})
+	}
+
+	fn dst_src(&mut self) -> Result<DstSrc, Error> {
+		let dst = try!(self.r());
+		let src = try!(self.src());
+
+		Ok(DstSrc{dst: dst, src: src})
+	}
+
+	fn dst_src_src(&mut self) -> Result<DstSrcSrc, Error> {
+		let dst = try!(self.r());
+		let a = try!(self.src());
+		let b = try!(self.src());
+
+		Ok(DstSrcSrc{dst: dst, src: [a, b]})
+	}
+
+	fn src_src_src(&mut self) -> Result<SrcSrcSrc, Error> {
+		let a = try!(self.src());
+		let b = try!(self.src());
+		let c = try!(self.src());
+
+		Ok(SrcSrcSrc{src: [a, b, c]})
+	}
+
+	fn op(&mut self) -> Result<Op, Error> {
+		Ok(match try!(self.u8()) {
+			0 => Op::Nop,
+
+			1 => Op::Add(try!(self.dst_src_src())),
+			2 => Op::Sub(try!(self.dst_src_src())),
+			3 => Op::Mul(try!(self.dst_src_src())),
+			4 => Op::Mulh(try!(self.dst_src_src())),
+			5 => Op::Mulhu(try!(self.dst_src_src())),
+			6 => Op::Div(try!(self.dst_src_src())),
+			7 => Op::Divu(try!(self.dst_src_src())),
+			8 => Op::Mod(try!(self.dst_src_src())),
+			9 => Op::Modu(try!(self.dst_src_src())),
+
+			10 => Op::And(try!(self.dst_src_src())),
+			11 => Op::Or(try!(self.dst_src_src())),
+			12 => Op::Nor(try!(self.dst_src_src())),
+			13 => Op::Sll(try!(self.dst_src_src())),
+			14 => Op::Sra(try!(self.dst_src_src())),
+			15 => Op::Srl(try!(self.dst_src_src())),
+			16 => Op::Xor(try!(self.dst_src_src())),
+
+			17 => { let cond = try!(self.cond()); Op::Set(cond, try!(self.dst_src_src())) },
+			18 => { let cond = try!(self.cond()); Op::CMov(cond, try!(self.dst_src_src())) },
+			19 => {
+				let signed = try!(self.u8()) != 0;
+				let width = try!(self.u8());
+
+				Op::Ext(Ext{signed: signed, width: width}, try!(self.dst_src()))
+			},
+			20 => Op::Clz(try!(self.dst_src())),
+			21 => Op::Clo(try!(self.dst_src())),
+
+			22 => Op::Lb(try!(self.dst_src_src())),
+			23 => Op::Lbs(try!(self.dst_src_src())),
+			24 => Op::Lh(try!(self.dst_src_src())),
+			25 => Op::Lw(try!(self.dst_src_src())),
+			26 => Op::Sb(try!(self.src_src_src())),
+			27 => Op::Sh(try!(self.src_src_src())),
+			28 => Op::Sw(try!(self.src_src_src())),
+			29 => Op::Ld(try!(self.dst_src())),
+			30 => Op::Fence,
+
+			31 => Op::Call(try!(self.src())),
+
+			32 => {
+				let cond = try!(self.cond());
+				let a = try!(self.src());
+				let b = try!(self.src());
+				let target = try!(self.src());
+
+				Op::B(cond, SrcSrcTarget{src: [a, b], target: target})
+			},
+
+			33 => {
+				let cond = try!(self.cond());
+				let a = try!(self.src());
+				let b = try!(self.src());
+				let target = try!(self.src());
+
+				// Delay slots don't nest, and decoding ones that claimed to would
+				// let an encoding recurse as deep as it likes
+				if self.bytes.get(self.pos) == Some(&33) {
+					return Err(self.malformed("Branch in a delay slot"));
+				}
+
+				let delay_slot = try!(self.op());
+
+				Op::Branch{cond: cond, src: [a, b], target: target, delay_slot: Box::new(delay_slot)}
+			},
+
+			34 => Op::Exc,
+			35 => Op::J(try!(self.src())),
+
+			36 => {
+				let cond = try!(self.cond());
+				let a = try!(self.src());
+				let b = try!(self.src());
+
+				Op::Trap(cond, SrcSrc{src: [a, b]})
+			},
+
+			tag => return Err(self.malformed(&format!("Unknown op tag {}", tag))),
+		})
+	}
+
+	fn pred(&mut self) -> Result<Pred, Error> {
+		Ok(match try!(self.u8()) {
+			0 => Pred::None,
+			1 => Pred::Pred(try!(self.r())),
+			2 => Pred::NotPred(try!(self.r())),
+
+			tag => return Err(self.malformed(&format!("Unknown predicate tag {}", tag))),
+		})
+	}
+}
+
+impl Instr {
+	pub fn encode(&self) -> Vec<u8> {
+		let mut buf = vec!(WIRE_VERSION);
+
+		put_op(&mut buf, &self.op);
+		put_pred(&mut buf, self.pred);
+		buf.push(self.exc);
+		buf.push(self.size);
+
+		buf
+	}
+
+	// Decodes the Instr at the start of bytes, returning it along with the
+	// number of bytes it took up
+	pub fn decode(bytes: &[u8]) -> Result<(Instr, usize), Error> {
+		let mut reader = Reader {
+			bytes: bytes,
+			pos:   0,
+		};
+
+		let version = try!(reader.u8());
+
+		if version != WIRE_VERSION {
+			return Err(Error::MalformedInstr(format!("Unsupported encoding version {}", version)));
+		}
+
+		let op = try!(reader.op());
+		let pred = try!(reader.pred());
+		let exc = try!(reader.u8());
+		let size = try!(reader.u8());
+
+		Ok((Instr{op: op, pred: pred, exc: exc, size: size}, reader.pos))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::{Cond, DstSrc, DstSrcSrc, Ext, Instr, Op, Pred, R, Src, SrcSrc, SrcSrcSrc, SrcSrcTarget};
+	use super::super::super::Error;
+
+	fn dss(dst: R, a: Src, b: Src) -> DstSrcSrc {
+		DstSrcSrc{dst: dst, src: [a, b]}
+	}
+
+	fn every_op() -> Vec<Op> {
+		let args = dss(R::W(2), Src::Reg(R::W(3)), Src::ImmI16(-4));
+		let ds = DstSrc{dst: R::H(5), src: Src::ImmU32(0xDEADBEEF)};
+		let sss = SrcSrcSrc{src: [Src::Reg(R::B(9)), Src::ImmI32(-0x10000), Src::Reg(R::X(1))]};
+
+		vec!(
+			Op::Nop,
+			Op::Add(args), Op::Sub(args), Op::Mul(args), Op::Mulh(args), Op::Mulhu(args),
+			Op::Div(args), Op::Divu(args), Op::Mod(args), Op::Modu(args),
+			Op::And(args), Op::Or(args), Op::Nor(args), Op::Sll(args), Op::Sra(args), Op::Srl(args), Op::Xor(args),
+			Op::Set(Cond::Lt, args),
+			Op::CMov(Cond::Ne, args),
+			Op::Ext(Ext{signed: true, width: 16}, ds),
+			Op::Clz(ds), Op::Clo(ds),
+			Op::Lb(args), Op::Lbs(args), Op::Lh(args), Op::Lw(args),
+			Op::Sb(sss), Op::Sh(sss), Op::Sw(sss),
+			Op::Ld(ds),
+			Op::Fence,
+			Op::Call(Src::Addr(0xFFFFFFFF80001000)),
+			Op::B(Cond::Ge, SrcSrcTarget{src: [Src::Reg(R::TW(1)), Src::ImmI8(-1)], target: Src::Addr(0x80000040)}),
+			Op::Branch{cond:       Cond::Eq,
+			           src:        [Src::Reg(R::W(6)), Src::Reg(R::Zero)],
+			           target:     Src::Addr(0x80710038),
+			           delay_slot: Box::new(Op::Add(dss(R::W(19), Src::Reg(R::W(7)), Src::ImmU32(0))))},
+			Op::Exc,
+			Op::J(Src::Reg(R::W(31))),
+			Op::Trap(Cond::Ne, SrcSrc{src: [Src::Reg(R::W(4)), Src::ImmI16(-2)]}),
+		)
+	}
+
+	fn round_trip(instr: Instr) {
+		let bytes = instr.encode();
+
+		assert_eq!((instr, bytes.len()), Instr::decode(&bytes).unwrap());
+	}
+
+	#[test]
+	fn every_op_round_trips() {
+		for op in every_op().into_iter() {
+			round_trip(Instr{op: op, pred: Pred::None, exc: 0, size: 4});
+		}
+	}
+
+	#[test]
+	fn every_operand_round_trips() {
+		let regs = [R::Ip, R::Discard, R::Zero, R::P(1), R::Pred(0x100000000), R::B(1), R::H(2), R::W(0xFFFF), R::X(4),
+		            R::TP(5), R::TPred(6), R::TB(7), R::TH(8), R::TW(9), R::TX(10)];
+		let srcs = [Src::ImmU8(0xFF), Src::ImmU16(0xFFFE), Src::ImmU32(0xFFFFFFFD), Src::ImmU64(0xFFFFFFFFFFFFFFFC),
+		            Src::ImmI8(-128), Src::ImmI16(-32768), Src::ImmI32(-2147483648), Src::ImmI64(-1), Src::Addr(0x1234)];
+
+		for reg in regs.iter() {
+			round_trip(Instr{op: Op::Ld(DstSrc{dst: *reg, src: Src::Reg(*reg)}), pred: Pred::Pred(*reg), exc: 1, size: 0});
+			round_trip(Instr{op: Op::Nop, pred: Pred::NotPred(*reg), exc: 0, size: 2});
+		}
+
+		for src in srcs.iter() {
+			round_trip(Instr{op: Op::J(*src), pred: Pred::None, exc: 0, size: 8});
+		}
+	}
+
+	#[test]
+	fn decode_reports_length_of_each_instr() {
+		let first = Instr{op: Op::Fence, pred: Pred::None, exc: 0, size: 4};
+		let second = Instr{op: Op::Ld(DstSrc{dst: R::W(1), src: Src::ImmU64(7)}), pred: Pred::None, exc: 0, size: 4};
+
+		let mut bytes = first.encode();
+		bytes.extend_from_slice(&second.encode());
+
+		let (decoded, len) = Instr::decode(&bytes).unwrap();
+		assert_eq!(first, decoded);
+
+		assert_eq!(second, Instr::decode(&bytes[len..]).unwrap().0);
+	}
+
+	#[test]
+	fn malformed_encodings_rejected() {
+		let bytes = Instr{op: Op::Add(dss(R::W(1), Src::ImmU32(1), Src::ImmU32(2))), pred: Pred::None, exc: 0, size: 4}.encode();
+
+		for len in 0..bytes.len() {
+			match Instr::decode(&bytes[..len]) {
+				Err(Error::MalformedInstr(_)) => {},
+				other => panic!("Decoded truncated encoding:  {:?}", other),
+			}
+		}
+
+		let mut bad_version = bytes.clone();
+		bad_version[0] = 0xFF;
+
+		match Instr::decode(&bad_version) {
+			Err(Error::MalformedInstr(_)) => {},
+			other => panic!("Decoded unknown version:  {:?}", other),
+		}
+
+		let branch = |delay_slot| Op::Branch{cond: Cond::Eq, src: [Src::ImmU32(0), Src::ImmU32(0)], target: Src::Addr(0), delay_slot: Box::new(delay_slot)};
+		let nested = Instr{op: branch(branch(Op::Nop)), pred: Pred::None, exc: 0, size: 4}.encode();
+
+		match Instr::decode(&nested) {
+			Err(Error::MalformedInstr(_)) => {},
+			other => panic!("Decoded a branch in a delay slot:  {:?}", other),
+		}
+	}
+}
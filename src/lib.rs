@@ -3,13 +3,17 @@
 #[macro_use]
 extern crate bitflags;
 
+pub mod elf;
 pub mod iisa;
 pub mod mem;
 pub mod mips;
 
+use std::cmp;
 use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
+use std::time::Duration;
 
 bitflags! {
 	flags MemProt: u8 {
@@ -26,19 +30,65 @@ bitflags! {
 
 bitflags! {
 	flags CpuOpt: u8 {
-		const CPU_ENDIAN_BIG    = 0b00000001,
-		const CPU_ENDIAN_LITTLE = 0b00000000,
+		const CPU_ENDIAN_BIG          = 0b00000001,
+		const CPU_ENDIAN_LITTLE       = 0b00000000,
+		const CPU_TRAP_ON_ZERO_FETCH  = 0b00000010,
+		const CPU_RECORD_BRANCHES     = 0b00000100,
+		const CPU_EXIT_AT_END_OF_CODE = 0b00001000,
+	}
+}
+
+impl CpuOpt {
+	pub fn big_endian() -> CpuOpt {
+		CPU_ENDIAN_BIG
+	}
+
+	pub fn little_endian() -> CpuOpt {
+		CPU_ENDIAN_LITTLE
 	}
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ExitReason {
-	CodeHookSignalledStop
+	CodeHookSignalledStop,
+	SingleStep,
+	ZeroInstructionFetch(u64),
+	StopAddressReached(u64),
+	InstructionLimitReached,
+	TimedOut,
+	// addr is where the write landed, and value what was written there
+	WatchpointHit { addr: u64, value: u64 },
+	// The attached watchdog went unkicked for its timeout, so the CPU was reset
+	WatchdogReset,
+	// Control went somewhere outside every executable range
+	PcOutOfBounds(u64),
+	// With CPU_EXIT_AT_END_OF_CODE, straight line code ran out of mapped bytes
+	// to fetch at this pc
+	FellOffEnd(u64),
+	// The replayed run stopped matching its trace at the instruction
+	// insn_count.  expected is the read the trace held next for it, and actual
+	// the (addr, width) the instruction read instead; None for expected means
+	// the trace held nothing more for it, and None for actual that the
+	// instruction finished with expected still unread.  Replay is off after.
+	ReplayDiverged { insn_count: u64, expected: Option<mem::MmioRead>, actual: Option<(u64, u8)> },
 }
 
-#[derive(Clone, Debug)]
+// An input from outside the CPU, stamped with the insn_count of the
+// instruction that took it in.  Bus slave reads are the only such input; the
+// translator's own timer interrupts follow from the cycle count.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReplayEvent {
+	pub insn_count: u64,
+	pub read: mem::MmioRead,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum CpuReg {
 	Pc,
+	// Guest cycles elapsed since reset, as charged by the translator's cost model
+	Cycles,
+	// The whole predicate register file, with P(n) in bit n
+	Predicates,
 	CpuSpecific(u32),
 }
 
@@ -48,6 +98,12 @@ pub enum Error {
 
 	MemAllocation,
 	UnableToFindRange(u64, usize),
+	InvalidRangeSize(u64, u64),
+	// (base, size) of a range past the end of every CPU's address space
+	UnreachableRange(u64, u64),
+	OverlappingRange(u64, u64),
+	BusFault { addr: u64, width: u8, is_write: bool },
+	UnalignedAccess(u64),
 
 	InvalidCpuCookie,
 
@@ -55,33 +111,158 @@ pub enum Error {
 	UnimplementedArchitecture,
 
 	GetRegUnknownReg(CpuReg),
+	// (slots in the file offered, slots the CPU's arch needs)
+	RegisterFileTooSmall(u16, u16),
+	// An instr named a register the register file doesn't have
+	InvalidRegister(iisa::R),
 
 	SetRegValueOutOfRange(CpuReg, u64),
 	SetRegUnknownReg(CpuReg, u64),
 
+	Io(String),
+	MalformedImage(String),
+
 	InvalidPC,
-	VirtualAddrNotMappable(u64),
+	TruncatedInstruction(u64),
+	MalformedInstr(String),
+	FetchTranslationFault(u64),
+	DataTranslationFault(u64),
+	// A buffered store faulted on its way to the bus, after the CPU had moved
+	// past the store at pc
+	StoreFault { pc: u64, fault: Box<Error> },
 
 	PromiseLost,
 }
 
+#[derive(Clone, Debug)]
+pub enum CpuEvent {
+	Started,
+	Stopped(ExitReason),
+	Fault(Error),
+}
+
+// PcOnly traces are a flat run of little endian u64s, one per instruction
+// executed, holding the address it was executed from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+	PcOnly,
+}
+
+// What a CPU does when it fetches an instruction it can't decode.  SkipOne
+// treats it as a nop of the instruction's size, while Trap raises the
+// architecture's reserved instruction exception with the pc still on it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DecodeErrorPolicy {
+	Fail,
+	SkipOne,
+	Trap,
+}
+
+impl Default for DecodeErrorPolicy {
+	fn default() -> DecodeErrorPolicy {
+		DecodeErrorPolicy::Fail
+	}
+}
+
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct BranchEdge {
+	pub from: u64,
+	pub to: u64,
+	pub fallthrough: bool,
+}
+
+pub type StepDiff = (Vec<iisa::Instr>, Vec<(CpuReg, u64, u64)>, ExitReason);
+
+// Limits for a single System::run; they're dropped again once it returns.
+// Execution stops before an instruction at any of stop_at, except the one the
+// run starts from, so a run can resume from where the last one stopped.
+#[derive(Clone, Debug, Default)]
+pub struct RunConfig {
+	pub max_insns: Option<u64>,
+	pub timeout: Option<Duration>,
+	pub stop_at: Vec<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub enum RunStop {
+	Exited(ExitReason),
+	Fault(Error),
+}
+
+#[derive(Clone, Debug)]
+pub struct RunOutcome {
+	pub stop: RunStop,
+	pub pc: u64,
+}
+
+// The pc, every architectural register and the predicate bits, all captured at
+// the same instruction boundary.  Snapshots of CPUs of the same architecture
+// compare (and hash) equal exactly when that state does.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RegisterSnapshot {
+	pc: u64,
+	regs: Vec<(CpuReg, u64)>,
+}
+
+impl RegisterSnapshot {
+	pub fn new(pc: u64, regs: Vec<(CpuReg, u64)>) -> RegisterSnapshot {
+		RegisterSnapshot {
+			pc:   pc,
+			regs: regs,
+		}
+	}
+
+	pub fn pc(&self) -> u64 {
+		self.pc
+	}
+
+	pub fn get(&self, reg: &CpuReg) -> Option<u64> {
+		self.regs.iter().find(|&&(ref snap_reg, _)| snap_reg == reg).map(|&(_, value)| value)
+	}
+}
+
+pub type CpuFactory = Box<Fn(CpuOpt, &mut mem::BusMatrix) -> Result<Box<Cpu>, Error>>;
+
 pub struct System {
 	fsb: mem::BusMatrix,
 	cpus: BTreeMap<usize, Box<Cpu>>,
+	cpu_arches: BTreeMap<usize, Arch>,
+	cpu_factories: Vec<(Arch, CpuFactory)>,
 	next_cpu_handle: usize,
+	auto_retire_cpus: bool,
+	retired_cpus: Arc<Mutex<Vec<usize>>>,
+	loaded_extents: Vec<(u64, u64)>,
 }
 
+// A CPU otherwise lives (along with its backend thread) until the System that
+// owns it is dropped, regardless of what happens to its cookie.  Cookies
+// handed out while auto retire is enabled instead queue their CPU to be shut
+// down and removed the next time the System looks at its set of CPUs.
 pub struct CpuCookie {
 	handle: usize,
+	retire_on_drop: Option<Arc<Mutex<Vec<usize>>>>,
+}
+
+impl Drop for CpuCookie {
+	fn drop(&mut self) {
+		if let Some(ref retired_cpus) = self.retire_on_drop {
+			if let Ok(mut retired_cpus) = retired_cpus.lock() {
+				retired_cpus.push(self.handle);
+			}
+		}
+	}
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Arch {
 	Mips(mips::Arch),
+	Custom(&'static str),
 }
 
 pub enum TraceExitHint {
 	ContinueExecution,
-	StopExecution
+	StopExecution,
+	RaiseException(u8),
 }
 
 pub struct Future<T> {
@@ -124,43 +305,179 @@ impl<T: Clone> Promise<T> {
 			rx: rx,
 		}
 	}
-}
 
-fn create_cpu(opts: CpuOpt, arch: Arch, fsb: &mut mem::BusMatrix) -> Result<Box<Cpu>, Error> {
-	match arch {
-		Arch::Mips(mips_info) => {
-			mips::mips_cpu_factory(opts, mips_info, fsb)
-		},
+	// Futures of other are signalled along with this promise's own
+	fn join(&mut self, other: Promise<T>) {
+		self.future_channels.extend(other.future_channels);
 	}
 }
 
+fn check_fits(address_bits: u8, base: u64, size: u64) -> Result<(), Error> {
+	let fits = (address_bits >= 64) || base.checked_add(size).map_or(false, |end| end <= (1 << address_bits));
+
+	if fits { Ok(()) } else { Err(Error::UnreachableRange(base, size)) }
+}
+
 impl System {
 	pub fn new() -> System {
-		System {
+		let mut system = System {
 			fsb: Default::default(),
 			cpus: BTreeMap::new(),
+			cpu_arches: BTreeMap::new(),
+			cpu_factories: Vec::new(),
 			next_cpu_handle: 0,
+			auto_retire_cpus: false,
+			retired_cpus: Arc::new(Mutex::new(Vec::new())),
+			loaded_extents: Vec::new(),
+		};
+
+		for mips_arch in [mips::Arch::R2000, mips::Arch::Sys161, mips::Arch::VR4300, mips::Arch::Mips4Kc].iter() {
+			let mips_arch = mips_arch.clone();
+
+			system.register_cpu_factory(Arch::Mips(mips_arch.clone()), Box::new(move |opts, fsb| {
+				mips::mips_cpu_factory(opts, mips_arch.clone(), fsb)
+			}));
+		}
+
+		system
+	}
+
+	pub fn register_cpu_factory(&mut self, arch: Arch, factory: CpuFactory) {
+		self.cpu_factories.retain(|&(ref registered_arch, _)| *registered_arch != arch);
+
+		self.cpu_factories.push((arch, factory));
+	}
+
+	fn create_cpu(&mut self, opts: CpuOpt, arch: Arch) -> Result<Box<Cpu>, Error> {
+		for &(ref registered_arch, ref factory) in self.cpu_factories.iter() {
+			if *registered_arch == arch {
+				return factory(opts, &mut self.fsb);
+			}
+		}
+
+		Err(Error::UnimplementedArchitecture)
+	}
+
+	// Nothing can be out of reach before there are CPUs to reach it
+	fn check_reachable(&self, base: u64, size: u64) -> Result<(), Error> {
+		let mut widest = None;
+
+		for (_, cpu) in self.cpus.iter() {
+			widest = cmp::max(widest, Some(try!(cpu.address_bits())));
+		}
+
+		match widest {
+			Some(bits) => check_fits(bits, base, size),
+			None       => Ok(()),
 		}
 	}
 
 	pub fn add_mappable_range(&mut self, prot: MemProt, base: u64, size: u64) -> Result<(), Error> {
+		try!(self.check_reachable(base, size));
+
 		self.fsb.add_mappable_range(base, size, prot)
 	}
 
-	fn register_cpu_no_throw(&mut self, cpu: Box<Cpu>) -> CpuCookie {
+	// Maps data as RAM at base without allocating through libc; it's freed once
+	// the system and every CPU are done with it
+	pub fn add_ram_range(&mut self, base: u64, data: Vec<u8>, prot: MemProt) -> Result<(), Error> {
+		try!(self.check_reachable(base, data.len() as u64));
+
+		self.fsb.add_owned_range(base, data, prot)
+	}
+
+	pub fn add_alias(&mut self, existing_base: u64, alias_base: u64) -> Result<(), Error> {
+		let existing = self.fsb.range_extents().into_iter().find(|&(base, _)| base == existing_base);
+
+		if let Some((_, size)) = existing {
+			try!(self.check_reachable(alias_base, size));
+		}
+
+		self.fsb.add_alias(existing_base, alias_base)
+	}
+
+	pub fn set_write_fault_handler(&mut self, handler: Option<mem::WriteFaultHandler>) {
+		self.fsb.set_write_fault_handler(handler)
+	}
+
+	pub fn probe(&self, addr: u64, access: MemProt) -> mem::ProbeResult {
+		self.fsb.probe(addr, access)
+	}
+
+	pub fn total_guest_memory(&self) -> u64 {
+		self.fsb.total_mapped_bytes()
+	}
+
+	// Every byte of RAM mapped at the same physical address in both systems
+	// that holds a different value, as (addr, self_byte, other_byte)
+	pub fn diff_memory(&self, other: &System) -> Vec<(u64, u8, u8)> {
+		self.fsb.diff_mapped_bytes(&other.fsb)
+	}
+
+	pub fn set_auto_retire_cpus(&mut self, enabled: bool) {
+		self.auto_retire_cpus = enabled;
+	}
+
+	fn reap_retired_cpus(&mut self) {
+		let retired_handles: Vec<usize> = match self.retired_cpus.lock() {
+			Ok(mut retired_cpus) => retired_cpus.drain(..).collect(),
+			Err(_)               => return,
+		};
+
+		for handle in retired_handles {
+			if let Some(mut cpu) = self.cpus.remove(&handle) {
+				cpu.shutdown();
+			}
+
+			let _ = self.cpu_arches.remove(&handle);
+		}
+	}
+
+	pub fn active_cpu_count(&mut self) -> usize {
+		self.reap_retired_cpus();
+
+		self.cpus.len()
+	}
+
+	fn register_cpu_no_throw(&mut self, cpu: Box<Cpu>, arch: Arch) -> CpuCookie {
+		self.reap_retired_cpus();
+
 		let this_handle = self.next_cpu_handle;
 
 		self.next_cpu_handle += 1;
 
 		let _ = self.cpus.insert(this_handle, cpu);
+		let _ = self.cpu_arches.insert(this_handle, arch);
 
-		CpuCookie{handle: this_handle}
+		CpuCookie {
+			handle: this_handle,
+			retire_on_drop: if self.auto_retire_cpus { Some(self.retired_cpus.clone()) } else { None },
+		}
 	}
 
 	pub fn register_cpu(&mut self, opts: CpuOpt, arch: Arch) -> Result<CpuCookie, Error> {
-		let cpu = try!(create_cpu(opts, arch, &mut self.fsb));
+		if (opts.bits() & !CpuOpt::all().bits()) != 0 {
+			return Err(Error::OptNotSupported(opts));
+		}
 
-		Ok(self.register_cpu_no_throw(cpu))
+		let cpu = try!(self.create_cpu(opts, arch.clone()));
+
+		// Whatever was mapped before this CPU came along has to be in its reach
+		let bits = try!(cpu.address_bits());
+
+		for (base, size) in self.fsb.range_extents() {
+			try!(check_fits(bits, base, size));
+		}
+
+		Ok(self.register_cpu_no_throw(cpu, arch))
+	}
+
+	// The arch the CPU was registered as
+	pub fn cpu_arch(&self, cpu_cookie: &CpuCookie) -> Result<Arch, Error> {
+		match self.cpu_arches.get(&cpu_cookie.handle) {
+			Some(arch) => Ok(arch.clone()),
+			None       => Err(Error::InvalidCpuCookie),
+		}
 	}
 
 	fn get_cpu(&mut self, cookie: &CpuCookie) -> Result<&mut Box<Cpu>, Error> {
@@ -170,11 +487,27 @@ impl System {
 		}
 	}
 
+	// A pc is in the address space if it's the zero or sign extension of an
+	// address that is
 	pub fn set_cpu_reg(&mut self, cpu_cookie: &CpuCookie, reg: CpuReg, value: u64) -> Result<(), Error> {
-		try!(self.get_cpu(cpu_cookie)).set_reg(reg, value)
+		let cpu = try!(self.get_cpu(cpu_cookie));
+
+		if reg == CpuReg::Pc {
+			let bits = try!(cpu.address_bits());
+
+			if bits < 64 && (value >> bits) != 0 && ((value as i64) >> (bits - 1)) != -1 {
+				return Err(Error::SetRegValueOutOfRange(reg, value));
+			}
+		}
+
+		cpu.set_reg(reg, value)
+	}
+
+	pub fn address_space_bits(&mut self, cpu_cookie: &CpuCookie) -> Result<u8, Error> {
+		try!(self.get_cpu(cpu_cookie)).address_bits()
 	}
 
-	pub fn add_block_hook_all(&mut self, hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
+	pub fn add_block_hook_all(&mut self, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
 		for (_, cpu) in self.cpus.iter_mut() {
 			try!(cpu.add_block_hook_all(hook.clone()));
 		}
@@ -182,7 +515,7 @@ impl System {
 		Ok(())
 	}
 
-	pub fn add_code_hook_single(&mut self, base: u64, hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
+	pub fn add_code_hook_single(&mut self, base: u64, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>) -> Result<(), Error> {
 		for(_, cpu) in self.cpus.iter_mut() {
 			try!(cpu.add_code_hook_single(base, hook.clone()));
 		}
@@ -190,17 +523,283 @@ impl System {
 		Ok(())
 	}
 
+	pub fn add_decode_hook(&mut self, hook: Arc<Mutex<Fn(u64, &[iisa::Instr]) + Send>>) -> Result<(), Error> {
+		for (_, cpu) in self.cpus.iter_mut() {
+			try!(cpu.add_decode_hook(hook.clone()));
+		}
+
+		Ok(())
+	}
+
+	// rewriter is handed the address and instrs of every fetch after it's
+	// decoded and may change them before they execute
+	pub fn add_decode_rewriter(&mut self, rewriter: Arc<Mutex<Fn(u64, &mut Vec<iisa::Instr>) + Send>>) -> Result<(), Error> {
+		for (_, cpu) in self.cpus.iter_mut() {
+			try!(cpu.add_decode_rewriter(rewriter.clone()));
+		}
+
+		Ok(())
+	}
+
+	// hook is called with the pc and the op before each op of class executes
+	pub fn add_op_class_hook(&mut self, class: iisa::OpClass, hook: Arc<Mutex<Fn(u64, &iisa::Op) -> TraceExitHint + Send>>) -> Result<(), Error> {
+		for (_, cpu) in self.cpus.iter_mut() {
+			try!(cpu.add_op_class_hook(class, hook.clone()));
+		}
+
+		Ok(())
+	}
+
+	// hook is called with the pc, old value and new value whenever an
+	// instruction changes reg
+	pub fn add_reg_watch(&mut self, cpu_cookie: &CpuCookie, reg: CpuReg, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint + Send>>) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).add_reg_watch(reg, hook)
+	}
+
+	// CPUs are silent by default; this routes their internal diagnostics (like
+	// a backend thread exiting unexpectedly) to hook instead.
+	pub fn set_diagnostic_hook(&mut self, hook: Arc<Mutex<Fn(&str) + Send>>) -> Result<(), Error> {
+		for (_, cpu) in self.cpus.iter_mut() {
+			try!(cpu.set_diagnostic_hook(hook.clone()));
+		}
+
+		Ok(())
+	}
+
+	pub fn subscribe_events(&mut self, cpu_cookie: &CpuCookie) -> Result<mpsc::Receiver<CpuEvent>, Error> {
+		try!(self.get_cpu(cpu_cookie)).subscribe_events()
+	}
+
+	pub fn is_cpu_running(&mut self, cpu_cookie: &CpuCookie) -> Result<bool, Error> {
+		try!(self.get_cpu(cpu_cookie)).is_running()
+	}
+
+	// Resolves the next time the CPU stops, however it was started, with the
+	// same result as whoever started it; any number may be waiting at once
+	pub fn on_stop(&mut self, cpu_cookie: &CpuCookie) -> Result<Future<ExitReason>, Error> {
+		try!(self.get_cpu(cpu_cookie)).on_stop()
+	}
+
+	// Returns once every memory map change made so far is visible to the CPU,
+	// even if it's in the middle of executing
+	pub fn sync_memory_map(&mut self, cpu_cookie: &CpuCookie) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).sync_memory_map()
+	}
+
+	pub fn reset_cpu(&mut self, cpu_cookie: &CpuCookie) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).reset()
+	}
+
 	pub fn execute(&mut self, cpu_cookie: &CpuCookie) -> Result<ExitReason, Error> {
 		try!(self.get_cpu(cpu_cookie)).execute()
 	}
 
+	pub fn run(&mut self, cpu_cookie: &CpuCookie, config: RunConfig) -> Result<RunOutcome, Error> {
+		try!(self.get_cpu(cpu_cookie)).run(config)
+	}
+
+	// Executes until the instruction that writes to any of [addr, addr + len)
+	// has completed
+	pub fn run_until_write(&mut self, cpu_cookie: &CpuCookie, addr: u64, len: u64) -> Result<ExitReason, Error> {
+		try!(self.get_cpu(cpu_cookie)).run_until_write(addr, len)
+	}
+
+	pub fn step_one_with_diff(&mut self, cpu_cookie: &CpuCookie) -> Result<StepDiff, Error> {
+		try!(self.get_cpu(cpu_cookie)).step_one_with_diff()
+	}
+
+	pub fn disassemble_at(&mut self, cpu_cookie: &CpuCookie, vaddr: u64, max_insns: usize) -> Result<Vec<(u64, iisa::Instr)>, Error> {
+		try!(self.get_cpu(cpu_cookie)).disassemble_at(vaddr, max_insns)
+	}
+
+	pub fn register_snapshot(&mut self, cpu_cookie: &CpuCookie) -> Result<RegisterSnapshot, Error> {
+		try!(self.get_cpu(cpu_cookie)).register_snapshot()
+	}
+
+	pub fn restore_register_snapshot(&mut self, cpu_cookie: &CpuCookie, snapshot: &RegisterSnapshot) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).restore_register_snapshot(snapshot)
+	}
+
+	// Installs new as the CPU's whole register file (pc included) between two
+	// instructions, handing back the one it replaced
+	pub fn swap_context(&mut self, cpu_cookie: &CpuCookie, new: iisa::RegisterFile) -> Result<iisa::RegisterFile, Error> {
+		try!(self.get_cpu(cpu_cookie)).swap_context(new)
+	}
+
+	pub fn load_iisa(&mut self, cpu_cookie: &CpuCookie, program: Vec<iisa::Instr>, base: u64) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).load_iisa(program, base)
+	}
+
+	pub fn current_insn_bytes(&mut self, cpu_cookie: &CpuCookie) -> Result<Vec<u8>, Error> {
+		try!(self.get_cpu(cpu_cookie)).current_insn_bytes()
+	}
+
+	pub fn branch_edges(&mut self, cpu_cookie: &CpuCookie) -> Result<Vec<BranchEdge>, Error> {
+		try!(self.get_cpu(cpu_cookie)).branch_edges()
+	}
+
+	pub fn cycle_count(&mut self, cpu_cookie: &CpuCookie) -> Result<u64, Error> {
+		try!(self.get_cpu(cpu_cookie)).cycle_count()
+	}
+
+	pub fn enable_trace_file(&mut self, cpu_cookie: &CpuCookie, path: &Path, format: TraceFormat) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).enable_trace_file(path, format)
+	}
+
+	pub fn set_decode_error_policy(&mut self, cpu_cookie: &CpuCookie, policy: DecodeErrorPolicy) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).set_decode_error_policy(policy)
+	}
+
+	pub fn add_bus_slave(&mut self, base: u64, size: u64, slave: Arc<Mutex<mem::BusSlave + Send>>) -> Result<(), Error> {
+		try!(self.check_reachable(base, size));
+
+		self.fsb.add_bus_slave(base, size, slave)
+	}
+
+	// Maps watchdog at base and has the CPU reset itself whenever the guest
+	// goes its timeout in cycles without kicking it
+	pub fn add_watchdog(&mut self, cpu_cookie: &CpuCookie, base: u64, watchdog: Arc<Mutex<mem::Watchdog>>) -> Result<(), Error> {
+		try!(self.check_reachable(base, mem::WATCHDOG_SIZE));
+
+		// Resolved first so a bad cookie doesn't leave the slave mapped
+		try!(self.get_cpu(cpu_cookie));
+
+		try!(self.fsb.add_bus_slave(base, mem::WATCHDOG_SIZE, watchdog.clone()));
+
+		try!(self.get_cpu(cpu_cookie)).set_watchdog(Some(watchdog))
+	}
+
+	// Stores are held in a buffer of this many entries until a fence (or a
+	// lack of room) drains them to memory.  Zero, the default, means no buffer.
+	pub fn set_store_buffer_entries(&mut self, cpu_cookie: &CpuCookie, entries: usize) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).set_store_buffer_entries(entries)
+	}
+
+	// Oldest first
+	pub fn pending_stores(&mut self, cpu_cookie: &CpuCookie) -> Result<Vec<iisa::PendingStore>, Error> {
+		try!(self.get_cpu(cpu_cookie)).pending_stores()
+	}
+
+	// Logs every value the CPU reads from a bus slave until stop_recording,
+	// which hands back the trace
+	pub fn start_recording(&mut self, cpu_cookie: &CpuCookie) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).start_recording()
+	}
+
+	pub fn stop_recording(&mut self, cpu_cookie: &CpuCookie) -> Result<Vec<ReplayEvent>, Error> {
+		try!(self.get_cpu(cpu_cookie)).stop_recording()
+	}
+
+	// Answers the CPU's bus slave reads from trace, at the instruction counts
+	// they were recorded at, rather than from the slaves themselves.  Counts
+	// are the CPU's own, so replay from the state the recording started in.
+	pub fn start_replay(&mut self, cpu_cookie: &CpuCookie, trace: Vec<ReplayEvent>) -> Result<(), Error> {
+		try!(self.get_cpu(cpu_cookie)).start_replay(trace)
+	}
+
 	pub fn get_cpu_reg(&mut self, cpu_cookie: &CpuCookie, reg: CpuReg) -> Result<u64, Error> {
 		try!(self.get_cpu(cpu_cookie)).get_reg(reg)
 	}
 
+	pub fn get_cpu_reg_at_boundary(&mut self, cpu_cookie: &CpuCookie, reg: CpuReg) -> Result<(u64, u64), Error> {
+		try!(self.get_cpu(cpu_cookie)).get_reg_at_boundary(reg)
+	}
+
 	pub fn set_range(&mut self, incoming: &[u8], base_addr: u64) -> Result<(), Error> {
 		self.fsb.set_range(incoming, base_addr)
 	}
+
+	// Images are written into already mapped memory.  Any number of them can be
+	// loaded as long as none overlaps another, so a memory image can be built
+	// up from several (say a bootloader and a kernel); an image that would
+	// overlap fails with OverlappingRange and loads nothing.
+	pub fn load_flat(&mut self, bytes: &[u8], base: u64) -> Result<(), Error> {
+		self.load_segments(&[elf::Segment{ addr: base, data: bytes, mem_size: bytes.len() as u64 }])
+	}
+
+	// Returns the entry point
+	pub fn load_elf(&mut self, bytes: &[u8]) -> Result<u64, Error> {
+		self.load_elf_at(bytes, 0)
+	}
+
+	// As load_elf, but with every segment (and the entry point) moved by offset
+	pub fn load_elf_at(&mut self, bytes: &[u8], offset: u64) -> Result<u64, Error> {
+		let image = try!(elf::parse(bytes));
+
+		let entry = match image.entry.checked_add(offset) {
+			Some(entry) => entry,
+			None        => return Err(Error::MalformedImage(format!("Entry point {:#x} moved by {:#x} overflows", image.entry, offset))),
+		};
+
+		let mut segments = Vec::new();
+
+		for segment in image.segments.into_iter() {
+			let addr = match segment.addr.checked_add(offset) {
+				Some(addr) => addr,
+				None       => return Err(Error::MalformedImage(format!("Segment @ {:#x} moved by {:#x} overflows", segment.addr, offset))),
+			};
+
+			segments.push(elf::Segment {
+				addr:     addr,
+				data:     segment.data,
+				mem_size: segment.mem_size,
+			});
+		}
+
+		try!(self.load_segments(&segments));
+
+		Ok(entry)
+	}
+
+	fn load_segments(&mut self, segments: &[elf::Segment]) -> Result<(), Error> {
+		let mut extents = self.loaded_extents.clone();
+
+		for segment in segments.iter().filter(|segment| segment.mem_size != 0) {
+			let end = match segment.addr.checked_add(segment.mem_size) {
+				Some(end) => end,
+				None      => return Err(Error::InvalidRangeSize(segment.addr, segment.mem_size)),
+			};
+
+			if extents.iter().any(|&(base, size)| segment.addr < (base + size) && base < end) {
+				return Err(Error::OverlappingRange(segment.addr, segment.mem_size));
+			}
+
+			// Checked up front so a bogus mem_size is never allocated for the bss
+			if segment.mem_size > (usize::MAX as u64) {
+				return Err(Error::InvalidRangeSize(segment.addr, segment.mem_size));
+			}
+
+			try!(self.fsb.find_range(segment.addr, segment.mem_size as usize));
+
+			extents.push((segment.addr, segment.mem_size));
+		}
+
+		for segment in segments.iter() {
+			try!(self.fsb.set_range(segment.data, segment.addr));
+
+			let bss_size = segment.mem_size - (segment.data.len() as u64);
+
+			if bss_size != 0 {
+				try!(self.fsb.set_range(&vec!(0; bss_size as usize), segment.addr + (segment.data.len() as u64)));
+			}
+		}
+
+		self.loaded_extents = extents;
+
+		Ok(())
+	}
+
+	pub fn set_u16(&mut self, addr: u64, value: u16, endian: mem::Endian) -> Result<(), Error> {
+		self.fsb.set_u16(addr, value, endian)
+	}
+
+	pub fn set_u32(&mut self, addr: u64, value: u32, endian: mem::Endian) -> Result<(), Error> {
+		self.fsb.set_u32(addr, value, endian)
+	}
+
+	pub fn set_u64(&mut self, addr: u64, value: u64, endian: mem::Endian) -> Result<(), Error> {
+		self.fsb.set_u64(addr, value, endian)
+	}
 }
 
 impl Drop for System {
@@ -214,13 +813,2195 @@ impl Drop for System {
 pub trait Cpu {
 	fn execute(&mut self) -> Result<ExitReason, Error>;
 
+	fn run(&mut self, config: RunConfig) -> Result<RunOutcome, Error>;
+
+	fn run_until_write(&mut self, addr: u64, len: u64) -> Result<ExitReason, Error>;
+
+	fn step_one_with_diff(&mut self) -> Result<StepDiff, Error>;
+
+	fn register_snapshot(&self) -> Result<RegisterSnapshot, Error>;
+
+	fn restore_register_snapshot(&mut self, snapshot: &RegisterSnapshot) -> Result<(), Error>;
+
+	fn swap_context(&mut self, new: iisa::RegisterFile) -> Result<iisa::RegisterFile, Error>;
+
+	fn disassemble_at(&self, addr: u64, max_insns: usize) -> Result<Vec<(u64, iisa::Instr)>, Error>;
+
+	fn load_iisa(&mut self, program: Vec<iisa::Instr>, base: u64) -> Result<(), Error>;
+
+	fn current_insn_bytes(&self) -> Result<Vec<u8>, Error>;
+
+	fn branch_edges(&self) -> Result<Vec<BranchEdge>, Error>;
+
+	fn cycle_count(&self) -> Result<u64, Error>;
+
+	fn enable_trace_file(&mut self, path: &Path, format: TraceFormat) -> Result<(), Error>;
+
+	fn set_decode_error_policy(&mut self, policy: DecodeErrorPolicy) -> Result<(), Error>;
+
+	fn set_watchdog(&mut self, watchdog: Option<Arc<Mutex<mem::Watchdog>>>) -> Result<(), Error>;
+
+	fn set_store_buffer_entries(&mut self, entries: usize) -> Result<(), Error>;
+
+	fn pending_stores(&self) -> Result<Vec<iisa::PendingStore>, Error>;
+
+	fn start_recording(&mut self) -> Result<(), Error>;
+	fn stop_recording(&mut self) -> Result<Vec<ReplayEvent>, Error>;
+	fn start_replay(&mut self, trace: Vec<ReplayEvent>) -> Result<(), Error>;
+
 	fn get_reg(&self, reg: CpuReg) -> Result<u64, Error>;
 
+	fn address_bits(&self) -> Result<u8, Error>;
+
+	// Returns (value, pc), both captured at the same instruction boundary
+	fn get_reg_at_boundary(&self, reg: CpuReg) -> Result<(u64, u64), Error>;
+
 	fn set_reg(&mut self, reg: CpuReg, value: u64) -> Result<(), Error>;
 
-	fn add_block_hook_all(&mut self, hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>) -> Result<(), Error>;
-	fn add_code_hook_single(&mut self, base: u64, hook: Arc<Mutex<Fn(u64, u64) -> TraceExitHint>>) -> Result<(), Error>;
+	fn add_block_hook_all(&mut self, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>) -> Result<(), Error>;
+	fn add_code_hook_single(&mut self, base: u64, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint>>) -> Result<(), Error>;
+
+	fn add_decode_hook(&mut self, hook: Arc<Mutex<Fn(u64, &[iisa::Instr]) + Send>>) -> Result<(), Error>;
+	fn add_decode_rewriter(&mut self, hook: Arc<Mutex<Fn(u64, &mut Vec<iisa::Instr>) + Send>>) -> Result<(), Error>;
+
+	fn add_op_class_hook(&mut self, class: iisa::OpClass, hook: Arc<Mutex<Fn(u64, &iisa::Op) -> TraceExitHint + Send>>) -> Result<(), Error>;
+
+	fn add_reg_watch(&mut self, reg: CpuReg, hook: Arc<Mutex<Fn(u64, u64, u64) -> TraceExitHint + Send>>) -> Result<(), Error>;
+
+	fn set_diagnostic_hook(&mut self, hook: Arc<Mutex<Fn(&str) + Send>>) -> Result<(), Error>;
+
+	fn subscribe_events(&mut self) -> Result<mpsc::Receiver<CpuEvent>, Error>;
+
+	fn is_running(&self) -> Result<bool, Error>;
+
+	fn on_stop(&mut self) -> Result<Future<ExitReason>, Error>;
+
+	fn sync_memory_map(&mut self) -> Result<(), Error>;
+
+	fn reset(&mut self) -> Result<(), Error>;
 
 	fn shutdown(&mut self);
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use mem::BusSlave;
+
+	use std::collections::HashSet;
+	use std::thread;
+
+	const ROM_BASE: u64 = 0x1FC00000;
+	const ROM_SIZE: u64 = 1024 * 1024;
+	const ROM_VIRT: u64 = ROM_BASE + 0xA0000000;
+
+	const ORI_AT_AT_0X3456_EB: [u8; 4] = [0x34, 0x21, 0x34, 0x56];
+
+	const RAM_BASE: u64 = 0x00000000;
+	const RAM_SIZE: u64 = 64 * 1024;
+
+	fn map_code(system: &mut System, base: u64, size: u64) {
+		system.add_mappable_range(PROT_ALL, base, size).unwrap();
+		system.set_range(&ORI_AT_AT_0X3456_EB, base).unwrap();
+	}
+
+	// Writes code a big endian word at a time, starting at base
+	fn write_code(system: &mut System, base: u64, code: &[u32]) {
+		for (i, word) in code.iter().enumerate() {
+			system.set_u32(base + ((i as u64) * 4), *word, mem::Endian::Big).unwrap();
+		}
+	}
+
+	// Maps the whole ROM with code at its base
+	fn load_rom(system: &mut System, code: &[u32]) {
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+
+		write_code(system, ROM_BASE, code);
+	}
+
+	fn register_r2000(system: &mut System) -> CpuCookie {
+		system.register_cpu(CPU_ENDIAN_BIG, Arch::Mips(mips::Arch::R2000)).unwrap()
+	}
+
+	fn stop_at(system: &mut System, vaddr: u64) {
+		system.add_code_hook_single(vaddr, Arc::new(Mutex::new(|_, _, _| {
+			TraceExitHint::StopExecution
+		}))).unwrap();
+	}
+
+	fn assert_executes_at(system: &mut System, cpu: &CpuCookie, base: u64) {
+		stop_at(system, base + 0xA0000000);
+
+		system.set_cpu_reg(cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(cpu, CpuReg::Pc, base + 0xA0000000).unwrap();
+
+		system.execute(cpu).unwrap();
+
+		assert_eq!(0x3456, system.get_cpu_reg(cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn map_then_register() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+		map_code(&mut system, RAM_BASE, RAM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		assert_executes_at(&mut system, &cpu, ROM_BASE);
+		assert_executes_at(&mut system, &cpu, RAM_BASE);
+	}
+
+	#[test]
+	fn register_then_map() {
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+		map_code(&mut system, RAM_BASE, RAM_SIZE);
+
+		assert_executes_at(&mut system, &cpu, ROM_BASE);
+		assert_executes_at(&mut system, &cpu, RAM_BASE);
+	}
+
+	#[test]
+	fn map_register_interleaved() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+		let cpu_a = register_r2000(&mut system);
+		map_code(&mut system, RAM_BASE, RAM_SIZE);
+		let cpu_b = register_r2000(&mut system);
+
+		for cpu in [cpu_a, cpu_b].iter() {
+			assert_executes_at(&mut system, cpu, ROM_BASE);
+			assert_executes_at(&mut system, cpu, RAM_BASE);
+		}
+	}
+
+	#[test]
+	fn register_map_interleaved() {
+		let mut system = System::new();
+
+		let cpu_a = register_r2000(&mut system);
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+		let cpu_b = register_r2000(&mut system);
+		map_code(&mut system, RAM_BASE, RAM_SIZE);
+
+		for cpu in [cpu_a, cpu_b].iter() {
+			assert_executes_at(&mut system, cpu, ROM_BASE);
+			assert_executes_at(&mut system, cpu, RAM_BASE);
+		}
+	}
+
+	#[test]
+	fn mult_hi_lo() {
+		const MULT_A0_A1_EB: [u8; 4] = [0x00, 0x85, 0x00, 0x18];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+		system.set_range(&MULT_A0_A1_EB, ROM_BASE).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xFFFFFFFE).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(5), 0x00010000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		system.execute(&cpu).unwrap();
+
+		assert_eq!(0xFFFFFFFF, system.get_cpu_reg(&cpu, mips::REG_HI).unwrap());
+		assert_eq!(0xFFFE0000, system.get_cpu_reg(&cpu, mips::REG_LO).unwrap());
+
+		system.set_cpu_reg(&cpu, mips::REG_HI, 0x1234).unwrap();
+		system.set_cpu_reg(&cpu, mips::REG_LO, 0x5678).unwrap();
+
+		assert_eq!(0x1234, system.get_cpu_reg(&cpu, mips::REG_HI).unwrap());
+		assert_eq!(0x5678, system.get_cpu_reg(&cpu, mips::REG_LO).unwrap());
+	}
+
+	#[test]
+	fn cycle_count_sums_op_costs() {
+		const CODE: [u32; 4] = [
+			0x34213456, // ori $at, $at, 0x3456
+			0x8c880010, // lw $t0, 16($a0)
+			0x00850018, // mult $a0, $a1
+			0x24210001, // addiu $at, $at, 1
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, RAM_SIZE).unwrap();
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 0xC);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0x80000000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(0, system.cycle_count(&cpu).unwrap());
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		// 1 (ori) + 2 (lw) + 12 (mult) + 1 (addiu)
+		assert_eq!(16, system.cycle_count(&cpu).unwrap());
+
+		system.reset_cpu(&cpu).unwrap();
+
+		assert_eq!(0, system.cycle_count(&cpu).unwrap());
+	}
+
+	#[test]
+	fn cycles_register_advances() {
+		let mut system = System::new();
+
+		map_loop_code(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		assert_eq!(0, system.get_cpu_reg(&cpu, CpuReg::Cycles).unwrap());
+
+		run_loop(&mut system, &cpu, 3);
+
+		let cycles = system.get_cpu_reg(&cpu, CpuReg::Cycles).unwrap();
+
+		assert!(cycles > 0);
+		assert_eq!(system.cycle_count(&cpu).unwrap(), cycles);
+
+		system.set_cpu_reg(&cpu, CpuReg::Cycles, 0x1000).unwrap();
+
+		assert_eq!(0x1000, system.cycle_count(&cpu).unwrap());
+	}
+
+	#[test]
+	fn cycles_register_wraps() {
+		let mut system = System::new();
+
+		map_loop_code(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Cycles, u64::MAX - 1).unwrap();
+
+		run_loop(&mut system, &cpu, 3);
+
+		assert!(system.get_cpu_reg(&cpu, CpuReg::Cycles).unwrap() < 0x100);
+	}
+
+	#[test]
+	fn compare_match_raises_timer_interrupt() {
+		const CODE: [u32; 2] = [
+			0x1000FFFF, // beq $zero, $zero, -4
+			0x24210001, // addiu $at, $at, 1
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, RAM_SIZE).unwrap();
+		load_rom(&mut system, &CODE);
+
+		// addiu $zero, $zero, 0 at the general exception vector
+		system.set_u32(RAM_BASE + 0x80, 0x24000000, mem::Endian::Big).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, 0x80000080);
+
+		// Timer interrupt unmasked and interrupts enabled; each trip around the
+		// loop costs two cycles
+		system.set_cpu_reg(&cpu, mips::REG_CP0_STATUS, 0x00008001).unwrap();
+		system.set_cpu_reg(&cpu, mips::REG_CP0_COMPARE, 10).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		// The hooked instruction at the vector still executes before stopping
+		assert_eq!(0x80000084, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(5, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(11, system.get_cpu_reg(&cpu, mips::REG_CP0_COUNT).unwrap());
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&cpu, mips::REG_CP0_EPC).unwrap());
+		assert_eq!(0x00008000, system.get_cpu_reg(&cpu, mips::REG_CP0_CAUSE).unwrap());
+		assert_eq!(0x00008004, system.get_cpu_reg(&cpu, mips::REG_CP0_STATUS).unwrap());
+
+		// Rewriting Compare acknowledges the interrupt
+		system.set_cpu_reg(&cpu, mips::REG_CP0_COMPARE, 100).unwrap();
+
+		assert_eq!(0, system.get_cpu_reg(&cpu, mips::REG_CP0_CAUSE).unwrap());
+	}
+
+	#[test]
+	fn guest_programs_and_acknowledges_the_timer() {
+		const CODE: [u32; 6] = [
+			0x34088001, // ori $t0, $zero, 0x8001
+			0x40886000, // mtc0 $t0, $12 (Status)
+			0x2408000A, // addiu $t0, $zero, 10
+			0x40885800, // mtc0 $t0, $11 (Compare)
+			0x1000FFFF, // beq $zero, $zero, -4
+			0x24210001, // addiu $at, $at, 1
+		];
+
+		const HANDLER: [u32; 5] = [
+			0x40096800, // mfc0 $t1, $13 (Cause)
+			0x24080064, // addiu $t0, $zero, 100
+			0x40885800, // mtc0 $t0, $11 (Compare)
+			0x400A6800, // mfc0 $t2, $13 (Cause)
+			0x24000000, // addiu $zero, $zero, 0
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, RAM_SIZE).unwrap();
+		load_rom(&mut system, &CODE);
+
+		write_code(&mut system, RAM_BASE + 0x80, &HANDLER);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, 0x80000090);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		assert_eq!(ROM_VIRT + 0x10, system.get_cpu_reg(&cpu, mips::REG_CP0_EPC).unwrap());
+		assert_eq!(0x00008000, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(9)).unwrap());
+		assert_eq!(0, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(10)).unwrap());
+		assert_eq!(100, system.get_cpu_reg(&cpu, mips::REG_CP0_COMPARE).unwrap());
+	}
+
+	#[test]
+	fn branch_uses_sources_from_before_delay_slot() {
+		const CODE: [u32; 4] = [
+			0x10200002, // beq $at, $zero, +8
+			0x24010001, // addiu $at, $zero, 1
+			0x34213456, // ori $at, $at, 0x3456
+			0x24080007, // addiu $t0, $zero, 7
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 0xC);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		// $at was still zero when the branch issued, so it's taken past the ori
+		assert_eq!(ROM_VIRT + 0x10, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(1, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(7, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(8)).unwrap());
+	}
+
+	#[test]
+	fn range_shorter_than_a_page_executes_to_its_end() {
+		const CODE: [u32; 4] = [
+			0x34210001, // ori $at, $at, 0x1
+			0x34210002, // ori $at, $at, 0x2
+			0x34210004, // ori $at, $at, 0x4
+			0x1000FFFF, // beq $zero, $zero, -4 (delay slot past the end of the range)
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, 0x10).unwrap();
+
+		write_code(&mut system, ROM_BASE, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		match system.execute(&cpu) {
+			Err(Error::TruncatedInstruction(addr)) => assert_eq!(ROM_VIRT + 0x10, addr),
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		assert_eq!(7, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(ROM_VIRT + 0xC, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn delay_slot_on_the_next_page_is_fetched() {
+		const CODE: [u32; 5] = [
+			0x34210001, // ori $at, $at, 0x1
+			0x10000002, // beq $zero, $zero, +8 (last word of the page)
+			0x34210002, // ori $at, $at, 0x2 (first word of the next page)
+			0x34210004, // ori $at, $at, 0x4
+			0x24080007, // addiu $t0, $zero, 7
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+
+		write_code(&mut system, ROM_BASE + 0xFF8, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 0x1008);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT + 0xFF8).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		assert_eq!(ROM_VIRT + 0x100C, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(3, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(7, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(8)).unwrap());
+	}
+
+	#[test]
+	fn run_reports_each_stop_condition() {
+		const CODE: [u32; 5] = [
+			0x34210001, // ori $at, $at, 0x1
+			0x34210002, // ori $at, $at, 0x2
+			0x34210004, // ori $at, $at, 0x4
+			0x1000FFFF, // beq $zero, $zero, -4
+			0x34210000, // ori $at, $at, 0x0
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		let outcome = system.run(&cpu, RunConfig { stop_at: vec!(ROM_VIRT + 0x8), ..Default::default() }).unwrap();
+		match outcome.stop {
+			RunStop::Exited(ExitReason::StopAddressReached(addr)) => assert_eq!(ROM_VIRT + 0x8, addr),
+			other => panic!("Unexpected stop:  {:?}", other),
+		}
+		assert_eq!(ROM_VIRT + 0x8, outcome.pc);
+		assert_eq!(3, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+
+		// Resuming from a stop address doesn't immediately stop again
+		let outcome = system.run(&cpu, RunConfig { max_insns: Some(1), stop_at: vec!(ROM_VIRT + 0x8), ..Default::default() }).unwrap();
+		match outcome.stop {
+			RunStop::Exited(ExitReason::InstructionLimitReached) => {},
+			other => panic!("Unexpected stop:  {:?}", other),
+		}
+		assert_eq!(ROM_VIRT + 0xC, outcome.pc);
+		assert_eq!(7, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+
+		let outcome = system.run(&cpu, RunConfig { timeout: Some(Duration::from_millis(10)), ..Default::default() }).unwrap();
+		match outcome.stop {
+			RunStop::Exited(ExitReason::TimedOut) => {},
+			other => panic!("Unexpected stop:  {:?}", other),
+		}
+		assert_eq!(ROM_VIRT + 0xC, outcome.pc);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT + ROM_SIZE).unwrap();
+
+		let outcome = system.run(&cpu, RunConfig::default()).unwrap();
+		match outcome.stop {
+			RunStop::Exited(ExitReason::PcOutOfBounds(addr)) => assert_eq!(ROM_VIRT + ROM_SIZE, addr),
+			other => panic!("Unexpected stop:  {:?}", other),
+		}
+		assert_eq!(ROM_VIRT + ROM_SIZE, outcome.pc);
+
+		stop_at(&mut system, ROM_VIRT + 0xC);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT + 0xC).unwrap();
+
+		let outcome = system.run(&cpu, RunConfig::default()).unwrap();
+		match outcome.stop {
+			RunStop::Exited(ExitReason::CodeHookSignalledStop) => {},
+			other => panic!("Unexpected stop:  {:?}", other),
+		}
+		assert_eq!(ROM_VIRT + 0xC, outcome.pc);
+	}
+
+	#[test]
+	fn register_snapshots_compare_state() {
+		const CODE: [u32; 2] = [
+			0x24000000, // addiu $zero, $zero, 0
+			0x34213456, // ori $at, $at, 0x3456
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+		let reference = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+		system.set_cpu_reg(&reference, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		let before = system.register_snapshot(&cpu).unwrap();
+
+		assert_eq!(before, system.register_snapshot(&reference).unwrap());
+
+		// A no-op only moves the pc
+		system.step_one_with_diff(&cpu).unwrap();
+
+		let after_nop = system.register_snapshot(&cpu).unwrap();
+
+		assert!(before != after_nop);
+		assert_eq!(ROM_VIRT + 4, after_nop.pc());
+
+		system.step_one_with_diff(&reference).unwrap();
+
+		let mut snapshots = HashSet::new();
+		snapshots.insert(after_nop.clone());
+		assert!(snapshots.contains(&system.register_snapshot(&reference).unwrap()));
+
+		system.step_one_with_diff(&cpu).unwrap();
+
+		let after_ori = system.register_snapshot(&cpu).unwrap();
+
+		assert_eq!(Some(0x3456), after_ori.get(&mips::REG_AT));
+		assert!(!snapshots.contains(&after_ori));
+	}
+
+	#[test]
+	fn register_snapshot_restores_predicates() {
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0x1234).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Predicates, 0x8000000000000005).unwrap();
+
+		let snapshot = system.register_snapshot(&cpu).unwrap();
+
+		assert_eq!(Some(0x8000000000000005), snapshot.get(&CpuReg::Predicates));
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT + 0x40).unwrap();
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Predicates, 0).unwrap();
+
+		assert!(snapshot != system.register_snapshot(&cpu).unwrap());
+
+		system.restore_register_snapshot(&cpu, &snapshot).unwrap();
+
+		assert_eq!(0x8000000000000005, system.get_cpu_reg(&cpu, CpuReg::Predicates).unwrap());
+		assert_eq!(0x1234, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(snapshot, system.register_snapshot(&cpu).unwrap());
+	}
+
+	// ori $at twice around an addiu that leaves it alone; only the first ori
+	// changes it
+	fn map_at_writes(system: &mut System) {
+		const CODE: [u32; 3] = [
+			0x34213456, // ori $at, $at, 0x3456
+			0x24080055, // addiu $t0, $zero, 0x55
+			0x34213456, // ori $at, $at, 0x3456
+		];
+
+		load_rom(system, &CODE);
+	}
+
+	#[test]
+	fn unkicked_watchdog_resets_cpu() {
+		const CODE: [u32; 2] = [
+			0x1000FFFF, // beq $zero, $zero, .
+			0x24080055, // addiu $t0, $zero, 0x55
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.add_watchdog(&cpu, 0x1F000000, Arc::new(Mutex::new(mem::Watchdog::new(100)))).unwrap();
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0x1234).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::WatchdogReset, system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(0, system.cycle_count(&cpu).unwrap());
+	}
+
+	#[test]
+	fn watchdog_for_unknown_cpu_maps_nothing() {
+		let mut system = System::new();
+		let mut other = System::new();
+
+		let cpu = register_r2000(&mut other);
+
+		match system.add_watchdog(&cpu, 0x1F000000, Arc::new(Mutex::new(mem::Watchdog::new(100)))) {
+			Err(Error::InvalidCpuCookie) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		assert_eq!(mem::ProbeResult::Unmapped, system.probe(0x1F000000, PROT_READ));
+	}
+
+	#[test]
+	fn branch_out_of_mapped_memory_stops_cleanly() {
+		const CODE: [u32; 2] = [
+			0x10000800, // beq $zero, $zero, +0x2004
+			0x24080055, // addiu $t0, $zero, 0x55
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, 0x1000).unwrap();
+
+		write_code(&mut system, ROM_BASE, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::PcOutOfBounds(ROM_VIRT + 0x2004), system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 0x2004, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0x55, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(8)).unwrap());
+	}
+
+	#[test]
+	fn swap_context_round_trips_both_contexts() {
+		let mut system = System::new();
+
+		map_at_writes(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0xAAAA).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT + 8).unwrap();
+
+		let mut guest = iisa::RegisterFile::new();
+		guest.write_u32(1, 0x10000);
+		guest.pc = ROM_VIRT;
+
+		let host = system.swap_context(&cpu, guest).unwrap();
+
+		assert_eq!(0xAAAA, host.read_u32(1));
+		assert_eq!(ROM_VIRT + 8, host.pc);
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		let guest = system.swap_context(&cpu, host).unwrap();
+
+		assert_eq!(0x13456, guest.read_u32(1));
+		assert_eq!(ROM_VIRT + 4, guest.pc);
+
+		assert_eq!(0xAAAA, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(ROM_VIRT + 8, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn swap_context_rejects_a_file_too_small_for_the_arch() {
+		let mut system = System::new();
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0xAAAA).unwrap();
+
+		match system.swap_context(&cpu, iisa::RegisterFile::with_slots(4)) {
+			Err(Error::RegisterFileTooSmall(4, _)) => {},
+			other => panic!("Unexpected result:  {:?}", other.map(|regs| regs.slots())),
+		}
+
+		// The CPU's own context is untouched, and it still answers
+		assert_eq!(0xAAAA, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn reg_watch_fires_on_change() {
+		let mut system = System::new();
+
+		map_at_writes(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		let changes = Arc::new(Mutex::new(Vec::new()));
+		let hook_changes = changes.clone();
+
+		system.add_reg_watch(&cpu, mips::REG_AT, Arc::new(Mutex::new(move |pc, old, new| {
+			hook_changes.lock().unwrap().push((pc, old, new));
+
+			TraceExitHint::ContinueExecution
+		}))).unwrap();
+
+		stop_at(&mut system, ROM_VIRT + 8);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(vec!((ROM_VIRT, 0, 0x3456)), *changes.lock().unwrap());
+	}
+
+	#[test]
+	fn reg_watch_can_stop_execution() {
+		let mut system = System::new();
+
+		map_at_writes(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.add_reg_watch(&cpu, mips::REG_AT, Arc::new(Mutex::new(|_, _, _| {
+			TraceExitHint::StopExecution
+		}))).unwrap();
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 4, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0x3456, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn reg_watch_sees_the_timer_raise_cause() {
+		const CODE: [u32; 2] = [
+			0x1000FFFF, // beq $zero, $zero, -4
+			0x24210001, // addiu $at, $at, 1
+		];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, RAM_SIZE).unwrap();
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		let changes = Arc::new(Mutex::new(Vec::new()));
+		let hook_changes = changes.clone();
+
+		// Nothing the guest runs writes Cause; the timer and exception entry do
+		system.add_reg_watch(&cpu, mips::REG_CP0_CAUSE, Arc::new(Mutex::new(move |_, old, new| {
+			hook_changes.lock().unwrap().push((old, new));
+
+			TraceExitHint::StopExecution
+		}))).unwrap();
+
+		system.set_cpu_reg(&cpu, mips::REG_CP0_STATUS, 0x00008001).unwrap();
+		system.set_cpu_reg(&cpu, mips::REG_CP0_COMPARE, 10).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(vec!((0, 0x00008000)), *changes.lock().unwrap());
+		assert_eq!(0x80000080, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn op_class_hook_fires_for_each_store() {
+		const CODE: [u32; 4] = [
+			0x24080055, // addiu $t0, $zero, 0x55
+			0xa0880000, // sb $t0, 0($a0)
+			0x8c890000, // lw $t1, 0($a0)
+			0x34210001, // ori $at, $at, 0x1
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+		system.add_mappable_range(PROT_ALL, 0, 0x1000).unwrap();
+		system.set_range(&[0; 4], 0).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		let stores = Arc::new(Mutex::new(Vec::new()));
+		let hook_stores = stores.clone();
+
+		system.add_op_class_hook(iisa::OpClass::Store, Arc::new(Mutex::new(move |pc, op: &iisa::Op| {
+			hook_stores.lock().unwrap().push((pc, op.clone()));
+
+			TraceExitHint::ContinueExecution
+		}))).unwrap();
+
+		stop_at(&mut system, ROM_VIRT + 0xC);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0000000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		let stores = stores.lock().unwrap();
+
+		assert_eq!(1, stores.len());
+		assert_eq!(ROM_VIRT + 0x4, stores[0].0);
+		assert_eq!(iisa::OpClass::Store, iisa::op_class(&stores[0].1));
+		assert_eq!(0x55000000, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(9)).unwrap());
+	}
+
+	#[test]
+	fn code_hooks_report_delay_slot_sizes() {
+		const CODE: [u32; 4] = [
+			0x10000002, // beq $zero, $zero, +8
+			0x34210001, // ori $at, $at, 0x1
+			0x34210002, // ori $at, $at, 0x2
+			0x34210004, // ori $at, $at, 0x4
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		let hits = Arc::new(Mutex::new(Vec::new()));
+
+		for offset in [0x0, 0x4].iter() {
+			let hook_hits = hits.clone();
+
+			system.add_code_hook_single(ROM_VIRT + offset, Arc::new(Mutex::new(move |pc, size, insn_count| {
+				hook_hits.lock().unwrap().push((pc, size, insn_count));
+
+				TraceExitHint::ContinueExecution
+			}))).unwrap();
+		}
+
+		stop_at(&mut system, ROM_VIRT + 0xC);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		assert_eq!(vec!((ROM_VIRT, 4, 0), (ROM_VIRT + 4, 4, 1)), *hits.lock().unwrap());
+		assert_eq!(5, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn flat_images_load_side_by_side() {
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, 0, 0x1000).unwrap();
+
+		system.load_flat(&[0x11, 0x22, 0x33, 0x44], 0x100).unwrap();
+		system.load_flat(&[0x55, 0x66], 0x104).unwrap();
+
+		let bytes: Vec<u8> = (0x100..0x106).map(|addr| system.fsb.read_u8(addr).into_result(addr).unwrap()).collect();
+
+		assert_eq!(vec!(0x11, 0x22, 0x33, 0x44, 0x55, 0x66), bytes);
+
+		match system.load_flat(&[0x77, 0x88], 0x103) {
+			Err(Error::OverlappingRange(0x103, 2)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		// The rejected image didn't get written
+		assert_eq!(0x44, system.fsb.read_u8(0x103).into_result(0x103).unwrap());
+	}
+
+	// Runs a couple of stores through $a0 = a0, stopping just after them
+	fn run_stores(a0: u64) -> System {
+		const CODE: [u32; 3] = [
+			0x24081234, // addiu $t0, $zero, 0x1234
+			0xa0880010, // sb $t0, 0x10($a0)
+			0xa4880002, // sh $t0, 2($a0)
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+		system.add_mappable_range(PROT_ALL, 0, 0x1000).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 8);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), a0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		system
+	}
+
+	// A store through $a0 and then a fence, with RAM mapped for the store
+	fn map_store_then_fence(system: &mut System) {
+		const CODE: [u32; 3] = [
+			0xa0880010, // sb $t0, 0x10($a0)
+			0x0000000f, // sync
+			0x34210001, // ori $at, $at, 0x1
+		];
+
+		load_rom(system, &CODE);
+		system.add_mappable_range(PROT_ALL, 0, 0x1000).unwrap();
+	}
+
+	#[test]
+	fn buffered_store_only_reaches_memory_at_fence() {
+		let mut system = System::new();
+		let mut untouched = System::new();
+
+		map_store_then_fence(&mut system);
+		map_store_then_fence(&mut untouched);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_store_buffer_entries(&cpu, 4).unwrap();
+
+		stop_at(&mut system, ROM_VIRT);
+		stop_at(&mut system, ROM_VIRT + 4);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0000000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(8), 0x1234).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(vec!(iisa::PendingStore{pc: ROM_VIRT, addr: 0xA0000010, phys: 0x10, width: 1, value: 0x34}), system.pending_stores(&cpu).unwrap());
+		assert_eq!(Vec::<(u64, u8, u8)>::new(), system.diff_memory(&untouched));
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(Vec::<iisa::PendingStore>::new(), system.pending_stores(&cpu).unwrap());
+		assert_eq!(vec!((0x10, 0x34, 0x00)), system.diff_memory(&untouched));
+	}
+
+	#[test]
+	fn buffered_store_faults_at_its_own_pc() {
+		let mut system = System::new();
+
+		map_store_then_fence(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_store_buffer_entries(&cpu, 4).unwrap();
+
+		// Past the end of mapped RAM, which only the fence finds out
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0100000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		match system.execute(&cpu) {
+			Err(Error::StoreFault{pc, fault}) => {
+				assert_eq!(ROM_VIRT, pc);
+
+				match *fault {
+					Error::BusFault{ addr: 0xA0100010, width: 1, is_write: true } => {},
+					other => panic!("Unexpected fault:  {:?}", other),
+				}
+			},
+
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn resizing_store_buffer_keeps_stores_it_could_not_drain() {
+		let mut system = System::new();
+
+		map_store_then_fence(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_store_buffer_entries(&cpu, 4).unwrap();
+
+		stop_at(&mut system, ROM_VIRT);
+
+		// Past the end of mapped RAM, so the drain faults
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0100000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		let pending = system.pending_stores(&cpu).unwrap();
+		assert_eq!(1, pending.len());
+
+		match system.set_store_buffer_entries(&cpu, 0) {
+			Err(Error::StoreFault{pc, ..}) => assert_eq!(ROM_VIRT, pc),
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		assert_eq!(pending, system.pending_stores(&cpu).unwrap());
+	}
+
+	#[test]
+	fn reset_drops_buffered_stores() {
+		let mut system = System::new();
+		let mut untouched = System::new();
+
+		map_store_then_fence(&mut system);
+		map_store_then_fence(&mut untouched);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_store_buffer_entries(&cpu, 4).unwrap();
+
+		stop_at(&mut system, ROM_VIRT);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0000000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(1, system.pending_stores(&cpu).unwrap().len());
+
+		system.reset_cpu(&cpu).unwrap();
+
+		assert_eq!(Vec::<iisa::PendingStore>::new(), system.pending_stores(&cpu).unwrap());
+		assert_eq!(Vec::<(u64, u8, u8)>::new(), system.diff_memory(&untouched));
+	}
+
+	#[test]
+	fn sw_stores_a_whole_register_into_ram() {
+		let mut system = System::new();
+		let mut untouched = System::new();
+
+		for system in [&mut system, &mut untouched].iter_mut() {
+			system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+			system.add_mappable_range(PROT_ALL, 0, 0x1000).unwrap();
+			system.set_u32(ROM_BASE,     0xac880010, mem::Endian::Big).unwrap(); // sw $t0, 0x10($a0)
+			system.set_u32(ROM_BASE + 4, 0x34210001, mem::Endian::Big).unwrap(); // ori $at, $at, 0x1
+		}
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 4);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0000000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(8), 0x12345678).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(vec!((0x10, 0x12, 0x00), (0x11, 0x34, 0x00), (0x12, 0x56, 0x00), (0x13, 0x78, 0x00)), system.diff_memory(&untouched));
+	}
+
+	#[test]
+	fn identical_runs_have_no_memory_divergence() {
+		let golden = run_stores(0xA0000000);
+		let candidate = run_stores(0xA0000000);
+
+		assert_eq!(Vec::<(u64, u8, u8)>::new(), golden.diff_memory(&candidate));
+	}
+
+	#[test]
+	fn diverging_runs_report_each_differing_byte() {
+		let golden = run_stores(0xA0000000);
+		let mut candidate = run_stores(0xA0000100);
+
+		// Only memory both systems map is compared
+		candidate.add_mappable_range(PROT_ALL, 0x2000, 0x1000).unwrap();
+		candidate.set_range(&[0xFF; 4], 0x2000).unwrap();
+
+		assert_eq!(vec!((0x002, 0x12, 0x00),
+		                (0x003, 0x34, 0x00),
+		                (0x010, 0x34, 0x00),
+		                (0x102, 0x00, 0x12),
+		                (0x103, 0x00, 0x34),
+		                (0x110, 0x00, 0x34)),
+		           golden.diff_memory(&candidate));
+	}
+
+	#[test]
+	fn mips_example_runs_from_owned_ram() {
+		const CODE: [u32; 3] = [
+			0x34213456, // ori $at, $at, 0x3456
+			0xa0810010, // sb $at, 0x10($a0)
+			0x34210001, // ori $at, $at, 0x1
+		];
+
+		for &(opts, endian) in [(CPU_ENDIAN_BIG, mem::Endian::Big), (CPU_ENDIAN_LITTLE, mem::Endian::Little)].iter() {
+			let mut code = Vec::new();
+
+			for word in CODE.iter() {
+				let bytes = [(*word >> 24) as u8, (*word >> 16) as u8, (*word >> 8) as u8, (*word >> 0) as u8];
+
+				match endian {
+					mem::Endian::Big    => code.extend(bytes.iter()),
+					mem::Endian::Little => code.extend(bytes.iter().rev()),
+				}
+			}
+
+			let mut system = System::new();
+
+			system.add_ram_range(ROM_BASE, code, PROT_ALL).unwrap();
+			system.add_ram_range(0, vec!(0; 0x1000), PROT_ALL).unwrap();
+
+			let cpu = system.register_cpu(opts, Arch::Mips(mips::Arch::R2000)).unwrap();
+
+			stop_at(&mut system, ROM_VIRT + 4);
+
+			system.set_cpu_reg(&cpu, mips::REG_AT, 0x6789).unwrap();
+			system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0000000).unwrap();
+			system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+			assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+			assert_eq!(ROM_VIRT + 8, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+			assert_eq!(0x77DF, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+
+			// The CPU's store lands in the same Vec the system sees
+			assert_eq!(0xDF, system.fsb.read_u8(0x10).into_result(0x10).unwrap());
+		}
+	}
+
+	#[test]
+	fn run_until_write_stops_after_the_store() {
+		const CODE: [u32; 4] = [
+			0x24081234, // addiu $t0, $zero, 0x1234
+			0xa0880010, // sb $t0, 0x10($a0)
+			0xa4880002, // sh $t0, 2($a0)
+			0x34210001, // ori $at, $at, 0x1
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+		system.add_mappable_range(PROT_ALL, 0, 0x1000).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0000000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::WatchpointHit{ addr: 0xA0000002, value: 0x1234 },
+		           system.run_until_write(&cpu, 0xA0000000, 4).unwrap());
+
+		assert_eq!(ROM_VIRT + 0xC, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(0x12, system.fsb.read_u8(2).into_result(2).unwrap());
+	}
+
+	#[test]
+	fn unmapped_load_reports_address() {
+		const LW_T0_0X10_A0_EB: [u8; 4] = [0x8c, 0x88, 0x00, 0x10];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+		system.set_range(&LW_T0_0X10_A0_EB, ROM_BASE).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0100000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		match system.execute(&cpu) {
+			Err(Error::BusFault{ addr: 0xA0100010, width: 4, is_write: false }) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn step_one_with_diff() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0x6789).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		let (instrs, changed, exit_reason) = system.step_one_with_diff(&cpu).unwrap();
+
+		assert_eq!(1, instrs.len());
+		assert_eq!(iisa::Op::Or(iisa::DstSrcSrc{dst: iisa::R::W(1), src: [iisa::Src::Reg(iisa::R::W(1)), iisa::Src::ImmU16(0x3456)]}), instrs[0].op);
+		assert_eq!(vec![(mips::REG_AT, 0x6789, 0x77df)], changed);
+		assert_eq!(ExitReason::SingleStep, exit_reason);
+	}
+
+	#[test]
+	fn disassemble_at_stops_at_end_of_block() {
+		const CODE: [u32; 3] = [
+			0x34213456, // ori $at, $at, 0x3456
+			0x10000003, // beq $zero, $zero, +12
+			0x34420001, // ori $v0, $v0, 0x1
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0x6789).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		let disassembly = system.disassemble_at(&cpu, ROM_VIRT, 16).unwrap();
+
+		assert_eq!(2, disassembly.len());
+		assert_eq!((ROM_VIRT, iisa::Op::Or(iisa::DstSrcSrc{dst: iisa::R::W(1), src: [iisa::Src::Reg(iisa::R::W(1)), iisa::Src::ImmU16(0x3456)]})),
+		           (disassembly[0].0, disassembly[0].1.op.clone()));
+		assert_eq!(ROM_VIRT + 4, disassembly[1].0);
+		assert!(iisa::is_end_of_block(&disassembly[1].1.op));
+
+		assert_eq!(1, system.disassemble_at(&cpu, ROM_VIRT, 1).unwrap().len());
+
+		assert_eq!(0x6789, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn fetch_translation_fault() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x00400000).unwrap();
+
+		match system.execute(&cpu) {
+			Err(Error::FetchTranslationFault(0x00400000)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn data_translation_fault() {
+		const LW_T0_0X10_A0_EB: [u8; 4] = [0x8c, 0x88, 0x00, 0x10];
+
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+		system.set_range(&LW_T0_0X10_A0_EB, ROM_BASE).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0x00400000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		match system.execute(&cpu) {
+			Err(Error::DataTranslationFault(0x00400010)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn cpu_opt_constructors() {
+		assert_eq!(CPU_ENDIAN_BIG, CpuOpt::big_endian());
+		assert_eq!(CPU_ENDIAN_LITTLE, CpuOpt::little_endian());
+		assert!(!CpuOpt::little_endian().contains(CPU_ENDIAN_BIG));
+	}
+
+	#[test]
+	fn cpu_opt_constructors_execute() {
+		for opts in [CpuOpt::big_endian(), CpuOpt::little_endian()].iter() {
+			let mut system = System::new();
+
+			system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+
+			let endian = if opts.contains(CPU_ENDIAN_BIG) { mem::Endian::Big } else { mem::Endian::Little };
+			system.set_u32(ROM_BASE, 0x34213456, endian).unwrap();
+
+			let cpu = system.register_cpu(*opts, Arch::Mips(mips::Arch::R2000)).unwrap();
+
+			assert_executes_at(&mut system, &cpu, ROM_BASE);
+		}
+	}
+
+	#[test]
+	fn on_stop_wakes_every_waiter() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		let waiters: Vec<_> = (0..2).map(|_| {
+			let on_stop = system.on_stop(&cpu).unwrap();
+
+			thread::spawn(move || on_stop.wait())
+		}).collect();
+
+		assert_executes_at(&mut system, &cpu, ROM_BASE);
+
+		for waiter in waiters.into_iter() {
+			match waiter.join().unwrap() {
+				Ok(ExitReason::CodeHookSignalledStop) => {},
+				other => panic!("Unexpected result:  {:?}", other),
+			}
+		}
+
+		// Each future is good for only the one stop
+		let on_stop = system.on_stop(&cpu).unwrap();
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x10000000).unwrap();
+
+		assert!(system.execute(&cpu).is_err());
+
+		match on_stop.wait() {
+			Err(Error::FetchTranslationFault(0x10000000)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn subscribe_events_reports_start_and_stop() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		let events = system.subscribe_events(&cpu).unwrap();
+
+		assert!(!system.is_cpu_running(&cpu).unwrap());
+
+		assert_executes_at(&mut system, &cpu, ROM_BASE);
+
+		match events.recv().unwrap() {
+			CpuEvent::Started => {},
+			event => panic!("Unexpected event:  {:?}", event),
+		}
+
+		match events.recv().unwrap() {
+			CpuEvent::Stopped(ExitReason::CodeHookSignalledStop) => {},
+			event => panic!("Unexpected event:  {:?}", event),
+		}
+
+		assert!(!system.is_cpu_running(&cpu).unwrap());
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x10000000).unwrap();
+
+		assert!(system.execute(&cpu).is_err());
+
+		match events.recv().unwrap() {
+			CpuEvent::Started => {},
+			event => panic!("Unexpected event:  {:?}", event),
+		}
+
+		match events.recv().unwrap() {
+			CpuEvent::Fault(Error::FetchTranslationFault(0x10000000)) => {},
+			event => panic!("Unexpected event:  {:?}", event),
+		}
+	}
+
+	#[test]
+	fn total_guest_memory() {
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, RAM_SIZE).unwrap();
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+
+		assert_eq!(RAM_SIZE + ROM_SIZE, system.total_guest_memory());
+	}
+
+	#[test]
+	fn dropped_cookie_retires_cpu() {
+		let mut system = System::new();
+
+		let leaked = register_r2000(&mut system);
+
+		system.set_auto_retire_cpus(true);
+
+		let retired = register_r2000(&mut system);
+		let kept = register_r2000(&mut system);
+
+		assert_eq!(3, system.active_cpu_count());
+
+		drop(retired);
+
+		assert_eq!(2, system.active_cpu_count());
+
+		drop(leaked);
+
+		assert_eq!(2, system.active_cpu_count());
+
+		system.set_cpu_reg(&kept, CpuReg::Pc, ROM_VIRT).unwrap();
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&kept, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn register_cpu_through_custom_factory() {
+		let mut system = System::new();
+
+		let factory_calls = Arc::new(Mutex::new(0));
+		let hook_factory_calls = factory_calls.clone();
+
+		system.register_cpu_factory(Arch::Custom("dummy"), Box::new(move |opts, fsb| {
+			*hook_factory_calls.lock().unwrap() += 1;
+
+			mips::mips_cpu_factory(opts, mips::Arch::R2000, fsb)
+		}));
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG, Arch::Custom("dummy")).unwrap();
+
+		assert_eq!(1, *factory_calls.lock().unwrap());
+
+		assert_executes_at(&mut system, &cpu, ROM_BASE);
+	}
+
+	#[test]
+	fn cpu_arch_is_the_registered_arch() {
+		let mut system = System::new();
+
+		let mips_cpu = register_r2000(&mut system);
+
+		system.register_cpu_factory(Arch::Custom("dummy"), Box::new(|opts, fsb| {
+			mips::mips_cpu_factory(opts, mips::Arch::R2000, fsb)
+		}));
+
+		let custom_cpu = system.register_cpu(CPU_ENDIAN_BIG, Arch::Custom("dummy")).unwrap();
+
+		assert_eq!(Arch::Mips(mips::Arch::R2000), system.cpu_arch(&mips_cpu).unwrap());
+		assert_eq!(Arch::Custom("dummy"), system.cpu_arch(&custom_cpu).unwrap());
+	}
+
+	#[test]
+	fn register_cpu_rejects_unknown_arch() {
+		let mut system = System::new();
+
+		match system.register_cpu(CPU_ENDIAN_BIG, Arch::Custom("unregistered")) {
+			Err(Error::UnimplementedArchitecture) => {},
+			Err(err) => panic!("Unexpected error:  {:?}", err),
+			Ok(_) => panic!("Unregistered arch accepted"),
+		}
+	}
+
+	#[test]
+	fn unimplemented_mips_arches_fail_cleanly() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let mips4kc = system.register_cpu(CPU_ENDIAN_BIG, Arch::Mips(mips::Arch::Mips4Kc)).unwrap();
+		let vr4300 = system.register_cpu(CPU_ENDIAN_BIG, Arch::Mips(mips::Arch::VR4300)).unwrap();
+
+		// Even a policy that skips undecodable instructions can't skip the arch
+		system.set_decode_error_policy(&mips4kc, DecodeErrorPolicy::SkipOne).unwrap();
+		system.set_cpu_reg(&mips4kc, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		match system.execute(&mips4kc) {
+			Err(Error::UnimplementedArchitecture) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		// The VR4300 has neither a decoder nor registers yet
+		match system.disassemble_at(&vr4300, ROM_VIRT, 1) {
+			Err(Error::UnimplementedArchitecture) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match system.set_cpu_reg(&vr4300, CpuReg::Pc, ROM_VIRT) {
+			Err(Error::UnimplementedArchitecture) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match system.get_cpu_reg(&vr4300, CpuReg::Pc) {
+			Err(Error::UnimplementedArchitecture) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn register_cpu_rejects_unknown_opt() {
+		let mut system = System::new();
+
+		match system.register_cpu(CpuOpt{ bits: 0b10000000 }, Arch::Mips(mips::Arch::R2000)) {
+			Err(Error::OptNotSupported(opts)) => assert_eq!(0b10000000, opts.bits()),
+			Err(err) => panic!("Unexpected error:  {:?}", err),
+			Ok(_) => panic!("Unknown option bit accepted"),
+		}
+	}
+
+	#[test]
+	fn reset_cpu() {
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0x6789).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x80001000).unwrap();
+
+		system.reset_cpu(&cpu).unwrap();
+
+		assert_eq!(0, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(0xBFC00000, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn hooks_see_increasing_insn_count() {
+		const CODE: [u32; 9] = [
+			0x34210001, // ori $at, $at, 0x1
+			0x10000002, // beq $zero, $zero, +8
+			0x34210002, // ori $at, $at, 0x2
+			0x00000000,
+			0x34210004, // ori $at, $at, 0x4
+			0x10000002, // beq $zero, $zero, +8
+			0x34210008, // ori $at, $at, 0x8
+			0x00000000,
+			0x34210010, // ori $at, $at, 0x10
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let cpu = register_r2000(&mut system);
+
+		let blocks = Arc::new(Mutex::new(Vec::new()));
+		let hook_blocks = blocks.clone();
+
+		system.add_block_hook_all(Arc::new(Mutex::new(move |address, size, insn_count| {
+			hook_blocks.lock().unwrap().push((address, size, insn_count));
+
+			TraceExitHint::ContinueExecution
+		}))).unwrap();
+
+		stop_at(&mut system, ROM_VIRT + 0x20);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(0x1F, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(ROM_VIRT + 0x24, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+
+		assert_eq!(vec![(ROM_VIRT,        0xC, 0),
+		                (ROM_VIRT + 0x10, 0xC, 3),
+		                (ROM_VIRT + 0x20, 0x4, 6)],
+		           *blocks.lock().unwrap());
+	}
+
+	#[test]
+	fn trapped_insns_arent_counted() {
+		use iisa::{Cond, Instr, Op, Pred, Src, SrcSrc};
+
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		system.load_iisa(&cpu, vec!(
+			Instr{op: Op::Trap(Cond::Eq, SrcSrc{src: [Src::ImmU32(0), Src::ImmU32(0)]}), pred: Pred::None, exc: 13, size: 4},
+		), 0x1000).unwrap();
+
+		system.load_iisa(&cpu, vec!(Instr{op: Op::Nop, pred: Pred::None, exc: 0, size: 4}), 0x80000080).unwrap();
+
+		let counts = Arc::new(Mutex::new(Vec::new()));
+		let hook_counts = counts.clone();
+
+		system.add_code_hook_single(0x80000080, Arc::new(Mutex::new(move |_, _, insn_count| {
+			hook_counts.lock().unwrap().push(insn_count);
+
+			TraceExitHint::StopExecution
+		}))).unwrap();
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x1000).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(vec!(0), *counts.lock().unwrap());
+	}
+
+	#[test]
+	fn current_insn_bytes_at_entry() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ORI_AT_AT_0X3456_EB.to_vec(), system.current_insn_bytes(&cpu).unwrap());
+	}
+
+	// Counts $at up to $t0, stopping at ROM_VIRT + 0x14 when the two match
+	const LOOP_CODE: [u32; 6] = [
+		0x24210001, // addiu $at, $at, 1
+		0x10280003, // beq $at, $t0, +12
+		0x24000000, // addiu $zero, $zero, 0
+		0x1000FFFC, // beq $zero, $zero, -16
+		0x24000000, // addiu $zero, $zero, 0
+		0x24000000, // addiu $zero, $zero, 0
+	];
+
+	fn map_loop_code(system: &mut System) {
+		load_rom(system, &LOOP_CODE);
+	}
+
+	fn run_loop(system: &mut System, cpu: &CpuCookie, iterations: u64) {
+		stop_at(system, ROM_VIRT + 0x14);
+
+		system.set_cpu_reg(cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(cpu, CpuReg::CpuSpecific(8), iterations).unwrap();
+		system.set_cpu_reg(cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(cpu).unwrap());
+		assert_eq!(iterations, system.get_cpu_reg(cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn branch_edges_record_back_edge() {
+		let mut system = System::new();
+
+		map_loop_code(&mut system);
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG | CPU_RECORD_BRANCHES, Arch::Mips(mips::Arch::R2000)).unwrap();
+
+		run_loop(&mut system, &cpu, 5);
+
+		assert_eq!(vec![BranchEdge{ from: ROM_VIRT + 0x4, to: ROM_VIRT + 0xC,  fallthrough: true  },
+		                BranchEdge{ from: ROM_VIRT + 0x4, to: ROM_VIRT + 0x14, fallthrough: false },
+		                BranchEdge{ from: ROM_VIRT + 0xC, to: ROM_VIRT,        fallthrough: false }],
+		           system.branch_edges(&cpu).unwrap());
+	}
+
+	#[test]
+	fn pc_trace_file() {
+		use std::fs::{self, File};
+		use std::io::Read;
+
+		let path = std::env::temp_dir().join(format!("ani-core-pc-trace-{}.bin", std::process::id()));
+
+		let mut system = System::new();
+
+		map_loop_code(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.enable_trace_file(&cpu, &path, TraceFormat::PcOnly).unwrap();
+
+		run_loop(&mut system, &cpu, 2);
+
+		let mut bytes = Vec::new();
+		File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		let pcs: Vec<u64> = bytes.chunks(8).map(|record| {
+			record.iter().rev().fold(0, |pc, byte| (pc << 8) | (*byte as u64))
+		}).collect();
+
+		assert_eq!(vec![ROM_VIRT, ROM_VIRT + 0x4, ROM_VIRT + 0xC,
+		                ROM_VIRT, ROM_VIRT + 0x4, ROM_VIRT + 0x14],
+		           pcs);
+	}
+
+	#[test]
+	fn branch_edges_off_by_default() {
+		let mut system = System::new();
+
+		map_loop_code(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		run_loop(&mut system, &cpu, 2);
+
+		assert!(system.branch_edges(&cpu).unwrap().is_empty());
+	}
+
+	#[test]
+	fn decode_hook_fires_once_per_block() {
+		let mut system = System::new();
+
+		map_loop_code(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		let decoded = Arc::new(Mutex::new(Vec::new()));
+		let hook_decoded = decoded.clone();
+
+		system.add_decode_hook(Arc::new(Mutex::new(move |address, instrs: &[iisa::Instr]| {
+			hook_decoded.lock().unwrap().push((address, instrs.len()));
+		}))).unwrap();
+
+		let executed_blocks = Arc::new(Mutex::new(0));
+		let hook_executed_blocks = executed_blocks.clone();
+
+		system.add_block_hook_all(Arc::new(Mutex::new(move |_, _, _| {
+			*hook_executed_blocks.lock().unwrap() += 1;
+
+			TraceExitHint::ContinueExecution
+		}))).unwrap();
+
+		run_loop(&mut system, &cpu, 5);
+
+		assert_eq!(vec![(ROM_VIRT,        2),
+		                (ROM_VIRT + 0xC,  1),
+		                (ROM_VIRT + 0x14, 1)],
+		           *decoded.lock().unwrap());
+		assert_eq!(10, *executed_blocks.lock().unwrap());
+	}
+
+	#[test]
+	fn decode_rewriter_can_nop_out_an_instruction() {
+		let mut system = System::new();
+
+		map_at_writes(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.add_decode_rewriter(Arc::new(Mutex::new(|address, instrs: &mut Vec<iisa::Instr>| {
+			if address == ROM_VIRT {
+				for instr in instrs.iter_mut() {
+					instr.op = iisa::Op::Nop;
+				}
+			}
+		}))).unwrap();
+
+		stop_at(&mut system, ROM_VIRT + 4);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0x6789).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 8, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0x6789, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(0x55, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(8)).unwrap());
+	}
+
+	#[test]
+	fn load_iisa_runs_without_decode() {
+		use iisa::{DstSrcSrc, Instr, Op, Pred, Src, R};
+
+		fn instr(op: Op) -> Instr {
+			Instr{op: op, pred: Pred::None, exc: 0, size: 4}
+		}
+
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		system.load_iisa(&cpu, vec!(
+			instr(Op::Add(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmI16(5)]})),
+			instr(Op::Add(DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(1)), Src::Reg(R::W(1))]})),
+			instr(Op::J(Src::Addr(0x2000))),
+		), 0x1000).unwrap();
+
+		system.load_iisa(&cpu, vec!(
+			instr(Op::Add(DstSrcSrc{dst: R::W(3), src: [Src::Reg(R::W(2)), Src::ImmU32(1)]})),
+		), 0x2000).unwrap();
+
+		stop_at(&mut system, 0x2000);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x1000).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(5,  system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+		assert_eq!(10, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(2)).unwrap());
+		assert_eq!(11, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(3)).unwrap());
+		assert_eq!(0x2004, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn call_links_to_ra() {
+		use iisa::{Instr, Op, Pred, Src};
+
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		system.load_iisa(&cpu, vec!(
+			Instr{op: Op::Call(Src::Addr(0x2000)), pred: Pred::None, exc: 0, size: 4},
+		), 0x1000).unwrap();
+
+		system.load_iisa(&cpu, vec!(
+			Instr{op: Op::Nop, pred: Pred::None, exc: 0, size: 4},
+		), 0x2000).unwrap();
+
+		stop_at(&mut system, 0x2000);
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0x1000).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(0x1004, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(31)).unwrap());
+		assert_eq!(0x2004, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn hook_raises_exception() {
+		let mut system = System::new();
+
+		map_code(&mut system, ROM_BASE, ROM_SIZE);
+
+		let cpu = register_r2000(&mut system);
+
+		system.add_code_hook_single(ROM_VIRT, Arc::new(Mutex::new(|_, _, _| {
+			TraceExitHint::RaiseException(10)
+		}))).unwrap();
+
+		stop_at(&mut system, ROM_VIRT);
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(0x80000080, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+	}
+
+	// An undecodable word between two ori $at, $at, 0x3456
+	fn map_bad_word(system: &mut System) {
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+		system.set_range(&ORI_AT_AT_0X3456_EB, ROM_BASE).unwrap();
+		system.set_range(&[0xFF, 0xFF, 0xFF, 0xFF], ROM_BASE + 4).unwrap();
+		system.set_range(&ORI_AT_AT_0X3456_EB, ROM_BASE + 8).unwrap();
+	}
+
+	#[test]
+	fn decode_error_policy_fail() {
+		let mut system = System::new();
+
+		map_bad_word(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_decode_error_policy(&cpu, DecodeErrorPolicy::Fail).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert!(system.execute(&cpu).is_err());
+		assert_eq!(ROM_VIRT + 4, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	#[test]
+	fn decode_error_policy_skip_one() {
+		let mut system = System::new();
+
+		map_bad_word(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 8);
+
+		system.set_decode_error_policy(&cpu, DecodeErrorPolicy::SkipOne).unwrap();
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 12, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0x3456, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn decode_error_policy_trap() {
+		let mut system = System::new();
+
+		map_bad_word(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		stop_at(&mut system, ROM_VIRT + 4);
+
+		system.set_decode_error_policy(&cpu, DecodeErrorPolicy::Trap).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(0x80000080, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(ROM_VIRT + 4, system.get_cpu_reg(&cpu, mips::REG_CP0_EPC).unwrap());
+		assert_eq!(10 << 2, system.get_cpu_reg(&cpu, mips::REG_CP0_CAUSE).unwrap() & 0x7C);
+	}
+
+	// teq $a0, $a1, then an ori for the untrapped case to fall through to.
+	// Traps are MIPS II, so only a core past MIPS I decodes the teq.
+	fn map_teq(system: &mut System) {
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+		system.set_u32(ROM_BASE, 0x00850034, mem::Endian::Big).unwrap();
+		system.set_u32(ROM_BASE + 4, 0x34210001, mem::Endian::Big).unwrap();
+	}
+
+	#[test]
+	fn teq_traps_when_equal() {
+		let mut system = System::new();
+
+		map_teq(&mut system);
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG, Arch::Mips(mips::Arch::Mips4Kc)).unwrap();
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 7).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(5), 7).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		system.step_one_with_diff(&cpu).unwrap();
+
+		assert_eq!(0x80000080, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&cpu, mips::REG_CP0_EPC).unwrap());
+		assert_eq!(13 << 2, system.get_cpu_reg(&cpu, mips::REG_CP0_CAUSE).unwrap() & 0x7C);
+	}
+
+	#[test]
+	fn teq_falls_through_when_not_equal() {
+		let mut system = System::new();
+
+		map_teq(&mut system);
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG, Arch::Mips(mips::Arch::Mips4Kc)).unwrap();
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 7).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(5), 8).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		system.step_one_with_diff(&cpu).unwrap();
+
+		assert_eq!(ROM_VIRT + 4, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0, system.get_cpu_reg(&cpu, mips::REG_CP0_CAUSE).unwrap());
+	}
+
+	#[test]
+	fn teq_is_reserved_on_mips_i() {
+		let mut system = System::new();
+
+		map_teq(&mut system);
+
+		let cpu = register_r2000(&mut system);
+
+		system.set_decode_error_policy(&cpu, DecodeErrorPolicy::Trap).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 7).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(5), 7).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		system.step_one_with_diff(&cpu).unwrap();
+
+		assert_eq!(0x80000080, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(ROM_VIRT, system.get_cpu_reg(&cpu, mips::REG_CP0_EPC).unwrap());
+		assert_eq!(10 << 2, system.get_cpu_reg(&cpu, mips::REG_CP0_CAUSE).unwrap() & 0x7C);
+	}
+
+	#[test]
+	fn trap_on_zero_fetch() {
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, ROM_BASE, ROM_SIZE).unwrap();
+		system.set_range(&ORI_AT_AT_0X3456_EB, ROM_BASE).unwrap();
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG | CPU_TRAP_ON_ZERO_FETCH, Arch::Mips(mips::Arch::R2000)).unwrap();
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT + 0x1000).unwrap();
+
+		assert_eq!(ExitReason::ZeroInstructionFetch(ROM_VIRT + 0x1000), system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 0x1000, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+	}
+
+	// Two lbu $t0/$t1, 0($a0) from a UART at 0x1000, holding whatever input it's
+	// given, then runs to the ori after them
+	fn run_uart_reads(input: &[u8], setup: &Fn(&mut System, &CpuCookie)) -> (System, CpuCookie) {
+		let (mut system, cpu) = uart_reads(input, setup);
+
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+
+		(system, cpu)
+	}
+
+	fn uart_reads(input: &[u8], setup: &Fn(&mut System, &CpuCookie)) -> (System, CpuCookie) {
+		const CODE: [u32; 3] = [
+			0x90880000, // lbu $t0, 0($a0)
+			0x90890000, // lbu $t1, 0($a0)
+			0x34210001, // ori $at, $at, 0x1
+		];
+
+		let mut system = System::new();
+
+		load_rom(&mut system, &CODE);
+
+		let mut uart = mem::Uart16550::new(Vec::new(), 0);
+		uart.receive(input);
+
+		system.add_bus_slave(0x1000, 8, Arc::new(Mutex::new(uart))).unwrap();
+
+		let cpu = register_r2000(&mut system);
+
+		setup(&mut system, &cpu);
+
+		stop_at(&mut system, ROM_VIRT + 8);
+
+		system.set_cpu_reg(&cpu, CpuReg::CpuSpecific(4), 0xA0001000).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		(system, cpu)
+	}
+
+	#[test]
+	fn replay_feeds_back_recorded_mmio_reads() {
+		let (mut recorded, recorded_cpu) = run_uart_reads(b"AB", &|system, cpu| system.start_recording(cpu).unwrap());
+
+		let trace = recorded.stop_recording(&recorded_cpu).unwrap();
+
+		assert_eq!(vec!(ReplayEvent{insn_count: 0, read: mem::MmioRead{addr: 0x1000, width: 1, value: 0x41}},
+		                ReplayEvent{insn_count: 1, read: mem::MmioRead{addr: 0x1000, width: 1, value: 0x42}}),
+		           trace);
+
+		// The replayed UART has nothing to give, so only the trace can fill $t0/$t1
+		let (mut replayed, replayed_cpu) = run_uart_reads(b"", &|system, cpu| system.start_replay(cpu, trace.clone()).unwrap());
+
+		for reg in [CpuReg::Pc, CpuReg::CpuSpecific(8), CpuReg::CpuSpecific(9)].iter() {
+			assert_eq!(recorded.get_cpu_reg(&recorded_cpu, reg.clone()).unwrap(),
+			           replayed.get_cpu_reg(&replayed_cpu, reg.clone()).unwrap());
+		}
+
+		assert_eq!(0x42, replayed.get_cpu_reg(&replayed_cpu, CpuReg::CpuSpecific(9)).unwrap());
+	}
+
+	#[test]
+	fn replay_stops_on_a_read_the_trace_doesnt_hold() {
+		let trace = vec!(ReplayEvent{insn_count: 0, read: mem::MmioRead{addr: 0x1000, width: 1, value: 0x41}},
+		                 ReplayEvent{insn_count: 1, read: mem::MmioRead{addr: 0x1004, width: 1, value: 0x42}});
+
+		// The UART has input to give, which the diverged read mustn't take
+		let (mut system, cpu) = uart_reads(b"Z", &|system, cpu| system.start_replay(cpu, trace.clone()).unwrap());
+
+		assert_eq!(ExitReason::ReplayDiverged{insn_count: 1, expected: Some(trace[1].read), actual: Some((0x1000, 1))},
+		           system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 4, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0x41, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(8)).unwrap());
+		assert_eq!(0, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(9)).unwrap());
+
+		// Replay is off now, so rerunning reads the live UART
+		assert_eq!(ExitReason::CodeHookSignalledStop, system.execute(&cpu).unwrap());
+		assert_eq!(0x5A, system.get_cpu_reg(&cpu, CpuReg::CpuSpecific(9)).unwrap());
+	}
+
+	#[test]
+	fn replay_stops_on_reads_left_unread() {
+		let trace = vec!(ReplayEvent{insn_count: 0, read: mem::MmioRead{addr: 0x1000, width: 1, value: 0x41}},
+		                 ReplayEvent{insn_count: 1, read: mem::MmioRead{addr: 0x1000, width: 1, value: 0x42}},
+		                 ReplayEvent{insn_count: 2, read: mem::MmioRead{addr: 0x1000, width: 1, value: 0x43}});
+
+		let (mut system, cpu) = uart_reads(b"", &|system, cpu| system.start_replay(cpu, trace.clone()).unwrap());
+
+		// The ori never reads the UART
+		assert_eq!(ExitReason::ReplayDiverged{insn_count: 2, expected: Some(trace[2].read), actual: None},
+		           system.execute(&cpu).unwrap());
+	}
+
+	#[test]
+	fn r2000_reaches_only_32_bits() {
+		let mut system = System::new();
+
+		// No CPU yet to say what's reachable, but it's checked once there is
+		system.add_mappable_range(PROT_ALL, 1 << 32, 0x1000).unwrap();
+
+		match system.register_cpu(CPU_ENDIAN_BIG, Arch::Mips(mips::Arch::R2000)) {
+			Err(Error::UnreachableRange(0x100000000, 0x1000)) => {},
+			Err(other) => panic!("Unexpected error:  {:?}", other),
+			Ok(_)      => panic!("Registered a CPU that can't reach mapped memory"),
+		}
+
+		let mut system = System::new();
+
+		let cpu = register_r2000(&mut system);
+
+		assert_eq!(32, system.address_space_bits(&cpu).unwrap());
+
+		system.add_mappable_range(PROT_ALL, 0xFFFFF000, 0x1000).unwrap();
+
+		match system.add_mappable_range(PROT_ALL, 1 << 32, 0x1000) {
+			Err(Error::UnreachableRange(0x100000000, 0x1000)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match system.add_mappable_range(PROT_ALL, 0xFFFFF000, 0x1001) {
+			Err(Error::UnreachableRange(0xFFFFF000, 0x1001)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match system.add_alias(0xFFFFF000, 1 << 32) {
+			Err(Error::UnreachableRange(0x100000000, 0x1000)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match system.add_watchdog(&cpu, 1 << 32, Arc::new(Mutex::new(mem::Watchdog::new(100)))) {
+			Err(Error::UnreachableRange(0x100000000, _)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, 0xFFFFFFFF_BFC00000).unwrap();
+
+		match system.set_cpu_reg(&cpu, CpuReg::Pc, 1 << 32) {
+			Err(Error::SetRegValueOutOfRange(CpuReg::Pc, 0x100000000)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn exit_at_end_of_code_stops_after_the_last_word() {
+		let mut code = Vec::new();
+
+		code.extend(ORI_AT_AT_0X3456_EB.iter());
+		code.extend([0x34, 0x21, 0x00, 0x01].iter()); // ori $at, $at, 0x1
+
+		let mut system = System::new();
+
+		system.add_ram_range(ROM_BASE, code, PROT_ALL).unwrap();
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG | CPU_EXIT_AT_END_OF_CODE, Arch::Mips(mips::Arch::R2000)).unwrap();
+
+		system.set_cpu_reg(&cpu, mips::REG_AT, 0).unwrap();
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::FellOffEnd(ROM_VIRT + 8), system.execute(&cpu).unwrap());
+		assert_eq!(ROM_VIRT + 8, system.get_cpu_reg(&cpu, CpuReg::Pc).unwrap());
+		assert_eq!(0x3457, system.get_cpu_reg(&cpu, mips::REG_AT).unwrap());
+	}
+
+	#[test]
+	fn segment_larger_than_its_range_rejected() {
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, 0x1000).unwrap();
+
+		let data = [0xAAu8; 4];
+		let segment = elf::Segment{ addr: RAM_BASE, data: &data, mem_size: 0x7FFFFFFF };
+
+		match system.load_segments(&[segment]) {
+			Err(Error::UnableToFindRange(RAM_BASE, 0x7FFFFFFF)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		assert!(!system.fsb.is_initialized(RAM_BASE, 4));
+	}
+
+	#[test]
+	fn elf_moved_past_the_top_of_memory_rejected() {
+		let mut system = System::new();
+
+		system.add_mappable_range(PROT_ALL, RAM_BASE, 0x2000).unwrap();
+
+		// The entry point at 0x80001000 overflows, the segment at 0x1000 doesn't
+		match system.load_elf_at(&elf::tests::elf32_be(), u64::max_value() - 0x7FFF) {
+			Err(Error::MalformedImage(_)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		// With the entry point at 0, only the segment overflows
+		let mut bytes = elf::tests::elf32_be();
+		bytes[0x18..0x1C].copy_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+
+		match system.load_elf_at(&bytes, u64::max_value() - 0xFFF) {
+			Err(Error::MalformedImage(_)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		assert!(!system.fsb.is_initialized(0x1000, 4));
+
+		assert_eq!(0, system.load_elf_at(&bytes, 0).unwrap());
+		assert!(system.fsb.is_initialized(0x1000, 4));
+	}
+
+	#[test]
+	fn each_step_decodes_once() {
+		let mut code = Vec::new();
+
+		code.extend(ORI_AT_AT_0X3456_EB.iter());
+		code.extend([0x34, 0x21, 0x00, 0x01].iter()); // ori $at, $at, 0x1
+
+		let mut system = System::new();
+
+		system.add_ram_range(ROM_BASE, code, PROT_ALL).unwrap();
+
+		let cpu = system.register_cpu(CPU_ENDIAN_BIG | CPU_EXIT_AT_END_OF_CODE, Arch::Mips(mips::Arch::R2000)).unwrap();
+
+		let decodes = Arc::new(Mutex::new(Vec::new()));
+		let rewriter_decodes = decodes.clone();
+
+		system.add_decode_rewriter(Arc::new(Mutex::new(move |address, _: &mut Vec<iisa::Instr>| {
+			rewriter_decodes.lock().unwrap().push(address);
+		}))).unwrap();
+
+		system.add_op_class_hook(iisa::OpClass::Logical, Arc::new(Mutex::new(|_, _: &iisa::Op| {
+			TraceExitHint::ContinueExecution
+		}))).unwrap();
+
+		system.add_code_hook_single(ROM_VIRT + 4, Arc::new(Mutex::new(|_, _, _| {
+			TraceExitHint::ContinueExecution
+		}))).unwrap();
+
+		system.set_cpu_reg(&cpu, CpuReg::Pc, ROM_VIRT).unwrap();
+
+		assert_eq!(ExitReason::FellOffEnd(ROM_VIRT + 8), system.execute(&cpu).unwrap());
+		assert_eq!(vec!(ROM_VIRT, ROM_VIRT + 4), *decodes.lock().unwrap());
+	}
+}
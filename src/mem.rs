@@ -1,9 +1,14 @@
 extern crate libc;
 
-use super::{MemProt, Error, PROT_READ};
+use super::{MemProt, Error, PROT_READ, PROT_RW, PROT_WRITE};
 
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Write;
 use std::mem;
 use std::ptr;
+use std::slice;
 use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Eq, PartialEq)]
@@ -20,6 +25,34 @@ pub enum WriteResult {
 	Success,
 }
 
+// The results don't know where the access went, so the caller supplies the
+// address (and for writes, the width) used to build the Error.
+impl<T> ReadResult<T> {
+	pub fn into_result(self, addr: u64) -> Result<T, Error> {
+		match self {
+			ReadResult::Success(value) => Ok(value),
+			ReadResult::Unaligned      => Err(Error::UnalignedAccess(addr)),
+			ReadResult::BusError       => Err(Error::BusFault{ addr: addr, width: mem::size_of::<T>() as u8, is_write: false }),
+		}
+	}
+}
+
+impl WriteResult {
+	pub fn into_result(self, addr: u64, width: u8) -> Result<(), Error> {
+		match self {
+			WriteResult::Success   => Ok(()),
+			WriteResult::Unaligned => Err(Error::UnalignedAccess(addr)),
+			WriteResult::BusError  => Err(Error::BusFault{ addr: addr, width: width, is_write: true }),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Endian {
+	Big,
+	Little,
+}
+
 pub trait BusSlave {
 	fn read_u8(&mut self, addr: u64) -> ReadResult<u8>;
 	fn read_u16(&mut self, addr: u64) -> ReadResult<u16>;
@@ -31,14 +64,129 @@ pub trait BusSlave {
 	fn write_u64(&mut self, addr: u64, data: u64) -> WriteResult;
 }
 
+// cells either comes from posix_memalign, or from Box::into_raw when the
+// memory was handed over by the caller instead (boxed)
+struct Backing {
+	cells: *mut UnsafeCell<u8>,
+	size: u64,
+	boxed: bool,
+}
+
+// The allocation never moves, and is only freed once the last range (in any
+// matrix, under any alias) referencing it has been dropped.  Every holder sees
+// the same bytes, same as the guest's view of physical memory, so they're
+// shared mutable state: each byte is an UnsafeCell, and they're only reached
+// by copying through raw pointers (see MappedRegion::read/write), never
+// through a reference that another holder could invalidate.
+unsafe impl Send for Backing { }
+unsafe impl Sync for Backing { }
+
+impl Drop for Backing {
+	fn drop(&mut self) {
+		if self.boxed {
+			unsafe { drop(Box::from_raw(slice::from_raw_parts_mut(self.cells, self.size as usize) as *mut [UnsafeCell<u8>])) };
+		} else {
+			unsafe { libc::free(self.cells as *mut libc::c_void) };
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct MappedRegion {
+	backing: Arc<Backing>,
+}
+
+impl MappedRegion {
+	fn alloc(size: u64) -> Result<MappedRegion, Error> {
+		let ptr: *mut u8 = unsafe {
+			let mut page_mem: *mut libc::c_void = mem::transmute(0 as usize);
+
+			match libc::posix_memalign(&mut page_mem, 4096, size as libc::size_t) {
+				// posix_memalign doesn't clear what it hands back, and guests (and
+				// tests) expect fresh memory to read as zero
+				0 => {
+					ptr::write_bytes(page_mem as *mut u8, 0, size as usize);
+
+					page_mem as *mut u8
+				},
+
+				_ => {
+					return Err(Error::MemAllocation);
+				},
+			}
+		};
+
+		Ok(MappedRegion {
+			backing: Arc::new(Backing {
+				cells: ptr as *mut UnsafeCell<u8>,
+				size:  size,
+				boxed: false,
+			}),
+		})
+	}
+
+	// Takes over data as the region's memory, without going through libc.  Only
+	// the raw pointer is kept, as a Box alongside it would claim the bytes
+	// exclusively out from under every write made through the pointer.
+	fn from_vec(data: Vec<u8>) -> MappedRegion {
+		let size = data.len() as u64;
+		let cells = Box::into_raw(data.into_boxed_slice()) as *mut u8;
+
+		MappedRegion {
+			backing: Arc::new(Backing {
+				cells: cells as *mut UnsafeCell<u8>,
+				size:  size,
+				boxed: true,
+			}),
+		}
+	}
+
+	pub fn size(&self) -> u64 {
+		self.backing.size
+	}
+
+	pub fn shares_backing(&self, other: &MappedRegion) -> bool {
+		Arc::ptr_eq(&self.backing, &other.backing)
+	}
+
+	fn checked_offset(&self, offset: u64, len: usize) -> Option<usize> {
+		match offset.checked_add(len as u64) {
+			Some(end) if end <= self.backing.size => Some(offset as usize),
+			_                                     => None,
+		}
+	}
+
+	fn ptr_at(&self, offset: u64, len: usize) -> Option<*mut u8> {
+		// UnsafeCell<u8> has u8's layout, and the pointer keeps the whole
+		// allocation's provenance (where going through a &UnsafeCell wouldn't)
+		self.checked_offset(offset, len).map(|offset| unsafe { self.backing.cells.offset(offset as isize) as *mut u8 })
+	}
+
+	// Bytes are only ever copied in and out, never lent out as slices, as any
+	// clone of the region may be writing them at the same time
+	pub fn read(&self, offset: u64, buf: &mut [u8]) -> Option<()> {
+		self.ptr_at(offset, buf.len()).map(|ptr| unsafe { ptr::copy_nonoverlapping(ptr as *const u8, buf.as_mut_ptr(), buf.len()) })
+	}
+
+	pub fn write(&mut self, offset: u64, data: &[u8]) -> Option<()> {
+		self.ptr_at(offset, data.len()).map(|ptr| unsafe { ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len()) })
+	}
+
+	pub fn read_scalar<T: Copy>(&self, offset: u64) -> Option<T> {
+		self.ptr_at(offset, mem::size_of::<T>()).map(|ptr| unsafe { ptr::read_unaligned(ptr as *const T) })
+	}
+
+	pub fn write_scalar<T: Copy>(&mut self, offset: u64, value: T) -> Option<()> {
+		self.ptr_at(offset, mem::size_of::<T>()).map(|ptr| unsafe { ptr::write_unaligned(ptr as *mut T, value) })
+	}
+}
+
 #[derive(Clone)]
 pub enum MemRangeImpl {
-	Mappable(*mut u8, MemProt),
+	Mappable(MappedRegion, MemProt),
 	Mmio(Arc<Mutex<BusSlave + Send>>),
 }
 
-unsafe impl Send for MemRangeImpl { }
-
 #[derive(Clone)]
 pub struct MemRange {
 	base: u64,
@@ -46,63 +194,352 @@ pub struct MemRange {
 	backing: MemRangeImpl,
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub enum ProbeResult {
+	Unmapped,
+	Allowed,
+	Denied,
+}
+
 pub enum BusMatrixUpdateOp {
 	Add(MemRange),
+	Initialized(u64, u64),
+	WriteFaultHandler(Option<WriteFaultHandler>),
+}
+
+// What to do about a write to mappable memory without PROT_WRITE
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WriteFaultAction {
+	// Fail it with a bus error, same as with no handler
+	Deny,
+	// Let this write through, but keep faulting on later ones
+	Allow,
+	// Add PROT_WRITE to the range (in this matrix) and let the write through
+	MakeWritable,
+}
+
+// Called with the address and data of the faulting write
+pub type WriteFaultHandler = Arc<Fn(u64, u64) -> WriteFaultAction + Send + Sync>;
+
+fn write_fault_allows(handler: &Option<WriteFaultHandler>, prot: &mut MemProt, addr: u64, data: u64) -> bool {
+	match handler.as_ref().map(|handler| handler(addr, data)) {
+		Some(WriteFaultAction::Allow) => true,
+
+		Some(WriteFaultAction::MakeWritable) => {
+			prot.insert(PROT_WRITE);
+
+			true
+		},
+
+		Some(WriteFaultAction::Deny) | None => false,
+	}
+}
+
+// A write that touched the watched range.  data is as it was handed to the
+// bus, so its bytes are in guest memory order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WatchHit {
+	pub addr: u64,
+	pub width: u8,
+	pub data: u64,
+}
+
+// A value a bus slave handed back for a read
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MmioRead {
+	pub addr: u64,
+	pub width: u8,
+	pub value: u64,
+}
+
+// Record keeps every successful bus slave read.  Replay answers reads from the
+// front of the queue instead of asking the slave.  A read that isn't the one
+// queued next never reaches the slave; it bus errors and leaves the tap
+// Diverged, holding the read that was expected (if any) and the one made.
+#[derive(Debug)]
+pub enum MmioTap {
+	Record(Vec<MmioRead>),
+	Replay(VecDeque<MmioRead>),
+	Diverged { expected: Option<MmioRead>, addr: u64, width: u8 },
+}
+
+fn tap_read<T, N, F>(tap: &mut Option<MmioTap>, addr: u64, narrow: N, read: F) -> ReadResult<T>
+	where T: Copy + Into<u64>, N: FnOnce(u64) -> T, F: FnOnce() -> ReadResult<T> {
+	let width = mem::size_of::<T>() as u8;
+
+	let expected = match *tap {
+		Some(MmioTap::Replay(ref mut reads)) => match reads.pop_front() {
+			Some(read) if read.addr == addr && read.width == width => return ReadResult::Success(narrow(read.value)),
+
+			expected => expected,
+		},
+
+		Some(MmioTap::Diverged{..}) => return ReadResult::BusError,
+
+		_ => None,
+	};
+
+	if let Some(MmioTap::Replay(_)) = *tap {
+		*tap = Some(MmioTap::Diverged{expected: expected, addr: addr, width: width});
+
+		return ReadResult::BusError;
+	}
+
+	let result = read();
+
+	if let (&mut Some(MmioTap::Record(ref mut reads)), &ReadResult::Success(value)) = (tap, &result) {
+		reads.push(MmioRead{addr: addr, width: width, value: value.into()});
+	}
+
+	result
 }
 
 #[derive(Default)]
 pub struct BusMatrix {
 	ranges: Vec<MemRange>,
+	initialized: Vec<(u64, u64)>,
 	update_fns: Vec<Box<FnMut(BusMatrixUpdateOp)>>,
+	write_watch: Option<(u64, u64)>,
+	watch_hit: Option<WatchHit>,
+	write_fault_handler: Option<WriteFaultHandler>,
+	mmio_tap: Option<MmioTap>,
+}
+
+fn check_range_size(base: u64, size: u64) -> Result<(), Error> {
+	if size == 0 {
+		return Err(Error::InvalidRangeSize(base, size));
+	}
+
+	match base.checked_add(size) {
+		Some(_) => Ok(()),
+		None    => Err(Error::InvalidRangeSize(base, size)),
+	}
 }
 
 impl BusMatrix {
 	pub fn add_mappable_range(&mut self, base: u64, size: u64, prot: MemProt) -> Result<(), Error> {
-		let ptr: *mut u8 = unsafe {
-			let mut page_mem: *mut libc::c_void = mem::transmute(0 as usize);
+		try!(check_range_size(base, size));
 
-			match libc::posix_memalign(&mut page_mem, 4096, size as libc::size_t) {
-				0 => {
-					page_mem as *mut u8
-				},
+		let region = try!(MappedRegion::alloc(size));
 
-				_ => {
-					return Err(Error::MemAllocation);
-				},
-			}
-		};
+		self.add_range(MemRange{base: base, size: size, backing: MemRangeImpl::Mappable(region, prot)});
+
+		Ok(())
+	}
+
+	// Maps data itself at base, already initialized with whatever it holds
+	pub fn add_owned_range(&mut self, base: u64, data: Vec<u8>, prot: MemProt) -> Result<(), Error> {
+		let size = data.len() as u64;
 
-		self.ranges.push(MemRange{base: base, size: size, backing: MemRangeImpl::Mappable(ptr, prot)});
+		try!(check_range_size(base, size));
+
+		self.add_range(MemRange{base: base, size: size, backing: MemRangeImpl::Mappable(MappedRegion::from_vec(data), prot)});
+		self.mark_initialized(base, size);
 
 		Ok(())
 	}
 
 	pub fn add_bus_slave(&mut self, base: u64, size: u64, slave: Arc<Mutex<BusSlave + Send>>) -> Result<(), Error> {
-		self.ranges.push(MemRange{base: base, size: size, backing: MemRangeImpl::Mmio(slave)});
+		try!(check_range_size(base, size));
+
+		self.add_range(MemRange{base: base, size: size, backing: MemRangeImpl::Mmio(slave)});
+
+		Ok(())
+	}
+
+	// Replaces the handler consulted on writes to ranges without PROT_WRITE,
+	// here and in every child matrix
+	pub fn set_write_fault_handler(&mut self, handler: Option<WriteFaultHandler>) {
+		for update_fn in self.update_fns.iter_mut() {
+			update_fn(BusMatrixUpdateOp::WriteFaultHandler(handler.clone()));
+		}
+
+		self.write_fault_handler = handler;
+	}
+
+	// Only affects this matrix, not its children
+	pub fn set_mmio_tap(&mut self, tap: Option<MmioTap>) {
+		self.mmio_tap = tap;
+	}
+
+	pub fn take_mmio_tap(&mut self) -> Option<MmioTap> {
+		self.mmio_tap.take()
+	}
+
+	// Only one range is watched at a time, and only the first write into it is
+	// held until taken; installing a watch (or None) drops any pending hit.
+	pub fn set_write_watch(&mut self, watch: Option<(u64, u64)>) {
+		self.write_watch = watch;
+		self.watch_hit = None;
+	}
+
+	pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+		self.watch_hit.take()
+	}
+
+	fn note_write(&mut self, addr: u64, width: u8, data: u64) {
+		if let Some((base, len)) = self.write_watch {
+			if self.watch_hit.is_none() && addr < base.saturating_add(len) && base < addr.saturating_add(width as u64) {
+				self.watch_hit = Some(WatchHit {
+					addr:  addr,
+					width: width,
+					data:  data,
+				});
+			}
+		}
+	}
+
+	// Installs a second window at alias_base onto the range based at
+	// existing_base, so accesses through either see the same backing.
+	pub fn add_alias(&mut self, existing_base: u64, alias_base: u64) -> Result<(), Error> {
+		let (size, backing) = match self.ranges.iter().find(|range| range.base == existing_base) {
+			Some(range) => (range.size, range.backing.clone()),
+			None        => return Err(Error::UnableToFindRange(existing_base, 0)),
+		};
+
+		try!(check_range_size(alias_base, size));
+
+		self.add_range(MemRange{base: alias_base, size: size, backing: backing});
 
 		Ok(())
 	}
 
+	fn add_range(&mut self, range: MemRange) {
+		for update_fn in self.update_fns.iter_mut() {
+			update_fn(BusMatrixUpdateOp::Add(range.clone()));
+		}
+
+		self.ranges.push(range);
+	}
+
 	pub fn apply_update_op(&mut self, op: BusMatrixUpdateOp) {
 		match op {
 			BusMatrixUpdateOp::Add(range) => {
 				self.ranges.push(range);
 			},
+			BusMatrixUpdateOp::Initialized(base, size) => {
+				self.initialized.push((base, size));
+			},
+			BusMatrixUpdateOp::WriteFaultHandler(handler) => {
+				self.write_fault_handler = handler;
+			},
+		}
+	}
+
+	pub fn is_initialized(&self, base: u64, len: u64) -> bool {
+		self.initialized.iter().any(|&(init_base, init_size)| {
+			(base >= init_base) && ((base + len) <= (init_base + init_size))
+		})
+	}
+
+	// Reports what an access would do without performing it.  MMIO decides
+	// for itself whether to fault, so it's always reported as allowed.
+	pub fn probe(&self, addr: u64, access: MemProt) -> ProbeResult {
+		for range in self.ranges.iter() {
+			if (addr < range.base) || ((addr - range.base) >= range.size) {
+				continue;
+			}
+
+			return match range.backing {
+				MemRangeImpl::Mappable(_, prot) if prot.contains(access) => ProbeResult::Allowed,
+				MemRangeImpl::Mappable(_, _)                             => ProbeResult::Denied,
+				MemRangeImpl::Mmio(_)                                    => ProbeResult::Allowed,
+			};
+		}
+
+		ProbeResult::Unmapped
+	}
+
+	// (base, size) of every range, aliases and MMIO included
+	pub fn range_extents(&self) -> Vec<(u64, u64)> {
+		self.ranges.iter().map(|range| (range.base, range.size)).collect()
+	}
+
+	// Aliases of a region share its backing, so only count it once
+	pub fn total_mapped_bytes(&self) -> u64 {
+		let mut counted: Vec<&MappedRegion> = Vec::new();
+
+		self.ranges.iter().fold(0, |total, range| {
+			match range.backing {
+				MemRangeImpl::Mappable(ref region, _) => {
+					if counted.iter().any(|counted| counted.shares_backing(region)) {
+						return total;
+					}
+
+					counted.push(region);
+
+					total + range.size
+				},
+				MemRangeImpl::Mmio(_) => total,
+			}
+		})
+	}
+
+	// Compares the RAM that both matrices map at the same addresses, returning
+	// (addr, self_byte, other_byte) for every byte that differs, in address
+	// order.  MMIO is never read, so it's never compared.
+	pub fn diff_mapped_bytes(&self, other: &BusMatrix) -> Vec<(u64, u8, u8)> {
+		let mut diffs = Vec::new();
+
+		for range in self.ranges.iter() {
+			let region = match range.backing {
+				MemRangeImpl::Mappable(ref region, _) => region,
+				MemRangeImpl::Mmio(_)                 => continue,
+			};
+
+			for other_range in other.ranges.iter() {
+				let other_region = match other_range.backing {
+					MemRangeImpl::Mappable(ref other_region, _) => other_region,
+					MemRangeImpl::Mmio(_)                       => continue,
+				};
+
+				let start = cmp::max(range.base, other_range.base);
+				let end = cmp::min(range.base + range.size, other_range.base + other_range.size);
+
+				if start >= end {
+					continue;
+				}
+
+				let len = (end - start) as usize;
+
+				let mut bytes = vec!(0u8; len);
+				let mut other_bytes = vec!(0u8; len);
+
+				match (region.read(start - range.base, &mut bytes), other_region.read(start - other_range.base, &mut other_bytes)) {
+					(Some(_), Some(_)) => {},
+					_                  => continue,
+				}
+
+				for (i, (byte, other_byte)) in bytes.iter().zip(other_bytes.iter()).enumerate() {
+					if byte != other_byte {
+						diffs.push((start + (i as u64), *byte, *other_byte));
+					}
+				}
+			}
 		}
+
+		// Overlapping ranges can find the same byte more than once
+		diffs.sort();
+		diffs.dedup_by_key(|diff| diff.0);
+
+		diffs
 	}
 
-	pub fn find_range(&self, base: u64, len: usize) -> Result<*mut u8, Error> {
-		let end = base + (len as u64);
+	fn find_region(&self, base: u64, len: usize) -> Result<(MappedRegion, u64), Error> {
+		let end = match base.checked_add(len as u64) {
+			Some(end) => end,
+			None      => return Err(Error::UnableToFindRange(base, len)),
+		};
 		for ref range in self.ranges.iter() {
-			let range_end = range.base + range.size;
+			let range_end = match range.base.checked_add(range.size) {
+				Some(range_end) => range_end,
+				None            => continue,
+			};
 			match range.backing {
-				MemRangeImpl::Mappable(buffer, _) => {
+				MemRangeImpl::Mappable(ref region, _) => {
 					if (base >= range.base) && (base < range_end) &&
 					   (end > range.base) && (end <= range_end) {
-						let offset = base - range.base;
-						unsafe {
-							return Ok(buffer.offset(offset as isize));
-						}
+						return Ok((region.clone(), base - range.base));
 					}
 				},
 				_ => {},// Skip everything else
@@ -111,23 +548,162 @@ impl BusMatrix {
 		Err(Error::UnableToFindRange(base, len))
 	}
 
+	// Checks that [base, base + len) is backed by a single mappable range
+	pub fn find_range(&self, base: u64, len: usize) -> Result<(), Error> {
+		let (region, offset) = try!(self.find_region(base, len));
+
+		match region.checked_offset(offset, len) {
+			Some(_) => Ok(()),
+			None    => Err(Error::UnableToFindRange(base, len)),
+		}
+	}
+
+	// Copies out the run of mapped bytes around addr that falls within the
+	// window [window_base, window_base + window_len), for callers that want as
+	// much of a window as is backed without requiring all of it to be.  Returns
+	// the run's base address along with its bytes.
+	pub fn read_span(&self, addr: u64, window_base: u64, window_len: usize) -> Result<(u64, Vec<u8>), Error> {
+		let (region, offset) = try!(self.find_region(addr, 1));
+
+		let range_base = addr - offset;
+		let start = if window_base > range_base { window_base } else { range_base };
+		let range_end = range_base.saturating_add(region.size());
+		let window_end = window_base.saturating_add(window_len as u64);
+		let end = if window_end < range_end { window_end } else { range_end };
+
+		if addr < start || addr >= end {
+			return Err(Error::UnableToFindRange(addr, 1));
+		}
+
+		let mut bytes = vec!(0u8; (end - start) as usize);
+
+		match region.read(start - range_base, &mut bytes) {
+			Some(()) => Ok((start, bytes)),
+			None     => Err(Error::UnableToFindRange(addr, 1)),
+		}
+	}
+
 	pub fn set_range(&mut self, incoming: &[u8], base: u64) -> Result<(), Error> {
-		let backing_range = try!(self.find_range(base, incoming.len()));
+		let (mut region, offset) = try!(self.find_region(base, incoming.len()));
 
-		unsafe {
-			ptr::copy_nonoverlapping(incoming.as_ptr(), backing_range, incoming.len());
+		if region.write(offset, incoming).is_none() {
+			return Err(Error::UnableToFindRange(base, incoming.len()));
 		}
 
+		self.mark_initialized(base, incoming.len() as u64);
+
 		Ok(())
 	}
 
+	fn mark_initialized(&mut self, base: u64, len: u64) {
+		self.initialized.push((base, len));
+
+		for update_fn in self.update_fns.iter_mut() {
+			update_fn(BusMatrixUpdateOp::Initialized(base, len));
+		}
+	}
+
+	pub fn set_u16(&mut self, addr: u64, value: u16, endian: Endian) -> Result<(), Error> {
+		let bytes: [u8; 2] = unsafe {
+			mem::transmute(match endian {
+				Endian::Big    => value.to_be(),
+				Endian::Little => value.to_le(),
+			})
+		};
+
+		self.set_range(&bytes, addr)
+	}
+
+	pub fn set_u32(&mut self, addr: u64, value: u32, endian: Endian) -> Result<(), Error> {
+		let bytes: [u8; 4] = unsafe {
+			mem::transmute(match endian {
+				Endian::Big    => value.to_be(),
+				Endian::Little => value.to_le(),
+			})
+		};
+
+		self.set_range(&bytes, addr)
+	}
+
+	pub fn set_u64(&mut self, addr: u64, value: u64, endian: Endian) -> Result<(), Error> {
+		let bytes: [u8; 8] = unsafe {
+			mem::transmute(match endian {
+				Endian::Big    => value.to_be(),
+				Endian::Little => value.to_le(),
+			})
+		};
+
+		self.set_range(&bytes, addr)
+	}
+
 	pub fn add_child_matrix(&mut self, mut update_fn: Box<FnMut(BusMatrixUpdateOp)>) {
 		for range in self.ranges.iter() {
 			update_fn(BusMatrixUpdateOp::Add(range.clone()));
 		}
 
+		for &(base, size) in self.initialized.iter() {
+			update_fn(BusMatrixUpdateOp::Initialized(base, size));
+		}
+
+		if self.write_fault_handler.is_some() {
+			update_fn(BusMatrixUpdateOp::WriteFaultHandler(self.write_fault_handler.clone()));
+		}
+
 		self.update_fns.push(update_fn)
 	}
+
+	pub fn modify_u32<F: FnOnce(u32) -> u32>(&mut self, addr: u64, f: F) -> Result<(), Error> {
+		let end = match addr.checked_add(4) {
+			Some(end) => end,
+			None      => return Err(Error::BusFault{ addr: addr, width: 4, is_write: false }),
+		};
+
+		for range in self.ranges.iter_mut() {
+			let range_end = match range.base.checked_add(range.size) {
+				Some(range_end) => range_end,
+				None            => continue,
+			};
+
+			if (addr < range.base) || (end > range_end) {
+				continue;
+			}
+
+			let addr_offset = addr - range.base;
+			return match range.backing {
+				MemRangeImpl::Mappable(ref mut region, prot) => {
+					if !prot.contains(PROT_RW) {
+						return Err(Error::BusFault{ addr: addr, width: 4, is_write: true });
+					}
+
+					let value = match region.read_scalar::<u32>(addr_offset) {
+						Some(value) => value,
+						None        => return Err(Error::BusFault{ addr: addr, width: 4, is_write: false }),
+					};
+
+					match region.write_scalar(addr_offset, f(value)) {
+						Some(()) => Ok(()),
+						None     => Err(Error::BusFault{ addr: addr, width: 4, is_write: true }),
+					}
+				},
+				MemRangeImpl::Mmio(ref mut slave_mutex) => {
+					// Hold the lock across both halves so that no other accessor of
+					// this slave can observe or clobber the intermediate state
+					let mut slave = match slave_mutex.lock() {
+						Ok(slave) => slave,
+						Err(_) => {
+							return Err(Error::BusFault{ addr: addr, width: 4, is_write: false });
+						},
+					};
+
+					let value = try!(slave.read_u32(addr_offset).into_result(addr));
+
+					slave.write_u32(addr_offset, f(value)).into_result(addr, 4)
+				},
+			};
+		}
+
+		Err(Error::BusFault{ addr: addr, width: 4, is_write: false })
+	}
 }
 
 impl BusSlave for BusMatrix {
@@ -139,11 +715,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
+				MemRangeImpl::Mappable(ref region, prot) => {
 					if prot.contains(PROT_READ) {
-						ReadResult::Success(unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u8)
-						})
+						match region.read_scalar::<u8>(addr_offset) {
+							Some(value) => ReadResult::Success(value),
+							None        => ReadResult::BusError,
+						}
 					} else {
 						ReadResult::BusError
 					}
@@ -156,7 +733,7 @@ impl BusSlave for BusMatrix {
 						},
 					};
 						
-					slave.read_u8(addr_offset)
+					tap_read(&mut self.mmio_tap, addr, |value| value as u8, || slave.read_u8(addr_offset))
 				},
 			};
 		}
@@ -172,11 +749,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
+				MemRangeImpl::Mappable(ref region, prot) => {
 					if prot.contains(PROT_READ) {
-						ReadResult::Success(unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u16)
-						})
+						match region.read_scalar::<u16>(addr_offset) {
+							Some(value) => ReadResult::Success(value),
+							None        => ReadResult::BusError,
+						}
 					} else {
 						ReadResult::BusError
 					}
@@ -189,7 +767,7 @@ impl BusSlave for BusMatrix {
 						},
 					};
 						
-					slave.read_u16(addr_offset)
+					tap_read(&mut self.mmio_tap, addr, |value| value as u16, || slave.read_u16(addr_offset))
 				},
 			};
 		}
@@ -205,11 +783,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
+				MemRangeImpl::Mappable(ref region, prot) => {
 					if prot.contains(PROT_READ) {
-						ReadResult::Success(unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u32)
-						})
+						match region.read_scalar::<u32>(addr_offset) {
+							Some(value) => ReadResult::Success(value),
+							None        => ReadResult::BusError,
+						}
 					} else {
 						ReadResult::BusError
 					}
@@ -222,7 +801,7 @@ impl BusSlave for BusMatrix {
 						},
 					};
 						
-					slave.read_u32(addr_offset)
+					tap_read(&mut self.mmio_tap, addr, |value| value as u32, || slave.read_u32(addr_offset))
 				},
 			};
 		}
@@ -238,11 +817,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
+				MemRangeImpl::Mappable(ref region, prot) => {
 					if prot.contains(PROT_READ) {
-						ReadResult::Success(unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u64)
-						})
+						match region.read_scalar::<u64>(addr_offset) {
+							Some(value) => ReadResult::Success(value),
+							None        => ReadResult::BusError,
+						}
 					} else {
 						ReadResult::BusError
 					}
@@ -255,7 +835,7 @@ impl BusSlave for BusMatrix {
 						},
 					};
 						
-					slave.read_u64(addr_offset)
+					tap_read(&mut self.mmio_tap, addr, |value| value as u64, || slave.read_u64(addr_offset))
 				},
 			};
 		}
@@ -264,6 +844,8 @@ impl BusSlave for BusMatrix {
 	}
 
 	fn write_u8(&mut self, addr: u64, data: u8) -> WriteResult {
+		self.note_write(addr, 1, data as u64);
+
 		for mut slave in self.ranges.iter_mut() {
 			if (addr < slave.base) || ((addr + 1) >= (slave.base + slave.size)) {
 				continue;
@@ -271,12 +853,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
-					if prot.contains(PROT_READ) {
-						unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u8) = data;
+				MemRangeImpl::Mappable(ref mut region, ref mut prot) => {
+					if prot.contains(PROT_WRITE) || write_fault_allows(&self.write_fault_handler, prot, addr, data as u64) {
+						match region.write_scalar(addr_offset, data) {
+							Some(()) => WriteResult::Success,
+							None     => WriteResult::BusError,
 						}
-						WriteResult::Success
 					} else {
 						WriteResult::BusError
 					}
@@ -298,6 +880,8 @@ impl BusSlave for BusMatrix {
 	}
 
 	fn write_u16(&mut self, addr: u64, data: u16) -> WriteResult {
+		self.note_write(addr, 2, data as u64);
+
 		for mut slave in self.ranges.iter_mut() {
 			if (addr < slave.base) || ((addr + 1) >= (slave.base + slave.size)) {
 				continue;
@@ -305,12 +889,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
-					if prot.contains(PROT_READ) {
-						unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u16) = data;
+				MemRangeImpl::Mappable(ref mut region, ref mut prot) => {
+					if prot.contains(PROT_WRITE) || write_fault_allows(&self.write_fault_handler, prot, addr, data as u64) {
+						match region.write_scalar(addr_offset, data) {
+							Some(()) => WriteResult::Success,
+							None     => WriteResult::BusError,
 						}
-						WriteResult::Success
 					} else {
 						WriteResult::BusError
 					}
@@ -332,6 +916,8 @@ impl BusSlave for BusMatrix {
 	}
 
 	fn write_u32(&mut self, addr: u64, data: u32) -> WriteResult {
+		self.note_write(addr, 4, data as u64);
+
 		for mut slave in self.ranges.iter_mut() {
 			if (addr < slave.base) || ((addr + 1) >= (slave.base + slave.size)) {
 				continue;
@@ -339,12 +925,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
-					if prot.contains(PROT_READ) {
-						unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u32) = data;
+				MemRangeImpl::Mappable(ref mut region, ref mut prot) => {
+					if prot.contains(PROT_WRITE) || write_fault_allows(&self.write_fault_handler, prot, addr, data as u64) {
+						match region.write_scalar(addr_offset, data) {
+							Some(()) => WriteResult::Success,
+							None     => WriteResult::BusError,
 						}
-						WriteResult::Success
 					} else {
 						WriteResult::BusError
 					}
@@ -366,6 +952,8 @@ impl BusSlave for BusMatrix {
 	}
 
 	fn write_u64(&mut self, addr: u64, data: u64) -> WriteResult {
+		self.note_write(addr, 8, data);
+
 		for mut slave in self.ranges.iter_mut() {
 			if (addr < slave.base) || ((addr + 1) >= (slave.base + slave.size)) {
 				continue;
@@ -373,12 +961,12 @@ impl BusSlave for BusMatrix {
 
 			let addr_offset = addr - slave.base;
 			return match slave.backing {
-				MemRangeImpl::Mappable(buffer, prot) => {
-					if prot.contains(PROT_READ) {
-						unsafe {
-							*(((buffer as u64) + addr_offset) as *mut u64) = data;
+				MemRangeImpl::Mappable(ref mut region, ref mut prot) => {
+					if prot.contains(PROT_WRITE) || write_fault_allows(&self.write_fault_handler, prot, addr, data as u64) {
+						match region.write_scalar(addr_offset, data) {
+							Some(()) => WriteResult::Success,
+							None     => WriteResult::BusError,
 						}
-						WriteResult::Success
 					} else {
 						WriteResult::BusError
 					}
@@ -400,9 +988,388 @@ impl BusSlave for BusMatrix {
 	}
 }
 
-#[cfg(test)]
-mod tests {
-	use super::{BusMatrix, BusMatrixUpdateOp, BusSlave, ReadResult, WriteResult};
+pub struct CoalescingSlave<S: BusSlave> {
+	inner: S,
+	endian: Endian,
+	pending_base: u64,
+	pending: [u8; 4],
+	pending_len: usize,
+}
+
+impl<S: BusSlave> CoalescingSlave<S> {
+	pub fn new(inner: S, endian: Endian) -> CoalescingSlave<S> {
+		CoalescingSlave {
+			inner:        inner,
+			endian:       endian,
+			pending_base: 0,
+			pending:      [0; 4],
+			pending_len:  0,
+		}
+	}
+
+	pub fn inner(&self) -> &S {
+		&self.inner
+	}
+
+	pub fn flush(&mut self) -> WriteResult {
+		let pending_len = self.pending_len;
+
+		self.pending_len = 0;
+
+		for i in 0..pending_len {
+			match self.inner.write_u8(self.pending_base + (i as u64), self.pending[i]) {
+				WriteResult::Success => {},
+				result               => { return result; },
+			}
+		}
+
+		WriteResult::Success
+	}
+
+	fn is_adjacent(&self, addr: u64) -> bool {
+		(self.pending_len != 0) && (addr == self.pending_base + (self.pending_len as u64))
+	}
+
+	fn push_bytes(&mut self, addr: u64, bytes: &[u8]) -> WriteResult {
+		if !self.is_adjacent(addr) {
+			match self.flush() {
+				WriteResult::Success => {},
+				result               => { return result; },
+			}
+
+			self.pending_base = addr;
+		}
+
+		for byte in bytes.iter() {
+			self.pending[self.pending_len] = *byte;
+			self.pending_len += 1;
+		}
+
+		if self.pending_len != self.pending.len() {
+			return WriteResult::Success;
+		}
+
+		let raw: u32 = unsafe { mem::transmute(self.pending) };
+		let data = match self.endian {
+			Endian::Big    => u32::from_be(raw),
+			Endian::Little => u32::from_le(raw),
+		};
+
+		self.pending_len = 0;
+
+		self.inner.write_u32(self.pending_base, data)
+	}
+}
+
+impl<S: BusSlave> BusSlave for CoalescingSlave<S> {
+	fn read_u8(&mut self, addr: u64) -> ReadResult<u8> {
+		match self.flush() {
+			WriteResult::Success => self.inner.read_u8(addr),
+			_                    => ReadResult::BusError,
+		}
+	}
+
+	fn read_u16(&mut self, addr: u64) -> ReadResult<u16> {
+		match self.flush() {
+			WriteResult::Success => self.inner.read_u16(addr),
+			_                    => ReadResult::BusError,
+		}
+	}
+
+	fn read_u32(&mut self, addr: u64) -> ReadResult<u32> {
+		match self.flush() {
+			WriteResult::Success => self.inner.read_u32(addr),
+			_                    => ReadResult::BusError,
+		}
+	}
+
+	fn read_u64(&mut self, addr: u64) -> ReadResult<u64> {
+		match self.flush() {
+			WriteResult::Success => self.inner.read_u64(addr),
+			_                    => ReadResult::BusError,
+		}
+	}
+
+	fn write_u8(&mut self, addr: u64, data: u8) -> WriteResult {
+		if !self.is_adjacent(addr) && ((addr & 0x3) != 0) {
+			match self.flush() {
+				WriteResult::Success => {},
+				result               => { return result; },
+			}
+
+			return self.inner.write_u8(addr, data);
+		}
+
+		self.push_bytes(addr, &[data])
+	}
+
+	fn write_u16(&mut self, addr: u64, data: u16) -> WriteResult {
+		let can_coalesce = if self.is_adjacent(addr) { (self.pending_len % 2) == 0 } else { (addr & 0x3) == 0 };
+
+		if !can_coalesce {
+			match self.flush() {
+				WriteResult::Success => {},
+				result               => { return result; },
+			}
+
+			return self.inner.write_u16(addr, data);
+		}
+
+		let bytes: [u8; 2] = unsafe {
+			mem::transmute(match self.endian {
+				Endian::Big    => data.to_be(),
+				Endian::Little => data.to_le(),
+			})
+		};
+
+		self.push_bytes(addr, &bytes)
+	}
+
+	fn write_u32(&mut self, addr: u64, data: u32) -> WriteResult {
+		match self.flush() {
+			WriteResult::Success => self.inner.write_u32(addr, data),
+			result               => result,
+		}
+	}
+
+	fn write_u64(&mut self, addr: u64, data: u64) -> WriteResult {
+		match self.flush() {
+			WriteResult::Success => self.inner.write_u64(addr, data),
+			result               => result,
+		}
+	}
+}
+
+const UART_RBR_THR: u64 = 0;
+const UART_IER: u64     = 1;
+const UART_IIR_FCR: u64 = 2;
+const UART_LCR: u64     = 3;
+const UART_MCR: u64     = 4;
+const UART_LSR: u64     = 5;
+const UART_MSR: u64     = 6;
+const UART_SCR: u64     = 7;
+
+const UART_LCR_DLAB: u8 = 0x80;
+const UART_LSR_DR: u8   = 0x01;
+const UART_LSR_THRE: u8 = 0x20;
+const UART_LSR_TEMT: u8 = 0x40;
+const UART_IIR_NONE: u8 = 0x01;
+
+// Just enough of a 16550 for a guest console.  Transmitted bytes go straight
+// to the sink, so the transmitter is always empty; received bytes are queued
+// with receive().  Registers are reg_shift bits apart, and only byte accesses
+// are decoded.  Interrupts, FIFOs and the modem lines aren't modelled, though
+// their registers read back what was written.
+pub struct Uart16550<W: Write> {
+	sink: W,
+	reg_shift: u8,
+	rx: VecDeque<u8>,
+	ier: u8,
+	lcr: u8,
+	mcr: u8,
+	scr: u8,
+	divisor: u16,
+}
+
+impl<W: Write> Uart16550<W> {
+	pub fn new(sink: W, reg_shift: u8) -> Uart16550<W> {
+		Uart16550 {
+			sink:      sink,
+			reg_shift: reg_shift,
+			rx:        VecDeque::new(),
+			ier:       0,
+			lcr:       0,
+			mcr:       0,
+			scr:       0,
+			divisor:   0,
+		}
+	}
+
+	pub fn sink(&self) -> &W {
+		&self.sink
+	}
+
+	pub fn receive(&mut self, bytes: &[u8]) {
+		self.rx.extend(bytes.iter().cloned());
+	}
+
+	fn reg_at(&self, addr: u64) -> Option<u64> {
+		let mask = (1u64 << self.reg_shift) - 1;
+
+		if (addr & mask) != 0 {
+			return None;
+		}
+
+		match addr >> self.reg_shift {
+			reg if reg <= UART_SCR => Some(reg),
+			_                      => None,
+		}
+	}
+
+	fn dlab(&self) -> bool {
+		(self.lcr & UART_LCR_DLAB) != 0
+	}
+}
+
+impl<W: Write> BusSlave for Uart16550<W> {
+	fn read_u8(&mut self, addr: u64) -> ReadResult<u8> {
+		let reg = match self.reg_at(addr) {
+			Some(reg) => reg,
+			None      => return ReadResult::BusError,
+		};
+
+		ReadResult::Success(match reg {
+			UART_RBR_THR if self.dlab() => self.divisor as u8,
+			UART_IER     if self.dlab() => (self.divisor >> 8) as u8,
+			UART_RBR_THR                => self.rx.pop_front().unwrap_or(0),
+			UART_IER                    => self.ier,
+			UART_IIR_FCR                => UART_IIR_NONE,
+			UART_LCR                    => self.lcr,
+			UART_MCR                    => self.mcr,
+			UART_LSR                    => {
+				let data_ready = if self.rx.is_empty() { 0 } else { UART_LSR_DR };
+
+				UART_LSR_THRE | UART_LSR_TEMT | data_ready
+			},
+			UART_MSR                    => 0,
+			_                           => self.scr,
+		})
+	}
+
+	fn read_u16(&mut self, _: u64) -> ReadResult<u16> {
+		ReadResult::BusError
+	}
+
+	fn read_u32(&mut self, _: u64) -> ReadResult<u32> {
+		ReadResult::BusError
+	}
+
+	fn read_u64(&mut self, _: u64) -> ReadResult<u64> {
+		ReadResult::BusError
+	}
+
+	fn write_u8(&mut self, addr: u64, data: u8) -> WriteResult {
+		let reg = match self.reg_at(addr) {
+			Some(reg) => reg,
+			None      => return WriteResult::BusError,
+		};
+
+		match reg {
+			UART_RBR_THR if self.dlab() => self.divisor = (self.divisor & 0xFF00) | (data as u16),
+			UART_IER     if self.dlab() => self.divisor = (self.divisor & 0x00FF) | ((data as u16) << 8),
+			UART_RBR_THR                => {
+				if self.sink.write_all(&[data]).is_err() {
+					return WriteResult::BusError;
+				}
+			},
+			UART_IER                    => self.ier = data,
+			UART_LCR                    => self.lcr = data,
+			UART_MCR                    => self.mcr = data,
+			UART_SCR                    => self.scr = data,
+			_                           => {}, // FCR, and the read only LSR and MSR
+		}
+
+		WriteResult::Success
+	}
+
+	fn write_u16(&mut self, _: u64, _: u16) -> WriteResult {
+		WriteResult::BusError
+	}
+
+	fn write_u32(&mut self, _: u64, _: u32) -> WriteResult {
+		WriteResult::BusError
+	}
+
+	fn write_u64(&mut self, _: u64, _: u64) -> WriteResult {
+		WriteResult::BusError
+	}
+}
+
+pub const WATCHDOG_SIZE: u64 = 8;
+
+// A write of any width to the one register, at offset 0, kicks the watchdog;
+// it reads as zero.  Bus slaves don't see time pass, so the countdown itself
+// is kept by the CPU the watchdog is attached to, in guest cycles.
+pub struct Watchdog {
+	timeout_cycles: u64,
+	kicked: bool,
+}
+
+impl Watchdog {
+	pub fn new(timeout_cycles: u64) -> Watchdog {
+		Watchdog {
+			timeout_cycles: timeout_cycles,
+			kicked:         false,
+		}
+	}
+
+	pub fn timeout_cycles(&self) -> u64 {
+		self.timeout_cycles
+	}
+
+	// Returns whether the guest has kicked since the last call
+	pub fn take_kick(&mut self) -> bool {
+		let kicked = self.kicked;
+
+		self.kicked = false;
+
+		kicked
+	}
+
+	fn read<T: Default>(&self, addr: u64) -> ReadResult<T> {
+		if addr == 0 { ReadResult::Success(T::default()) } else { ReadResult::BusError }
+	}
+
+	fn kick(&mut self, addr: u64) -> WriteResult {
+		if addr != 0 {
+			return WriteResult::BusError;
+		}
+
+		self.kicked = true;
+
+		WriteResult::Success
+	}
+}
+
+impl BusSlave for Watchdog {
+	fn read_u8(&mut self, addr: u64) -> ReadResult<u8> {
+		self.read(addr)
+	}
+
+	fn read_u16(&mut self, addr: u64) -> ReadResult<u16> {
+		self.read(addr)
+	}
+
+	fn read_u32(&mut self, addr: u64) -> ReadResult<u32> {
+		self.read(addr)
+	}
+
+	fn read_u64(&mut self, addr: u64) -> ReadResult<u64> {
+		self.read(addr)
+	}
+
+	fn write_u8(&mut self, addr: u64, _: u8) -> WriteResult {
+		self.kick(addr)
+	}
+
+	fn write_u16(&mut self, addr: u64, _: u16) -> WriteResult {
+		self.kick(addr)
+	}
+
+	fn write_u32(&mut self, addr: u64, _: u32) -> WriteResult {
+		self.kick(addr)
+	}
+
+	fn write_u64(&mut self, addr: u64, _: u64) -> WriteResult {
+		self.kick(addr)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{BusMatrix, BusMatrixUpdateOp, BusSlave, CoalescingSlave, Endian, MappedRegion, ProbeResult, ReadResult, Uart16550, WatchHit, Watchdog, WriteFaultAction, WriteResult};
+
+	use super::super::{Error, PROT_ALL, PROT_EXEC, PROT_READ, PROT_WRITE};
 
 	use std::sync::{Arc, Mutex};
 
@@ -530,5 +1497,446 @@ mod tests {
 		assert_eq!(vec![BusAccess::ReadU8(0), BusAccess::ReadU8(1)],
 		           slave.lock().unwrap().accesses);
 	}
-}
 
+	#[test]
+	fn write_watch_holds_first_overlapping_write() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+		matrix.set_write_watch(Some((0x1010, 4)));
+
+		assert_eq!(WriteResult::Success, matrix.write_u32(0x100C, 0x11111111));
+		assert_eq!(None, matrix.take_watch_hit());
+
+		assert_eq!(WriteResult::Success, matrix.write_u16(0x100F, 0x2222));
+		assert_eq!(WriteResult::Success, matrix.write_u8(0x1013, 0x33));
+		assert_eq!(Some(WatchHit{ addr: 0x100F, width: 2, data: 0x2222 }), matrix.take_watch_hit());
+		assert_eq!(None, matrix.take_watch_hit());
+	}
+
+	#[test]
+	fn writes_to_read_only_ranges_fault() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_READ).unwrap();
+
+		assert_eq!(WriteResult::BusError, matrix.write_u32(0x1010, 0x11111111));
+		assert_eq!(ReadResult::Success(0), matrix.read_u32(0x1010));
+	}
+
+	#[test]
+	fn write_fault_handler_can_make_range_writable() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_READ).unwrap();
+
+		let faults = Arc::new(Mutex::new(Vec::new()));
+		let handler_faults = faults.clone();
+
+		matrix.set_write_fault_handler(Some(Arc::new(move |addr, data| {
+			handler_faults.lock().unwrap().push((addr, data));
+
+			WriteFaultAction::MakeWritable
+		})));
+
+		assert_eq!(WriteResult::Success, matrix.write_u32(0x1010, 0x11111111));
+		assert_eq!(WriteResult::Success, matrix.write_u32(0x1014, 0x22222222));
+
+		assert_eq!(ReadResult::Success(0x11111111), matrix.read_u32(0x1010));
+		assert_eq!(ReadResult::Success(0x22222222), matrix.read_u32(0x1014));
+
+		// Only the first write faulted; the range was writable after that
+		assert_eq!(vec!((0x1010, 0x11111111)), *faults.lock().unwrap());
+	}
+
+	#[test]
+	fn write_fault_handler_allow_and_deny_are_one_shot() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_READ).unwrap();
+
+		matrix.set_write_fault_handler(Some(Arc::new(|addr, _| {
+			if addr == 0x1010 { WriteFaultAction::Allow } else { WriteFaultAction::Deny }
+		})));
+
+		assert_eq!(WriteResult::Success,  matrix.write_u8(0x1010, 0x11));
+		assert_eq!(WriteResult::BusError, matrix.write_u8(0x1011, 0x22));
+		assert_eq!(WriteResult::Success,  matrix.write_u8(0x1010, 0x33));
+
+		assert_eq!(ReadResult::Success(0x33), matrix.read_u8(0x1010));
+		assert_eq!(ReadResult::Success(0x00), matrix.read_u8(0x1011));
+	}
+
+	#[test]
+	fn new_regions_read_as_zero() {
+		let region = MappedRegion::alloc(0x3000).unwrap();
+
+		let mut bytes = vec!(0xFFu8; 0x3000);
+
+		region.read(0, &mut bytes).unwrap();
+
+		assert!(bytes.iter().all(|byte| *byte == 0));
+	}
+
+	#[test]
+	fn mapped_region_bounds() {
+		let mut region = MappedRegion::alloc(0x10).unwrap();
+
+		assert_eq!(0x10, region.size());
+
+		assert_eq!(Some(()), region.write(0x0, &[0xAA; 0x10]));
+
+		assert_eq!(Some(()), region.write_scalar(0xC, 0x12345678u32));
+		assert_eq!(Some(0x12345678u32), region.read_scalar(0xC));
+		assert_eq!(Some(0xAAu8), region.read_scalar(0xB));
+
+		let mut pair = [0u8; 2];
+
+		assert_eq!(Some(()), region.read(0xA, &mut pair));
+		assert_eq!([0xAA, 0xAA], pair);
+		assert_eq!(Some(()), region.read(0x0, &mut [0u8; 0x10]));
+		assert_eq!(Some(()), region.read(0x10, &mut []));
+
+		assert_eq!(None, region.read(0x0, &mut [0u8; 0x11]));
+		assert_eq!(None, region.read(0x11, &mut []));
+		assert_eq!(None, region.read(0xFFFFFFFFFFFFFFFF, &mut pair));
+		assert_eq!(None, region.write(0x8, &[0u8; 0x9]));
+		assert_eq!(None, region.read_scalar::<u32>(0xD));
+		assert_eq!(None, region.write_scalar(0xF, 0u16));
+		assert_eq!(None, region.read_scalar::<u64>(0x9));
+	}
+
+	#[test]
+	fn total_mapped_bytes_skips_mmio() {
+		let mut matrix: BusMatrix = Default::default();
+
+		assert_eq!(0, matrix.total_mapped_bytes());
+
+		matrix.add_mappable_range(0x00000000, 0x10000, PROT_ALL).unwrap();
+		matrix.add_mappable_range(0x1FC00000, 0x1000, PROT_ALL).unwrap();
+		matrix.add_bus_slave(0x1F000000, 0x200, Arc::new(Mutex::new(TestBusSlave::new()))).unwrap();
+
+		assert_eq!(0x11000, matrix.total_mapped_bytes());
+	}
+
+	#[test]
+	fn modify_u32_reads_then_writes() {
+		let mut matrix: BusMatrix = Default::default();
+
+		let slave = Arc::new(Mutex::new(TestBusSlave::new()));
+
+		matrix.add_bus_slave(0x1000, 0x200, slave.clone()).unwrap();
+
+		matrix.modify_u32(0x1010, |value| value | 0x80).unwrap();
+
+		assert_eq!(vec![BusAccess::ReadU32(0x10), BusAccess::WriteU32(0x10, 0x81)],
+		           slave.lock().unwrap().accesses);
+	}
+
+	#[test]
+	fn modify_u32_off_the_top_of_the_address_space_faults() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0xFFFFFFFFFFFFF000, 0xF00, PROT_ALL).unwrap();
+
+		match matrix.modify_u32(0xFFFFFFFFFFFFFFFE, |value| value | 0x80) {
+			Err(Error::BusFault{ addr: 0xFFFFFFFFFFFFFFFE, width: 4, is_write: false }) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn coalescing_slave_combines_byte_writes() {
+		let mut slave = CoalescingSlave::new(TestBusSlave::new(), Endian::Big);
+
+		assert_eq!(WriteResult::Success, slave.write_u8(0x10, 0x12));
+		assert_eq!(WriteResult::Success, slave.write_u8(0x11, 0x34));
+		assert_eq!(WriteResult::Success, slave.write_u8(0x12, 0x56));
+		assert_eq!(WriteResult::Success, slave.write_u8(0x13, 0x78));
+
+		assert_eq!(vec![BusAccess::WriteU32(0x10, 0x12345678)],
+		           slave.inner().accesses);
+	}
+
+	#[test]
+	fn coalescing_slave_flushes_partial_on_read() {
+		let mut slave = CoalescingSlave::new(TestBusSlave::new(), Endian::Little);
+
+		assert_eq!(WriteResult::Success, slave.write_u8(0x20, 0xAA));
+		assert_eq!(WriteResult::Success, slave.write_u8(0x21, 0xBB));
+		assert_eq!(ReadResult::Success(3), slave.read_u8(0x24));
+
+		assert_eq!(vec![BusAccess::WriteU8(0x20, 0xAA),
+		                BusAccess::WriteU8(0x21, 0xBB),
+		                BusAccess::ReadU8(0x24)],
+		           slave.inner().accesses);
+	}
+
+	struct RegisterBusSlave {
+		value: u32,
+	}
+
+	impl BusSlave for RegisterBusSlave {
+		fn read_u8(&mut self, _: u64) -> ReadResult<u8> { ReadResult::BusError }
+		fn read_u16(&mut self, _: u64) -> ReadResult<u16> { ReadResult::BusError }
+		fn read_u32(&mut self, _: u64) -> ReadResult<u32> { ReadResult::Success(self.value) }
+		fn read_u64(&mut self, _: u64) -> ReadResult<u64> { ReadResult::BusError }
+		fn write_u8(&mut self, _: u64, _: u8) -> WriteResult { WriteResult::BusError }
+		fn write_u16(&mut self, _: u64, _: u16) -> WriteResult { WriteResult::BusError }
+		fn write_u32(&mut self, _: u64, data: u32) -> WriteResult { self.value = data; WriteResult::Success }
+		fn write_u64(&mut self, _: u64, _: u64) -> WriteResult { WriteResult::BusError }
+	}
+
+	#[test]
+	fn modify_u32_is_atomic_across_matrices() {
+		const ITERATIONS: u32 = 1000;
+
+		let slave = Arc::new(Mutex::new(RegisterBusSlave{ value: 0 }));
+
+		let threads: Vec<_> = (0..2).map(|_| {
+			let slave = slave.clone();
+
+			thread::spawn(move || {
+				let mut matrix: BusMatrix = Default::default();
+
+				matrix.add_bus_slave(0x1000, 0x4, slave).unwrap();
+
+				for _ in 0..ITERATIONS {
+					matrix.modify_u32(0x1000, |value| value + 1).unwrap();
+				}
+			})
+		}).collect();
+
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		assert_eq!(2 * ITERATIONS, slave.lock().unwrap().value);
+	}
+
+	#[test]
+	fn zero_size_mappable_range_rejected() {
+		let mut matrix: BusMatrix = Default::default();
+
+		match matrix.add_mappable_range(0x1000, 0, PROT_ALL) {
+			Err(Error::InvalidRangeSize(0x1000, 0)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn overflowing_mappable_range_rejected() {
+		let mut matrix: BusMatrix = Default::default();
+
+		match matrix.add_mappable_range(0xFFFFFFFFFFFFF000, 0x2000, PROT_ALL) {
+			Err(Error::InvalidRangeSize(0xFFFFFFFFFFFFF000, 0x2000)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn find_range_overflow() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+
+		match matrix.find_range(0xFFFFFFFFFFFFFFFE, 8) {
+			Err(Error::UnableToFindRange(0xFFFFFFFFFFFFFFFE, 8)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	fn read_bytes(matrix: &mut BusMatrix, base: u64, len: u64) -> Vec<u8> {
+		(base..base + len).map(|addr| {
+			match matrix.read_u8(addr) {
+				ReadResult::Success(byte) => byte,
+				other => panic!("Unexpected read result:  {:?}", other),
+			}
+		}).collect()
+	}
+
+	#[test]
+	fn set_scalar_big_endian() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+
+		matrix.set_u16(0x1000, 0x0102, Endian::Big).unwrap();
+		matrix.set_u32(0x1010, 0x01020304, Endian::Big).unwrap();
+		matrix.set_u64(0x1020, 0x0102030405060708, Endian::Big).unwrap();
+
+		assert_eq!(vec![0x01, 0x02], read_bytes(&mut matrix, 0x1000, 2));
+		assert_eq!(vec![0x01, 0x02, 0x03, 0x04], read_bytes(&mut matrix, 0x1010, 4));
+		assert_eq!(vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08], read_bytes(&mut matrix, 0x1020, 8));
+	}
+
+	#[test]
+	fn set_scalar_little_endian() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+
+		matrix.set_u16(0x1000, 0x0102, Endian::Little).unwrap();
+		matrix.set_u32(0x1010, 0x01020304, Endian::Little).unwrap();
+		matrix.set_u64(0x1020, 0x0102030405060708, Endian::Little).unwrap();
+
+		assert_eq!(vec![0x02, 0x01], read_bytes(&mut matrix, 0x1000, 2));
+		assert_eq!(vec![0x04, 0x03, 0x02, 0x01], read_bytes(&mut matrix, 0x1010, 4));
+		assert_eq!(vec![0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01], read_bytes(&mut matrix, 0x1020, 8));
+	}
+
+	#[test]
+	fn alias_shares_backing() {
+		let mut matrix: BusMatrix = Default::default();
+
+		// Cached window at 0x1000, uncached view of the same RAM at 0x9000
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+		matrix.add_alias(0x1000, 0x9000).unwrap();
+
+		assert_eq!(WriteResult::Success, matrix.write_u32(0x1010, 0x12345678));
+		assert_eq!(ReadResult::Success(0x12345678), matrix.read_u32(0x9010));
+
+		matrix.set_u16(0x9020, 0x0102, Endian::Big).unwrap();
+		assert_eq!(vec![0x01, 0x02], read_bytes(&mut matrix, 0x1020, 2));
+
+		assert_eq!(0x1000, matrix.total_mapped_bytes());
+	}
+
+	#[test]
+	fn alias_of_unknown_range_rejected() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_ALL).unwrap();
+
+		match matrix.add_alias(0x1004, 0x9000) {
+			Err(Error::UnableToFindRange(0x1004, 0)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn read_result_into_result() {
+		assert_eq!(0x1234u16, ReadResult::Success(0x1234u16).into_result(0x10).unwrap());
+
+		match ReadResult::Unaligned::<u16>.into_result(0x11) {
+			Err(Error::UnalignedAccess(0x11)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match ReadResult::BusError::<u32>.into_result(0x20) {
+			Err(Error::BusFault{ addr: 0x20, width: 4, is_write: false }) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn write_result_into_result() {
+		WriteResult::Success.into_result(0x10, 2).unwrap();
+
+		match WriteResult::Unaligned.into_result(0x11, 2) {
+			Err(Error::UnalignedAccess(0x11)) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+
+		match WriteResult::BusError.into_result(0x20, 8) {
+			Err(Error::BusFault{ addr: 0x20, width: 8, is_write: true }) => {},
+			other => panic!("Unexpected result:  {:?}", other),
+		}
+	}
+
+	#[test]
+	fn uart_transmits_to_sink() {
+		let mut matrix: BusMatrix = Default::default();
+
+		let uart = Arc::new(Mutex::new(Uart16550::new(Vec::new(), 2)));
+
+		matrix.add_bus_slave(0x1F000000, 0x100, uart.clone()).unwrap();
+
+		assert_eq!(ReadResult::Success(0x60), matrix.read_u8(0x1F000000 + (5 << 2)));
+
+		for byte in b"hi\n".iter() {
+			assert_eq!(WriteResult::Success, matrix.write_u8(0x1F000000, *byte));
+		}
+
+		assert_eq!(WriteResult::BusError, matrix.write_u8(0x1F000001, b'x'));
+
+		assert_eq!(b"hi\n", &uart.lock().unwrap().sink()[..]);
+	}
+
+	#[test]
+	fn uart_receives_and_latches_divisor() {
+		let mut uart = Uart16550::new(Vec::new(), 0);
+
+		uart.receive(b"ok");
+
+		assert_eq!(ReadResult::Success(0x61), uart.read_u8(5));
+		assert_eq!(ReadResult::Success(b'o'), uart.read_u8(0));
+		assert_eq!(ReadResult::Success(b'k'), uart.read_u8(0));
+		assert_eq!(ReadResult::Success(0x60), uart.read_u8(5));
+
+		// With DLAB set, THR and IER are the divisor latch instead
+		assert_eq!(WriteResult::Success, uart.write_u8(3, 0x83));
+		assert_eq!(WriteResult::Success, uart.write_u8(0, 0x0C));
+		assert_eq!(WriteResult::Success, uart.write_u8(1, 0x00));
+		assert_eq!(ReadResult::Success(0x0C), uart.read_u8(0));
+		assert_eq!(WriteResult::Success, uart.write_u8(3, 0x03));
+
+		assert!(uart.sink().is_empty());
+	}
+
+	#[test]
+	fn watchdog_kicked_by_any_write_width() {
+		let mut watchdog = Watchdog::new(100);
+
+		assert!(!watchdog.take_kick());
+
+		for write in [|w: &mut Watchdog| w.write_u8(0, 1), |w: &mut Watchdog| w.write_u32(0, 1)].iter() {
+			assert_eq!(WriteResult::Success, write(&mut watchdog));
+			assert!(watchdog.take_kick());
+			assert!(!watchdog.take_kick());
+		}
+
+		assert_eq!(WriteResult::BusError, watchdog.write_u32(4, 1));
+		assert!(!watchdog.take_kick());
+		assert_eq!(ReadResult::Success(0), watchdog.read_u32(0));
+	}
+
+	#[test]
+	fn probe_checks_prot_without_accessing() {
+		let mut matrix: BusMatrix = Default::default();
+
+		let slave = Arc::new(Mutex::new(TestBusSlave::new()));
+
+		matrix.add_mappable_range(0x1000, 0x1000, PROT_READ).unwrap();
+		matrix.add_bus_slave(0x4000, 0x100, slave.clone()).unwrap();
+
+		assert_eq!(ProbeResult::Allowed,  matrix.probe(0x1FFF, PROT_READ));
+		assert_eq!(ProbeResult::Denied,   matrix.probe(0x1000, PROT_WRITE));
+		assert_eq!(ProbeResult::Denied,   matrix.probe(0x1000, PROT_EXEC));
+		assert_eq!(ProbeResult::Unmapped, matrix.probe(0x2000, PROT_READ));
+		assert_eq!(ProbeResult::Unmapped, matrix.probe(0x0FFF, PROT_READ));
+
+		assert_eq!(ProbeResult::Allowed,  matrix.probe(0x4010, PROT_WRITE));
+		assert!(slave.lock().unwrap().accesses.is_empty());
+	}
+
+	#[test]
+	fn read_span_clips_to_range_and_window() {
+		let mut matrix: BusMatrix = Default::default();
+
+		matrix.add_mappable_range(0x1800, 0x100, PROT_ALL).unwrap();
+		matrix.add_mappable_range(0x2000, 0x2000, PROT_ALL).unwrap();
+
+		matrix.set_range(&[0xAA, 0xBB], 0x18FE).unwrap();
+
+		let (base, bytes) = matrix.read_span(0x1810, 0x1000, 0x1000).unwrap();
+		assert_eq!((0x1800, 0x100), (base, bytes.len()));
+		assert_eq!(&[0xAA, 0xBB], &bytes[0xFE..]);
+
+		let (base, bytes) = matrix.read_span(0x3010, 0x3000, 0x1000).unwrap();
+		assert_eq!((0x3000, 0x1000), (base, bytes.len()));
+
+		assert!(matrix.read_span(0x1900, 0x1000, 0x1000).is_err());
+		assert!(matrix.read_span(0x2010, 0x3000, 0x1000).is_err());
+	}
+}
@@ -5,13 +5,15 @@ use super::{Cpu,
             Error};
 
 use super::iisa;
+use super::iisa::Translator;
 use super::mem;
 
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 
 pub mod translate;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Arch {
 	R2000,
 	Sys161,
@@ -26,6 +28,16 @@ impl Default for Arch {
 }
 
 pub const REG_AT: CpuReg = CpuReg::CpuSpecific(1);
+pub const REG_HI: CpuReg = CpuReg::CpuSpecific(32);
+pub const REG_LO: CpuReg = CpuReg::CpuSpecific(33);
+
+// CP0 register n is exposed as CpuSpecific(34 + n)
+pub const REG_CP0_COUNT: CpuReg   = CpuReg::CpuSpecific(34 + 9);
+pub const REG_CP0_COMPARE: CpuReg = CpuReg::CpuSpecific(34 + 11);
+pub const REG_CP0_STATUS: CpuReg  = CpuReg::CpuSpecific(34 + 12);
+pub const REG_CP0_CAUSE: CpuReg   = CpuReg::CpuSpecific(34 + 13);
+pub const REG_CP0_EPC: CpuReg     = CpuReg::CpuSpecific(34 + 14);
+pub const REG_CP0_EBASE: CpuReg   = CpuReg::CpuSpecific(66);
 
 #[allow(dead_code)]
 enum HookRange {
@@ -44,9 +56,102 @@ struct CodeHook {
 	hook: Arc<Mutex<Fn(u64, u64)>>,
 }
 
+// Lowers the single instruction word to iisa as if it were fetched from pc.
+// A branch has nowhere to find its delay slot, so it comes back as truncated.
+pub fn lower_word(arch: Arch, big_endian: bool, word: u32, pc: u64) -> Result<Vec<iisa::Instr>, Error> {
+	let translator = translate::MipsTranslator{ arch: arch, big_endian: big_endian };
+
+	let buffer: [u8; 4] = if big_endian {
+		[(word >> 24) as u8, (word >> 16) as u8, (word >> 8) as u8, (word >> 0) as u8]
+	} else {
+		[(word >> 0) as u8, (word >> 8) as u8, (word >> 16) as u8, (word >> 24) as u8]
+	};
+
+	match try!(translator.decode(pc, &buffer)) {
+		iisa::DecodeOutcome::Decoded(instrs)  => Ok(instrs),
+		iisa::DecodeOutcome::NeedMoreBytes(_) => Err(Error::TruncatedInstruction(pc)),
+	}
+}
+
+// Decodes buffer (based at base) an instruction at a time as it's iterated.
+// A branch and its delay slot come out as two items: the branch, sized as just
+// its own word but still carrying the slot so it can be executed, followed by
+// the slot's op on its own.  Iteration ends after the first error.
+pub struct InstructionStream<'a> {
+	translator: translate::MipsTranslator,
+	buffer: &'a [u8],
+	base: u64,
+	offset: usize,
+	pending: VecDeque<(u64, iisa::Instr)>,
+}
+
+impl<'a> InstructionStream<'a> {
+	pub fn new(arch: Arch, big_endian: bool, buffer: &'a [u8], base: u64) -> InstructionStream<'a> {
+		InstructionStream {
+			translator: translate::MipsTranslator{ arch: arch, big_endian: big_endian },
+			buffer:     buffer,
+			base:       base,
+			offset:     0,
+			pending:    VecDeque::new(),
+		}
+	}
+
+	fn decode_next(&mut self) -> Result<(), Error> {
+		let addr = self.base + (self.offset as u64);
+		let bytes = &self.buffer[self.offset..];
+
+		let instrs = match try!(self.translator.decode(addr, bytes)) {
+			iisa::DecodeOutcome::Decoded(instrs)  => instrs,
+			iisa::DecodeOutcome::NeedMoreBytes(_) => return Err(Error::TruncatedInstruction(addr)),
+		};
+
+		let mut cur_addr = addr;
+
+		for instr in instrs.into_iter() {
+			let size = instr.size as u64;
+
+			if let iisa::Op::Branch { ref delay_slot, .. } = instr.op {
+				let branch_size = try!(self.translator.instruction_size_at(cur_addr, &self.buffer[((cur_addr - self.base) as usize)..]));
+
+				self.pending.push_back((cur_addr, iisa::Instr{op: instr.op.clone(), pred: instr.pred, exc: instr.exc, size: branch_size}));
+				self.pending.push_back((cur_addr + (branch_size as u64), iisa::Instr{op: (**delay_slot).clone(), pred: iisa::Pred::None, exc: 0, size: instr.size - branch_size}));
+			} else {
+				self.pending.push_back((cur_addr, instr.clone()));
+			}
+
+			cur_addr += size;
+		}
+
+		if cur_addr == addr {
+			return Err(Error::Unimplemented(format!("Decode made no progress @ {:#x}", addr)));
+		}
+
+		self.offset += (cur_addr - addr) as usize;
+
+		Ok(())
+	}
+}
+
+impl<'a> Iterator for InstructionStream<'a> {
+	type Item = Result<(u64, iisa::Instr), Error>;
+
+	fn next(&mut self) -> Option<Result<(u64, iisa::Instr), Error>> {
+		if self.pending.is_empty() && self.offset < self.buffer.len() {
+			if let Err(err) = self.decode_next() {
+				self.pending.clear();
+				self.offset = self.buffer.len();
+
+				return Some(Err(err));
+			}
+		}
+
+		self.pending.pop_front().map(Ok)
+	}
+}
+
 pub fn mips_cpu_factory(opts: CpuOpt, arch: Arch, fsb: &mut mem::BusMatrix) -> Result<Box<Cpu>, Error> {
 	let translator = translate::MipsTranslator{ arch: arch, big_endian: (opts & CPU_ENDIAN_BIG) == CPU_ENDIAN_BIG };
 
-	iisa::executor::executor(translator, fsb)
+	iisa::executor::executor(translator, opts, fsb)
 }
 
@@ -13,6 +13,38 @@ pub struct MipsTranslator {
 	pub big_endian: bool,
 }
 
+const HI_REG: u16 = 64;
+const LO_REG: u16 = 65;
+
+const CP0_COUNT_REG: u16   = 32 + 9;
+const CP0_COMPARE_REG: u16 = 32 + 11;
+const CP0_STATUS_REG: u16  = 32 + 12;
+const CP0_CAUSE_REG: u16   = 32 + 13;
+const CP0_EPC_REG: u16     = 32 + 14;
+
+// Only select 0 of each CP0 register lives at 32 + n, so EBase (register 15,
+// select 1) gets a slot of its own past HI and LO
+const CP0_EBASE_REG: u16   = 66;
+
+// EBase's top two bits are hardwired to 0b10 and its low twelve bits hold the
+// CPU number rather than part of the base, so a cleared register still yields
+// the architectural 0x80000000
+const EBASE_FIXED: u32 = 0x80000000;
+const EBASE_BASE: u32  = 0x3FFFF000;
+
+const STATUS_BEV: u32          = 0x00400000;
+const STATUS_IM: u32           = 0x0000FF00;
+const STATUS_KU_IE_STACK: u32  = 0x0000003F;
+const STATUS_IEC: u32          = 0x00000001;
+const CAUSE_BD: u32            = 0x80000000;
+const CAUSE_IP: u32            = 0x0000FF00;
+const CAUSE_IP_TIMER: u32      = 0x00008000;
+const CAUSE_EXC_CODE: u32      = 0x0000007C;
+
+const EXC_CODE_INT: u8 = 0;
+const EXC_CODE_RI: u8  = 10;
+const EXC_CODE_TR: u8  = 13;
+
 #[derive(PartialEq)]
 enum BaseIsa {
 	Mips32,
@@ -28,6 +60,17 @@ fn isa_for_arch(arch: &Arch) -> BaseIsa {
 	}
 }
 
+// The conditional traps arrived with MIPS II, so on earlier cores they're
+// reserved instructions
+fn has_traps(arch: &Arch) -> bool {
+	match *arch {
+		Arch::R2000   => false,
+		Arch::Sys161  => false,
+		Arch::VR4300  => true,
+		Arch::Mips4Kc => true,
+	}
+}
+
 fn dest_gpr(gpr_num: u8) -> iisa::R {
 	match gpr_num {
 		0 => iisa::R::Discard,
@@ -42,6 +85,33 @@ fn src_gpr(gpr_num: u8) -> iisa::Src {
 	}
 }
 
+fn reg_file_index(cpu_specific: u32) -> Option<u16> {
+	match cpu_specific {
+		0 ... 31  => Some(cpu_specific as u16),
+		32        => Some(HI_REG),
+		33        => Some(LO_REG),
+		34 ... 65 => Some(32 + (cpu_specific - 34) as u16),
+		66        => Some(CP0_EBASE_REG),
+		_         => None,
+	}
+}
+
+// CpuReg values are numbers rather than memory images, so the guest's
+// endianness never changes which bits land in a register; it only matters for
+// loads and stores.  A 32-bit core keeps the low word of a 64-bit value, and
+// only accepts values that are the zero or sign extension of that word (the
+// latter being how a MIPS64 core would hold the same 32-bit result).  Reads
+// always hand back the zero extended word.
+fn narrow_to_u32(value: u64) -> Option<u32> {
+	let low = value as u32;
+
+	if (value == (low as u64)) || (value == (low as i32 as i64 as u64)) {
+		Some(low)
+	} else {
+		None
+	}
+}
+
 fn src_cpr(cpr_num: u8) -> iisa::Src {
 	iisa::Src::Reg(iisa::R::W((cpr_num + 32) as u16))
 }
@@ -65,12 +135,91 @@ fn sw_src(gpr_num: u8) -> iisa::Src {
 	}
 }
 
+// Only the CP0 registers backing the timer and interrupt state are writable
+// from guest code.  Writing Compare also acknowledges a pending timer
+// interrupt.
+fn mtc0_instrs(rt: u8, rd: u8) -> Option<Vec<iisa::Instr>> {
+	let dst = (rd as u16) + 32;
+
+	match dst {
+		CP0_COUNT_REG | CP0_COMPARE_REG | CP0_STATUS_REG | CP0_CAUSE_REG => {},
+		_ => return None,
+	}
+
+	let write = iisa::Op::Ld(iisa::DstSrc{dst: iisa::R::W(dst), src: sw_src(rt)});
+
+	if dst != CP0_COMPARE_REG {
+		return Some(vec!(iisa::Instr{op: write, pred: iisa::Pred::None, exc: 0, size: 4}));
+	}
+
+	let ack = iisa::Op::And(iisa::DstSrcSrc{dst: iisa::R::W(CP0_CAUSE_REG),
+	                                        src: [iisa::Src::Reg(iisa::R::W(CP0_CAUSE_REG)), src_u32(!CAUSE_IP_TIMER)]});
+
+	Some(vec!( iisa::Instr{op: write, pred: iisa::Pred::None, exc: 0, size: 0},
+	           iisa::Instr{op: ack,   pred: iisa::Pred::None, exc: 0, size: 4}, ))
+}
+
+fn mult_instrs(high_op: fn(iisa::DstSrcSrc) -> iisa::Op, rs: u8, rt: u8) -> Vec<iisa::Instr> {
+	let high = high_op(iisa::DstSrcSrc{dst: iisa::R::W(HI_REG), src: [src_gpr(rs), src_gpr(rt)]});
+	let low = iisa::Op::Mul(iisa::DstSrcSrc{dst: iisa::R::W(LO_REG), src: [src_gpr(rs), src_gpr(rt)]});
+
+	vec!( iisa::Instr{op: high, pred: iisa::Pred::None, exc: 0, size: 0},
+	      iisa::Instr{op: low,  pred: iisa::Pred::None, exc: 0, size: 4}, )
+}
+
+// The conditional traps, which compare rs against rt (teq and friends) or
+// against a sign extended immediate (teqi and friends).  The unsigned compares
+// (tgeu, tltu, tgeiu and tltiu) have no iisa condition, so aren't matched.
+fn trap_op(word: u32) -> Option<iisa::Op> {
+	let rs = ((word >> 21) & 0x1F) as u8;
+	let rt = ((word >> 16) & 0x1F) as u8;
+	let imm = src_i16(word as u16 as i16);
+
+	let (cond, against) = match (word >> 26, rt, word & 0x3F) {
+		(0x00, _,    0x30) => (iisa::Cond::Ge, src_gpr(rt)),
+		(0x00, _,    0x32) => (iisa::Cond::Lt, src_gpr(rt)),
+		(0x00, _,    0x34) => (iisa::Cond::Eq, src_gpr(rt)),
+		(0x00, _,    0x36) => (iisa::Cond::Ne, src_gpr(rt)),
+		(0x01, 0x08, _)    => (iisa::Cond::Ge, imm),
+		(0x01, 0x0A, _)    => (iisa::Cond::Lt, imm),
+		(0x01, 0x0C, _)    => (iisa::Cond::Eq, imm),
+		(0x01, 0x0E, _)    => (iisa::Cond::Ne, imm),
+		_                  => return None,
+	};
+
+	Some(iisa::Op::Trap(cond, iisa::SrcSrc{src: [src_gpr(rs), against]}))
+}
+
 #[allow(unused_variables)]
-fn decode_mips32(arch: &Arch, base: u64, buffer: &[u8], big_endian: bool, in_delay_slot: bool) -> Result<Vec<iisa::Instr>, Error> {
+fn decode_mips32(arch: &Arch, base: u64, buffer: &[u8], big_endian: bool, in_delay_slot: bool) -> Result<iisa::DecodeOutcome, Error> {
 	let decode_opts = opcode::mips::DecodeOptions{ decode_pseudo_ops: false, big_endian: big_endian };
-	let uarch_info = uarch_opts_for_arch(arch).unwrap();
 
-	let op = opcode::mips::decode_buf(buffer, base, uarch_info, &decode_opts).unwrap();
+	// Fetches stop at the end of a page or of mapped memory; ask for the rest
+	if buffer.len() < 4 {
+		return Ok(iisa::DecodeOutcome::NeedMoreBytes(4));
+	}
+
+	// Traps are lowered straight from the word, since all they need is the
+	// condition and two operands.  That doesn't need the opcode decoder, so
+	// they work even on cores it doesn't know yet.
+	let word = if big_endian {
+		((buffer[0] as u32) << 24) | ((buffer[1] as u32) << 16) | ((buffer[2] as u32) << 8) | (buffer[3] as u32)
+	} else {
+		((buffer[3] as u32) << 24) | ((buffer[2] as u32) << 16) | ((buffer[1] as u32) << 8) | (buffer[0] as u32)
+	};
+
+	if has_traps(arch) {
+		if let Some(trap) = trap_op(word) {
+			return Ok(iisa::DecodeOutcome::Decoded(vec!(iisa::Instr{op: trap, pred: iisa::Pred::None, exc: EXC_CODE_TR, size: 4})));
+		}
+	}
+
+	let uarch_info = try!(uarch_opts_for_arch(arch));
+
+	let op = match opcode::mips::decode_buf(buffer, base, uarch_info, &decode_opts) {
+		Ok(op)   => op,
+		Err(err) => return Err(Error::MalformedInstr(format!("mips32 decode failed @ {:#x}:  {:?}", base, err))),
+	};
 
 	let result = match op {
 		opcode::mips::Op::RtRsI16(opcode::mips::Mne::Addiu,
@@ -108,6 +257,20 @@ fn decode_mips32(arch: &Arch, base: u64, buffer: &[u8], big_endian: bool, in_del
 			iisa::Op::Or(iisa::DstSrcSrc{dst: dest_gpr(rd), src: [src_gpr(rs), src_gpr(rt)]})
 		},
 
+		opcode::mips::Op::RdRsRt(opcode::mips::Mne::Movz,
+		                         opcode::mips::Reg::Gpr(rd),
+		                         opcode::mips::Reg::Gpr(rs),
+		                         opcode::mips::Reg::Gpr(rt)) => {
+			iisa::Op::CMov(iisa::Cond::Eq, iisa::DstSrcSrc{dst: dest_gpr(rd), src: [src_gpr(rs), src_gpr(rt)]})
+		},
+
+		opcode::mips::Op::RdRsRt(opcode::mips::Mne::Movn,
+		                         opcode::mips::Reg::Gpr(rd),
+		                         opcode::mips::Reg::Gpr(rs),
+		                         opcode::mips::Reg::Gpr(rt)) => {
+			iisa::Op::CMov(iisa::Cond::Ne, iisa::DstSrcSrc{dst: dest_gpr(rd), src: [src_gpr(rs), src_gpr(rt)]})
+		},
+
 		opcode::mips::Op::RsRtTarget(opcode::mips::Mne::Beq,
 		                             opcode::mips::Reg::Gpr(rs),
 		                             opcode::mips::Reg::Gpr(rt),
@@ -118,26 +281,105 @@ fn decode_mips32(arch: &Arch, base: u64, buffer: &[u8], big_endian: bool, in_del
 
 			let delay_slot_buffer = &buffer[4..];
 
-			let other_instr = try!(decode_mips32(arch, base + 4, delay_slot_buffer, big_endian, true));
+			let mut other_instr = match try!(decode_mips32(arch, base + 4, delay_slot_buffer, big_endian, true)) {
+				iisa::DecodeOutcome::Decoded(instrs)    => instrs,
+				iisa::DecodeOutcome::NeedMoreBytes(len) => return Ok(iisa::DecodeOutcome::NeedMoreBytes(4 + len)),
+			};
+
+			if other_instr.len() != 1 {
+				return Err(Error::Unimplemented(format!("Multi-op instruction in delay slot @ {:#x}", base + 4)));
+			}
 
 			let branch_target = (((base as i64) + offset + 4) as u64) & 0x00000000FFFFFFFFu64;
 
-			let branch_instr = iisa::Op::B(iisa::Cond::Eq,
-			                               iisa::SrcSrcTarget{src: [src_gpr(rs), src_gpr(rt)],
-			                                                  target: iisa::Src::Addr(branch_target)} );
+			let branch_instr = iisa::Op::Branch{cond:       iisa::Cond::Eq,
+			                                    src:        [src_gpr(rs), src_gpr(rt)],
+			                                    target:     iisa::Src::Addr(branch_target),
+			                                    delay_slot: Box::new(other_instr.remove(0).op)};
+
+			return Ok(iisa::DecodeOutcome::Decoded(vec!(iisa::Instr{op: branch_instr, pred: iisa::Pred::None, exc: 0, size: 8})));
+		},
+
+		opcode::mips::Op::RdRt(opcode::mips::Mne::Seb,
+		                       opcode::mips::Reg::Gpr(rd),
+		                       opcode::mips::Reg::Gpr(rt)) => {
+			iisa::Op::Ext(iisa::Ext{signed: true, width: 8}, iisa::DstSrc{dst: dest_gpr(rd), src: src_gpr(rt)})
+		},
+
+		opcode::mips::Op::RdRt(opcode::mips::Mne::Seh,
+		                       opcode::mips::Reg::Gpr(rd),
+		                       opcode::mips::Reg::Gpr(rt)) => {
+			iisa::Op::Ext(iisa::Ext{signed: true, width: 16}, iisa::DstSrc{dst: dest_gpr(rd), src: src_gpr(rt)})
+		},
+
+		opcode::mips::Op::RdRs(opcode::mips::Mne::Clz,
+		                       opcode::mips::Reg::Gpr(rd),
+		                       opcode::mips::Reg::Gpr(rs)) => {
+			iisa::Op::Clz(iisa::DstSrc{dst: dest_gpr(rd), src: src_gpr(rs)})
+		},
+
+		opcode::mips::Op::RdRs(opcode::mips::Mne::Clo,
+		                       opcode::mips::Reg::Gpr(rd),
+		                       opcode::mips::Reg::Gpr(rs)) => {
+			iisa::Op::Clo(iisa::DstSrc{dst: dest_gpr(rd), src: src_gpr(rs)})
+		},
+
+		opcode::mips::Op::RsRt(opcode::mips::Mne::Mult,
+		                       opcode::mips::Reg::Gpr(rs),
+		                       opcode::mips::Reg::Gpr(rt)) => {
+			return Ok(iisa::DecodeOutcome::Decoded(mult_instrs(iisa::Op::Mulh, rs, rt)));
+		},
+
+		opcode::mips::Op::RsRt(opcode::mips::Mne::Multu,
+		                       opcode::mips::Reg::Gpr(rs),
+		                       opcode::mips::Reg::Gpr(rt)) => {
+			return Ok(iisa::DecodeOutcome::Decoded(mult_instrs(iisa::Op::Mulhu, rs, rt)));
+		},
+
+		opcode::mips::Op::Rd(opcode::mips::Mne::Mfhi, opcode::mips::Reg::Gpr(rd)) => {
+			iisa::Op::Ld(iisa::DstSrc{dst: dest_gpr(rd), src: iisa::Src::Reg(iisa::R::W(HI_REG))})
+		},
 
-			return Ok(vec!( iisa::Instr{op: other_instr[0].op,  pred: iisa::Pred::None, exc: 1, size: 0},
-			                iisa::Instr{op: branch_instr, pred: iisa::Pred::None, exc: 2, size: 8},), );
+		opcode::mips::Op::Rd(opcode::mips::Mne::Mflo, opcode::mips::Reg::Gpr(rd)) => {
+			iisa::Op::Ld(iisa::DstSrc{dst: dest_gpr(rd), src: iisa::Src::Reg(iisa::R::W(LO_REG))})
 		},
 
 		opcode::mips::Op::RtRd(opcode::mips::Mne::Mfc(opcode::mips::Cop::C0), opcode::mips::Reg::Gpr(rt), opcode::mips::Reg::Cpr(rd)) => {
 			iisa::Op::Ld(iisa::DstSrc{dst: dest_gpr(rt), src: src_cpr(rd)})
 		},
 
+		opcode::mips::Op::RtRd(opcode::mips::Mne::Mtc(opcode::mips::Cop::C0), opcode::mips::Reg::Gpr(rt), opcode::mips::Reg::Cpr(rd)) => {
+			match mtc0_instrs(rt, rd) {
+				Some(instrs) => return Ok(iisa::DecodeOutcome::Decoded(instrs)),
+				None         => return Err(Error::Unimplemented(format!("mips32 decode Unimplemented {:?}", op))),
+			}
+		},
+
 		opcode::mips::Op::RtU16(opcode::mips::Mne::Lui, opcode::mips::Reg::Gpr(rt), imm) => {
 			iisa::Op::Ld(iisa::DstSrc{dst: dest_gpr(rt), src: src_u32((imm as u32) << 16)})
 		},
 
+		opcode::mips::Op::RtOffsetBase(opcode::mips::Mne::Lb,
+		                               opcode::mips::Reg::Gpr(rt),
+		                               offset,
+		                               opcode::mips::Reg::Gpr(base)) => {
+			iisa::Op::Lbs(iisa::DstSrcSrc{dst: dest_gpr(rt), src: [src_i16(offset), src_gpr(base)]})
+		},
+
+		opcode::mips::Op::RtOffsetBase(opcode::mips::Mne::Lbu,
+		                               opcode::mips::Reg::Gpr(rt),
+		                               offset,
+		                               opcode::mips::Reg::Gpr(base)) => {
+			iisa::Op::Lb(iisa::DstSrcSrc{dst: dest_gpr(rt), src: [src_i16(offset), src_gpr(base)]})
+		},
+
+		opcode::mips::Op::RtOffsetBase(opcode::mips::Mne::Lw,
+		                               opcode::mips::Reg::Gpr(rt),
+		                               offset,
+		                               opcode::mips::Reg::Gpr(base)) => {
+			iisa::Op::Lw(iisa::DstSrcSrc{dst: dest_gpr(rt), src: [src_i16(offset), src_gpr(base)]})
+		},
+
 		opcode::mips::Op::RtOffsetBase(opcode::mips::Mne::Sw,
 		                               opcode::mips::Reg::Gpr(rt),
 		                               offset,
@@ -145,36 +387,72 @@ fn decode_mips32(arch: &Arch, base: u64, buffer: &[u8], big_endian: bool, in_del
 			iisa::Op::Sw(iisa::SrcSrcSrc{src: [sw_src(rt), src_i16(offset), src_gpr(base)]})
 		},
 
+		// Every stype is treated as a full barrier
+		opcode::mips::Op::Stype(opcode::mips::Mne::Sync, _) => {
+			iisa::Op::Fence
+		},
+
+		opcode::mips::Op::RtOffsetBase(opcode::mips::Mne::Sh,
+		                               opcode::mips::Reg::Gpr(rt),
+		                               offset,
+		                               opcode::mips::Reg::Gpr(base)) => {
+			iisa::Op::Sh(iisa::SrcSrcSrc{src: [sw_src(rt), src_i16(offset), src_gpr(base)]})
+		},
+
+		opcode::mips::Op::RtOffsetBase(opcode::mips::Mne::Sb,
+		                               opcode::mips::Reg::Gpr(rt),
+		                               offset,
+		                               opcode::mips::Reg::Gpr(base)) => {
+			iisa::Op::Sb(iisa::SrcSrcSrc{src: [sw_src(rt), src_i16(offset), src_gpr(base)]})
+		},
+
 		_ => {
 			return Err(Error::Unimplemented(format!("mips32 decode Unimplemented {:?}", op)));
 		},
 	};
 
-	Ok(vec!(iisa::Instr{op: result, pred: iisa::Pred::None, exc: 0, size: 4}))
+	Ok(iisa::DecodeOutcome::Decoded(vec!(iisa::Instr{op: result, pred: iisa::Pred::None, exc: 0, size: 4})))
 }
 
 #[allow(unused_variables)]
-fn decode_mips64(arch: &Arch, base: u64, buffer: &[u8]) -> Result<Vec<iisa::Instr>, Error> {
-	Err(Error::Unimplemented(format!("mips64 decode Unimplemented")))
+fn decode_mips64(arch: &Arch, base: u64, buffer: &[u8]) -> Result<iisa::DecodeOutcome, Error> {
+	Err(Error::UnimplementedArchitecture)
 }
 
-fn uarch_opts_for_arch(arch: &Arch) -> Option<&'static opcode::mips::UarchInfo> {
+// Arches that can be registered but have no opcode decoder behind them yet
+fn uarch_opts_for_arch(arch: &Arch) -> Result<&'static opcode::mips::UarchInfo, Error> {
 	match *arch {
-		Arch::R2000  => Some(opcode::mips::uarch_info_for_uarch(opcode::mips::Uarch::LsiR2000)),
-		Arch::Sys161 => Some(opcode::mips::uarch_info_for_uarch(opcode::mips::Uarch::HarvardMips161)),
-		_            => None,
+		Arch::R2000  => Ok(opcode::mips::uarch_info_for_uarch(opcode::mips::Uarch::LsiR2000)),
+		Arch::Sys161 => Ok(opcode::mips::uarch_info_for_uarch(opcode::mips::Uarch::HarvardMips161)),
+		_            => Err(Error::UnimplementedArchitecture),
 	}
 }
 
 impl iisa::Translator for MipsTranslator {
-	fn decode(&self, base: u64, buffer: &[u8]) -> Result<Vec<iisa::Instr>, Error> {
+	fn decode(&self, base: u64, buffer: &[u8]) -> Result<iisa::DecodeOutcome, Error> {
 		match isa_for_arch(&self.arch) {
 			BaseIsa::Mips32 => decode_mips32(&self.arch, base, buffer, self.big_endian, false),
 			BaseIsa::Mips64 => decode_mips64(&self.arch, base, buffer),
 		}
 	}
 
-	fn virtual_to_phys(&self, _: &iisa::RegisterFile, addr: u64) -> Option<u64> {
+	fn big_endian(&self) -> bool {
+		self.big_endian
+	}
+
+	fn instruction_size_at(&self, _: u64, _: &[u8]) -> Result<u8, Error> {
+		// No MIPS16/microMIPS support yet, so every instruction is a full word
+		match isa_for_arch(&self.arch) {
+			BaseIsa::Mips32 => Ok(4),
+			BaseIsa::Mips64 => Ok(4),
+		}
+	}
+
+	fn reset_vector(&self) -> u64 {
+		0xBFC00000
+	}
+
+	fn virtual_to_phys(&self, _: &iisa::RegFile, addr: u64) -> Option<u64> {
 		match addr {
 			0x80000000 ... 0x9FFFFFFF => Some(addr - 0x80000000),
 			0xA0000000 ... 0xBFFFFFFF => Some(addr - 0xA0000000),
@@ -184,19 +462,29 @@ impl iisa::Translator for MipsTranslator {
 
 	fn set_reg(&mut self, register_file: &mut iisa::RegisterFile, reg: CpuReg, value: u64) -> Result<(), Error> {
 		if BaseIsa::Mips32 == isa_for_arch(&self.arch) {
-			if value >= 0x100000000 {
-				return Err(Error::SetRegValueOutOfRange(reg, value));
-			}
+			let value32 = match narrow_to_u32(value) {
+				Some(value32) => value32,
+				None          => return Err(Error::SetRegValueOutOfRange(reg, value)),
+			};
 
 			match reg {
-				CpuReg::CpuSpecific(r) if r <= 31 => {
-					register_file.write_u32(r as u16, value as u32);
+				CpuReg::CpuSpecific(r) if reg_file_index(r).is_some() => {
+					let index = reg_file_index(r).unwrap();
+
+					register_file.write_u32(index, value32);
+
+					// As with mtc0, writing Compare acknowledges the timer interrupt
+					if index == CP0_COMPARE_REG {
+						let cause = register_file.read_u32(CP0_CAUSE_REG);
+
+						register_file.write_u32(CP0_CAUSE_REG, cause & !CAUSE_IP_TIMER);
+					}
 
 					Ok(())
 				},
 
 				CpuReg::Pc => {
-					register_file.pc = value;
+					register_file.pc = value32 as u64;
 
 					Ok(())
 				},
@@ -206,15 +494,15 @@ impl iisa::Translator for MipsTranslator {
 				},
 			}
 		} else {
-			Err(Error::SetRegUnknownReg(reg, value))
+			Err(Error::UnimplementedArchitecture)
 		}
 	}
 
 	fn get_reg(&self, register_file: &iisa::RegisterFile, reg: CpuReg) -> Result<u64, Error> {
 		if BaseIsa::Mips32 == isa_for_arch(&self.arch) {
 			match reg {
-				CpuReg::CpuSpecific(r) if r <= 31 => {
-					Ok(register_file.read_u32(r as u16) as u64)
+				CpuReg::CpuSpecific(r) if reg_file_index(r).is_some() => {
+					Ok(register_file.read_u32(reg_file_index(r).unwrap()) as u64)
 				},
 
 				CpuReg::Pc => {
@@ -226,54 +514,195 @@ impl iisa::Translator for MipsTranslator {
 				},
 			}
 		} else {
-			Err(Error::GetRegUnknownReg(reg))
+			Err(Error::UnimplementedArchitecture)
+		}
+	}
+
+	fn arch_registers(&self) -> Vec<CpuReg> {
+		// $zero is hardwired, so it's left out along with the PC
+		(1..34).map(|r| CpuReg::CpuSpecific(r)).collect()
+	}
+
+	fn reserved_instruction_code(&self) -> u8 {
+		EXC_CODE_RI
+	}
+
+	// Room for CP0 and the rest past the GPRs even once they're 64 bits wide
+	fn register_slots(&self) -> u16 {
+		CP0_EBASE_REG + 1
+	}
+
+	// The VR4300 is a 64-bit core, but its physical bus is still 32 bits
+	fn address_bits(&self) -> u8 {
+		match self.arch {
+			Arch::R2000   => 32,
+			Arch::Sys161  => 32,
+			Arch::VR4300  => 32,
+			Arch::Mips4Kc => 32,
+		}
+	}
+
+	fn link_reg(&self) -> iisa::R {
+		iisa::R::W(31)
+	}
+
+	// Count, Status, Cause and EPC also change under exception entry and the
+	// timer, so no op's defs cover every write to them
+	fn reg_storage(&self, reg: &CpuReg) -> Option<iisa::R> {
+		match *reg {
+			CpuReg::CpuSpecific(r) if isa_for_arch(&self.arch) == BaseIsa::Mips32 => match reg_file_index(r) {
+				Some(CP0_COUNT_REG) | Some(CP0_STATUS_REG) | Some(CP0_CAUSE_REG) | Some(CP0_EPC_REG) => None,
+
+				index => index.map(iisa::R::W),
+			},
+
+			_ => None,
+		}
+	}
+
+	fn op_cost(&self, op: &iisa::Op) -> u32 {
+		// Rough R2000 latencies.  The high half of a product or the remainder of a
+		// divide falls out of the same operation as its partner op, so only that
+		// partner carries the cost.
+		match *op {
+			iisa::Op::Mul(_)   => 12,
+			iisa::Op::Mulh(_)  => 0,
+			iisa::Op::Mulhu(_) => 0,
+			iisa::Op::Div(_)   => 35,
+			iisa::Op::Divu(_)  => 35,
+			iisa::Op::Mod(_)   => 0,
+			iisa::Op::Modu(_)  => 0,
+
+			iisa::Op::Lb(_)  => 2,
+			iisa::Op::Lbs(_) => 2,
+			iisa::Op::Lh(_)  => 2,
+			iisa::Op::Lw(_)  => 2,
+
+			iisa::Op::Branch { ref delay_slot, .. } => 1 + self.op_cost(delay_slot),
+
+			_ => 1,
+		}
+	}
+
+	fn advance_time(&self, registers: &mut iisa::RegisterFile, cycles: u32) {
+		// Count ticks once per cycle of the cost model and wraps silently.  An
+		// instruction can cost several cycles, so the timer fires if Count
+		// passed through Compare at any point during it.
+		let count = registers.read_u32(CP0_COUNT_REG);
+		let until_compare = registers.read_u32(CP0_COMPARE_REG).wrapping_sub(count);
+
+		registers.write_u32(CP0_COUNT_REG, count.wrapping_add(cycles));
+
+		if until_compare != 0 && until_compare <= cycles {
+			let cause = registers.read_u32(CP0_CAUSE_REG);
+
+			registers.write_u32(CP0_CAUSE_REG, cause | CAUSE_IP_TIMER);
 		}
 	}
+
+	fn take_interrupt(&self, registers: &mut iisa::RegisterFile) -> Result<bool, Error> {
+		let status = registers.read_u32(CP0_STATUS_REG);
+		let cause = registers.read_u32(CP0_CAUSE_REG);
+
+		if (status & STATUS_IEC) == 0 || (status & STATUS_IM & cause & CAUSE_IP) == 0 {
+			return Ok(false);
+		}
+
+		try!(self.raise_exception(registers, EXC_CODE_INT));
+
+		Ok(true)
+	}
+
+	fn raise_exception(&self, registers: &mut iisa::RegisterFile, code: u8) -> Result<(), Error> {
+		let status = registers.read_u32(CP0_STATUS_REG);
+		let cause = registers.read_u32(CP0_CAUSE_REG);
+
+		// Push the KU/IE stack, dropping into kernel mode with interrupts off
+		let status = (status & !STATUS_KU_IE_STACK) | ((status << 2) & STATUS_KU_IE_STACK);
+		let cause = (cause & !(CAUSE_BD | CAUSE_EXC_CODE)) | (((code as u32) << 2) & CAUSE_EXC_CODE);
+
+		registers.write_u32(CP0_STATUS_REG, status);
+		registers.write_u32(CP0_CAUSE_REG, cause);
+		registers.write_u32(CP0_EPC_REG, registers.pc as u32);
+
+		registers.pc = if (status & STATUS_BEV) != 0 {
+			0xBFC00180
+		} else {
+			(EBASE_FIXED | (registers.read_u32(CP0_EBASE_REG) & EBASE_BASE)) as u64 + 0x80
+		};
+
+		Ok(())
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use iisa::{Cond,
+	           DecodeOutcome,
 	           DstSrc,
 	           DstSrcSrc,
+	           Ext,
 	           Instr,
 	           Op,
 	           Pred,
 	           R,
+	           RegFile,
+	           RegisterFile,
 	           Src,
+	           SrcSrc,
 	           SrcSrcSrc,
-	           SrcSrcTarget,
 	           Translator};
+	use iisa;
+	use mem::BusMatrix;
 
-	use super::super::Arch;
-	use super::MipsTranslator;
+	use std::cell::RefCell;
+
+	use super::super::{CpuReg, Error};
+	use super::super::{lower_word, Arch, InstructionStream};
+	use super::{MipsTranslator, CAUSE_IP_TIMER, CP0_CAUSE_REG, CP0_COMPARE_REG, CP0_STATUS_REG};
+
+	// Decodes instr_word as both a big endian and a little endian word, and
+	// panics naming the first field the two decodes disagree on.  Returns the
+	// (agreed upon) decode.
+	fn assert_endian_agnostic(instr_word: u32) -> Vec<Instr> {
+		assert_endian_agnostic_on(Arch::R2000, instr_word)
+	}
+
+	fn assert_endian_agnostic_on(arch: Arch, instr_word: u32) -> Vec<Instr> {
+		let (iisa_be, iisa_le) = match (lower_word(arch.clone(), true, instr_word, 0), lower_word(arch, false, instr_word, 0)) {
+			(Ok(iisa_be), Ok(iisa_le)) => (iisa_be, iisa_le),
+			(be, le) => panic!("{:#010x} doesn't decode in both endiannesses:\n  BE: {:?}\n  LE: {:?}", instr_word, be, le),
+		};
+
+		if iisa_be.len() != iisa_le.len() {
+			panic!("{:#010x} decodes to {} ops big endian but {} little endian:\n  BE: {:?}\n  LE: {:?}",
+			       instr_word, iisa_be.len(), iisa_le.len(), iisa_be, iisa_le);
+		}
+
+		for (i, (be, le)) in iisa_be.iter().zip(iisa_le.iter()).enumerate() {
+			if be.op != le.op {
+				panic!("{:#010x} op {} diverges in `op`:\n  BE: {:?}\n  LE: {:?}", instr_word, i, be.op, le.op);
+			}
+			if be.pred != le.pred {
+				panic!("{:#010x} op {} diverges in `pred`:\n  BE: {:?}\n  LE: {:?}", instr_word, i, be.pred, le.pred);
+			}
+			if be.exc != le.exc {
+				panic!("{:#010x} op {} diverges in `exc`:  BE {} LE {}", instr_word, i, be.exc, le.exc);
+			}
+			if be.size != le.size {
+				panic!("{:#010x} op {} diverges in `size`:  BE {} LE {}", instr_word, i, be.size, le.size);
+			}
+		}
+
+		iisa_be
+	}
 
 	macro_rules! test_simple_r2000 {
 		($func_name:ident, $instr:expr, $translated:expr) => (
 			#[test]
 			#[allow(non_snake_case)]
 			fn $func_name() {
-				let translator_be = MipsTranslator{ arch: Arch::R2000, big_endian: true };
-				let translator_le = MipsTranslator{ arch: Arch::R2000, big_endian: false };
-
-				let buffer_be: [u8; 4] = [
-					($instr >> 24) as u8,
-					($instr >> 16) as u8,
-					($instr >>  8) as u8,
-					($instr >>  0) as u8,
-				];
-
-				let buffer_le: [u8; 4] = [
-					($instr >>  0) as u8,
-					($instr >>  8) as u8,
-					($instr >> 16) as u8,
-					($instr >> 24) as u8,
-				];
-
-				let iisa_be = translator_be.decode(0, &buffer_be).unwrap();
-				let iisa_le = translator_le.decode(0, &buffer_le).unwrap();
-				assert_eq!(iisa_be, vec!(Instr{op: $translated, pred: Pred::None, exc: 0, size: 4}));
-				assert_eq!(iisa_le, vec!(Instr{op: $translated, pred: Pred::None, exc: 0, size: 4}));
+				assert_eq!(assert_endian_agnostic($instr), vec!(Instr{op: $translated, pred: Pred::None, exc: 0, size: 4}));
 			}
 		);
 	}
@@ -284,18 +713,27 @@ mod tests {
 			#[allow(non_snake_case)]
 			fn $func_name() {
 				let translator_be = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+				let translator_le = MipsTranslator{ arch: Arch::R2000, big_endian: false };
 
 				let mut buffer_be = Vec::new();
+				let mut buffer_le = Vec::new();
 
 				for instr in $instrs.iter() {
 					buffer_be.push( (instr >> 24) as u8 );
 					buffer_be.push( (instr >> 16) as u8 );
 					buffer_be.push( (instr >>  8) as u8 );
 					buffer_be.push( (instr >>  0) as u8 );
+
+					buffer_le.push( (instr >>  0) as u8 );
+					buffer_le.push( (instr >>  8) as u8 );
+					buffer_le.push( (instr >> 16) as u8 );
+					buffer_le.push( (instr >> 24) as u8 );
 				}
 
 				let iisa_be = translator_be.decode($pc, &buffer_be).unwrap();
-				assert_eq!(iisa_be, $translated);
+				let iisa_le = translator_le.decode($pc, &buffer_le).unwrap();
+				assert_eq!(iisa_be, DecodeOutcome::Decoded($translated.to_vec()));
+				assert_eq!(iisa_le, DecodeOutcome::Decoded($translated.to_vec()));
 			}
 		);
 	}
@@ -306,11 +744,41 @@ mod tests {
 	test_simple_r2000!( r2000_addu___s2___s1_v1,       0x02239021u32, Op::Add(DstSrcSrc{dst: R::W(18), src: [Src::Reg(R::W(17)), Src::Reg(R::W(3))]}) );
 	test_simple_r2000!( r2000_addu___s1___a1_zero,     0x00a08821u32, Op::Add(DstSrcSrc{dst: R::W(17), src: [Src::Reg(R::W( 5)), Src::ImmU32(0)   ]}) ); 
 
+	test_simple_r2000!( r2000_clo____v0___a0,          0x70821021u32, Op::Clo(DstSrc{dst: R::W(2), src: Src::Reg(R::W(4))}) );
+	test_simple_r2000!( r2000_clz____t0___t1,          0x71284020u32, Op::Clz(DstSrc{dst: R::W(8), src: Src::Reg(R::W(9))}) );
+
+	test_simple_r2000!( r2000_lb_____t0___16_____a0,   0x80880010u32, Op::Lbs(DstSrcSrc{dst: R::W(8), src: [Src::ImmI16( 16), Src::Reg(R::W( 4))]}) );
+	test_simple_r2000!( r2000_lbu____v0___neg4___sp,   0x93a2fffcu32, Op::Lb( DstSrcSrc{dst: R::W(2), src: [Src::ImmI16( -4), Src::Reg(R::W(29))]}) );
+
 	test_simple_r2000!( r2000_lui____zero_0xabcd,      0x3c00abcdu32, Op::Ld(DstSrc{dst: R::Discard, src: Src::ImmU32(0xABCD0000)}) );
 	test_simple_r2000!( r2000_lui____gp___0x8072,      0x3c1c8072u32, Op::Ld(DstSrc{dst: R::W(28),   src: Src::ImmU32(0x80720000)}) );
 
+	test_simple_r2000!( r2000_lw_____t0___16_____a0,   0x8c880010u32, Op::Lw(DstSrcSrc{dst: R::W(8), src: [Src::ImmI16(16), Src::Reg(R::W(4))]}) );
+
+	test_simple_r2000!( r2000_mfhi___v0,               0x00001010u32, Op::Ld(DstSrc{dst: R::W(2), src: Src::Reg(R::W(64))}) );
+	test_simple_r2000!( r2000_mflo___t0,               0x00004012u32, Op::Ld(DstSrc{dst: R::W(8), src: Src::Reg(R::W(65))}) );
+
+	test_simple_r2000!( r2000_mfc0___t0___c0count,     0x40084800u32, Op::Ld(DstSrc{dst: R::W(8), src: Src::Reg(R::W(32 + 9))}) );
 	test_simple_r2000!( r2000_mfc0___t0___c0status,    0x40086000u32, Op::Ld(DstSrc{dst: R::W(8), src: Src::Reg(R::W(32 + 12))}) );
 
+	test_simple_r2000!( r2000_seb____t0___t1,          0x7c094420u32, Op::Ext(Ext{signed: true, width:  8}, DstSrc{dst: R::W(8), src: Src::Reg(R::W(9))}) );
+	test_simple_r2000!( r2000_seh____v0___a0,          0x7c041620u32, Op::Ext(Ext{signed: true, width: 16}, DstSrc{dst: R::W(2), src: Src::Reg(R::W(4))}) );
+
+	test_simple_r2000!( r2000_movz___t0___t1_t2,       0x012a400au32, Op::CMov(Cond::Eq, DstSrcSrc{dst: R::W(8), src: [Src::Reg(R::W(9)), Src::Reg(R::W(10))]}) );
+	test_simple_r2000!( r2000_movn___v0___a0_a1,       0x0085100bu32, Op::CMov(Cond::Ne, DstSrcSrc{dst: R::W(2), src: [Src::Reg(R::W(4)), Src::Reg(R::W( 5))]}) );
+
+	test_vec_r2000!( r2000_mult___a0___a1,
+	                 0,
+	                 [0x00850018u32],
+	                 [Instr{op: Op::Mulh(DstSrcSrc{dst: R::W(64), src: [Src::Reg(R::W(4)), Src::Reg(R::W(5))]}), pred: Pred::None, exc: 0, size: 0},
+	                  Instr{op: Op::Mul( DstSrcSrc{dst: R::W(65), src: [Src::Reg(R::W(4)), Src::Reg(R::W(5))]}), pred: Pred::None, exc: 0, size: 4},] );
+
+	test_vec_r2000!( r2000_multu__a0___a1,
+	                 0,
+	                 [0x00850019u32],
+	                 [Instr{op: Op::Mulhu(DstSrcSrc{dst: R::W(64), src: [Src::Reg(R::W(4)), Src::Reg(R::W(5))]}), pred: Pred::None, exc: 0, size: 0},
+	                  Instr{op: Op::Mul(  DstSrcSrc{dst: R::W(65), src: [Src::Reg(R::W(4)), Src::Reg(R::W(5))]}), pred: Pred::None, exc: 0, size: 4},] );
+
 	test_simple_r2000!( r2000_or_____t0___t0_at,       0x01014025u32, Op::Or(DstSrcSrc{dst: R::W(8), src: [Src::Reg(R::W(8)), Src::Reg(R::W(1))]}) );
 
 	test_simple_r2000!( r2000_ori____at___at_0x4354,   0x34214354u32, Op::Or(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmU16(0x4354)]}) );
@@ -320,12 +788,275 @@ mod tests {
 	test_simple_r2000!( r2000_sw_____zero_20_____sp,   0xafa00014u32, Op::Sw(SrcSrcSrc{src: [Src::ImmU32(0),     Src::ImmI16(  20), Src::Reg(R::W(29))]}) );
 	test_simple_r2000!( r2000_sw_____s3___neg336_gp,   0xaf93feb0u32, Op::Sw(SrcSrcSrc{src: [Src::Reg(R::W(19)), Src::ImmI16(-336), Src::Reg(R::W(28))]}) );
 
+	test_simple_r2000!( r2000_sync,                    0x0000000fu32, Op::Fence );
+	test_simple_r2000!( r2000_sync___0x10,             0x0000040fu32, Op::Fence );
+
+	test_simple_r2000!( r2000_sh_____t0___6______a0,   0xa4880006u32, Op::Sh(SrcSrcSrc{src: [Src::Reg(R::W(8)),  Src::ImmI16(   6), Src::Reg(R::W(4))]}) );
+	test_simple_r2000!( r2000_sb_____v0___neg1___sp,   0xa3a2ffffu32, Op::Sb(SrcSrcSrc{src: [Src::Reg(R::W(2)),  Src::ImmI16(  -1), Src::Reg(R::W(29))]}) );
+
 	test_simple_r2000!( r2000_xori___t0___t0_0x1f,     0x3908001fu32, Op::Xor(DstSrcSrc{dst: R::W(8), src: [Src::Reg(R::W(8)), Src::ImmU16(0x1F)]}) );
 
+	#[test]
+	fn r2000_instruction_size() {
+		let translator = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+
+		assert_eq!(4, translator.instruction_size_at(0, &[0x34, 0x21, 0x43, 0x54]).unwrap());
+	}
+
+	#[test]
+	fn r2000_set_reg_narrows_64_bit_values() {
+		for big_endian in [true, false].iter() {
+			let mut translator = MipsTranslator{ arch: Arch::R2000, big_endian: *big_endian };
+			let mut regs = RegisterFile::new();
+
+			translator.set_reg(&mut regs, CpuReg::CpuSpecific(1), 0xFFFFFFFF87654321).unwrap();
+			translator.set_reg(&mut regs, CpuReg::CpuSpecific(2), 0x0000000087654321).unwrap();
+			translator.set_reg(&mut regs, CpuReg::Pc, 0xFFFFFFFFBFC00000).unwrap();
+
+			assert_eq!(0x87654321, regs.read_u32(1));
+			assert_eq!(0x87654321, regs.read_u32(2));
+			assert_eq!(0xBFC00000, regs.pc);
+
+			assert_eq!(0x87654321, translator.get_reg(&regs, CpuReg::CpuSpecific(1)).unwrap());
+
+			match translator.set_reg(&mut regs, CpuReg::CpuSpecific(3), 0x0000000187654321) {
+				Err(Error::SetRegValueOutOfRange(CpuReg::CpuSpecific(3), 0x0000000187654321)) => {},
+				result => panic!("Unexpected result:  {:?}", result),
+			}
+
+			match translator.set_reg(&mut regs, CpuReg::CpuSpecific(3), 0xFFFFFFFF07654321) {
+				Err(Error::SetRegValueOutOfRange(CpuReg::CpuSpecific(3), 0xFFFFFFFF07654321)) => {},
+				result => panic!("Unexpected result:  {:?}", result),
+			}
+
+			assert_eq!(0, regs.read_u32(3));
+		}
+	}
+
+	#[test]
+	fn r2000_count_advances_and_wraps() {
+		let translator = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+		let mut regs = RegisterFile::new();
+
+		translator.advance_time(&mut regs, 12);
+		assert_eq!(12, regs.read_u32(super::CP0_COUNT_REG));
+
+		regs.write_u32(super::CP0_COUNT_REG, 0xFFFFFFFE);
+		translator.advance_time(&mut regs, 3);
+		assert_eq!(1, regs.read_u32(super::CP0_COUNT_REG));
+	}
+
+	#[test]
+	fn r2000_raise_exception() {
+		let translator = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(super::CP0_STATUS_REG, 0x00000005);
+		regs.write_u32(super::CP0_CAUSE_REG, 0x80000030);
+		regs.pc = 0xBFC00010;
+
+		translator.raise_exception(&mut regs, 8).unwrap();
+
+		assert_eq!(0x00000014, regs.read_u32(super::CP0_STATUS_REG));
+		assert_eq!(0x00000020, regs.read_u32(super::CP0_CAUSE_REG));
+		assert_eq!(0xBFC00010, regs.read_u32(super::CP0_EPC_REG));
+		assert_eq!(0x80000080, regs.pc);
+
+		regs.write_u32(super::CP0_STATUS_REG, 0x00400000);
+
+		translator.raise_exception(&mut regs, 8).unwrap();
+
+		assert_eq!(0x80000080, regs.read_u32(super::CP0_EPC_REG));
+		assert_eq!(0xBFC00180, regs.pc);
+	}
+
+	#[test]
+	fn r2000_exception_vectors_relative_to_ebase() {
+		let translator = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+		let mut regs = RegisterFile::new();
+
+		regs.write_u32(super::CP0_EBASE_REG, 0x00042003);
+		regs.pc = 0x80001000;
+
+		translator.raise_exception(&mut regs, 8).unwrap();
+
+		assert_eq!(0x80042080, regs.pc);
+
+		// The bootstrap vector ignores EBase
+		regs.write_u32(super::CP0_STATUS_REG, 0x00400000);
+
+		translator.raise_exception(&mut regs, 8).unwrap();
+
+		assert_eq!(0xBFC00180, regs.pc);
+	}
+
 	test_vec_r2000!( r2000_beq_a2_at_80710038_move_s3_a3,
 	                 0x80710028,
 	                 [0x10c10003u32, 0x00e09821u32],
-	                 [Instr{op: Op::Add(DstSrcSrc{dst: R::W(19), src: [Src::Reg(R::W(7)), Src::ImmU32(0)]}),                               pred: Pred::None, exc: 1, size: 0},
-	                  Instr{op: Op::B(Cond::Eq, SrcSrcTarget{src: [Src::Reg(R::W(6)), Src::Reg(R::W(1))], target: Src::Addr(0x80710038)}), pred: Pred::None, exc: 2, size: 8},] );
-}
+	                 [Instr{op: Op::Branch{cond:       Cond::Eq,
+	                                       src:        [Src::Reg(R::W(6)), Src::Reg(R::W(1))],
+	                                       target:     Src::Addr(0x80710038),
+	                                       delay_slot: Box::new(Op::Add(DstSrcSrc{dst: R::W(19), src: [Src::Reg(R::W(7)), Src::ImmU32(0)]}))},
+	                        pred: Pred::None, exc: 0, size: 8},] );
+
+	#[test]
+	fn r2000_short_buffers_ask_for_more_bytes() {
+		let translator = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+
+		assert_eq!(DecodeOutcome::NeedMoreBytes(4), translator.decode(0x80000ffe, &[0x27, 0x9c]).unwrap());
+
+		// A branch at the end of the buffer needs its delay slot too
+		assert_eq!(DecodeOutcome::NeedMoreBytes(8), translator.decode(0x80000ffc, &[0x10, 0xc1, 0x00, 0x03]).unwrap());
+	}
+
+	#[test]
+	fn instruction_stream_yields_each_word() {
+		const ROM: [u8; 16] = [
+			0x34, 0x21, 0x34, 0x56, // ori $at, $at, 0x3456
+			0x10, 0x00, 0x00, 0x02, // beq $zero, $zero, +12
+			0x24, 0x08, 0x00, 0x55, // addiu $t0, $zero, 0x55
+			0x34, 0x21, 0x00, 0x01, // ori $at, $at, 0x1
+		];
+
+		let addiu = Op::Add(DstSrcSrc{dst: R::W(8), src: [Src::ImmU32(0), Src::ImmI16(0x55)]});
+
+		let items: Vec<(u64, Op, u8)> = InstructionStream::new(Arch::R2000, true, &ROM, 0xBFC00000).map(|item| {
+			let (addr, instr) = item.unwrap();
+
+			(addr, instr.op, instr.size)
+		}).collect();
+
+		assert_eq!(vec!((0xBFC00000, Op::Or(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmU16(0x3456)]}), 4),
+		                (0xBFC00004, Op::Branch{cond:       Cond::Eq,
+		                                        src:        [Src::ImmU32(0), Src::ImmU32(0)],
+		                                        target:     Src::Addr(0xBFC00010),
+		                                        delay_slot: Box::new(addiu.clone())}, 4),
+		                (0xBFC00008, addiu, 4),
+		                (0xBFC0000C, Op::Or(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmU16(0x0001)]}), 4)),
+		           items);
+	}
+
+	#[test]
+	fn instruction_stream_stops_after_an_error() {
+		let mut stream = InstructionStream::new(Arch::R2000, true, &[0x34, 0x21, 0x34, 0x56, 0x10, 0x00], 0);
+
+		assert!(stream.next().unwrap().is_ok());
+
+		match stream.next() {
+			Some(Err(Error::TruncatedInstruction(4))) => {},
+			other => panic!("Unexpected item:  {:?}", other),
+		}
+
+		assert!(stream.next().is_none());
+	}
+
+	// Records every register read and write (as (is_write, reg, value)) on the
+	// way through to a real register file
+	struct LoggingRegs {
+		inner: RegisterFile,
+		log: RefCell<Vec<(bool, R, u64)>>,
+	}
+
+	impl RegFile for LoggingRegs {
+		fn read_reg(&self, reg: R) -> u64 {
+			let value = self.inner.read_reg(reg);
+
+			self.log.borrow_mut().push((false, reg, value));
+
+			value
+		}
+
+		fn write_reg(&mut self, reg: R, value: u64) {
+			self.log.borrow_mut().push((true, reg, value));
 
+			self.inner.write_reg(reg, value)
+		}
+
+		fn pc(&self) -> u64 {
+			self.inner.pc
+		}
+
+		fn set_pc(&mut self, pc: u64) {
+			self.inner.pc = pc;
+		}
+	}
+
+	#[test]
+	fn interpreter_runs_example_over_logging_registers() {
+		let translator = MipsTranslator{ arch: Arch::R2000, big_endian: true };
+		let mut bus: BusMatrix = Default::default();
+
+		let mut regs = LoggingRegs {
+			inner: RegisterFile::new(),
+			log:   RefCell::new(Vec::new()),
+		};
+
+		regs.inner.write_u32(1, 0x4321);
+		regs.inner.pc = 0xBFC00000;
+
+		// The mips example's one instruction, ori $at, $at, 0x3456
+		let instrs = lower_word(Arch::R2000, true, 0x34213456, 0xBFC00000).unwrap();
+
+		iisa::interpret_op_list(&instrs, &mut regs, &mut bus, &mut iisa::StoreBuffer::default(), &translator).unwrap();
+
+		assert_eq!(vec!((false, R::W(1), 0x4321), (true, R::W(1), 0x7777)), *regs.log.borrow());
+		assert_eq!(0xBFC00004, regs.inner.pc);
+	}
+
+	#[test]
+	fn trap_family_decodes_with_trap_exc() {
+		let trap = |cond, against| {
+			vec!(Instr{op: Op::Trap(cond, SrcSrc{src: [Src::Reg(R::W(4)), against]}), pred: Pred::None, exc: 13, size: 4})
+		};
+
+		let lower = |word| assert_endian_agnostic_on(Arch::Mips4Kc, word);
+
+		assert_eq!(trap(Cond::Eq, Src::Reg(R::W(5))), lower(0x00850034)); // teq $a0, $a1
+		assert_eq!(trap(Cond::Ne, Src::Reg(R::W(5))), lower(0x00850036)); // tne $a0, $a1
+		assert_eq!(trap(Cond::Ge, Src::Reg(R::W(5))), lower(0x00850030)); // tge $a0, $a1
+		assert_eq!(trap(Cond::Lt, Src::Reg(R::W(5))), lower(0x00850032)); // tlt $a0, $a1
+		assert_eq!(trap(Cond::Eq, Src::ImmI16(-2)),   lower(0x048CFFFE)); // teqi $a0, -2
+		assert_eq!(trap(Cond::Lt, Src::ImmI16(5)),    lower(0x048A0005)); // tlti $a0, 5
+
+		// No unsigned condition to lower tgeu to
+		assert!(lower_word(Arch::Mips4Kc, true, 0x00850031, 0).is_err());
+
+		// MIPS I has no traps at all
+		assert!(lower_word(Arch::R2000, true, 0x00850034, 0).is_err());
+		assert!(lower_word(Arch::Sys161, true, 0x048CFFFE, 0).is_err());
+	}
+
+	#[test]
+	fn mtc0_compare_acknowledges_the_timer() {
+		let write = |cpr| Op::Ld(DstSrc{dst: R::W(cpr), src: Src::Reg(R::W(8))});
+		let ack = Op::And(DstSrcSrc{dst: R::W(CP0_CAUSE_REG), src: [Src::Reg(R::W(CP0_CAUSE_REG)), Src::ImmU32(!CAUSE_IP_TIMER)]});
+
+		// mtc0 $t0, $12 (Status)
+		assert_eq!(vec!(Instr{op: write(CP0_STATUS_REG), pred: Pred::None, exc: 0, size: 4}),
+		           assert_endian_agnostic(0x40886000));
+
+		// mtc0 $t0, $11 (Compare)
+		assert_eq!(vec!(Instr{op: write(CP0_COMPARE_REG), pred: Pred::None, exc: 0, size: 0},
+		                Instr{op: ack,                    pred: Pred::None, exc: 0, size: 4}),
+		           assert_endian_agnostic(0x40885800));
+
+		// mtc0 $t0, $14 (EPC) isn't writable from guest code
+		assert!(lower_word(Arch::R2000, true, 0x40887000, 0).is_err());
+	}
+
+	#[test]
+	fn lower_word_decodes_a_lone_word() {
+		let ori = Op::Or(DstSrcSrc{dst: R::W(1), src: [Src::Reg(R::W(1)), Src::ImmU16(0x4354)]});
+
+		for big_endian in [true, false].iter() {
+			let instrs = lower_word(Arch::R2000, *big_endian, 0x34214354, 0xBFC00000).unwrap();
+
+			assert_eq!(vec!(Instr{op: ori.clone(), pred: Pred::None, exc: 0, size: 4}), instrs);
+		}
+
+		match lower_word(Arch::R2000, true, 0x10c10003, 0xBFC00000) {
+			Err(Error::TruncatedInstruction(0xBFC00000)) => {},
+			other => panic!("Unexpected lowering of a lone branch:  {:?}", other),
+		}
+	}
+}